@@ -0,0 +1,293 @@
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use data_encoding::HEXLOWER;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::Dfs;
+use crate::compression::{self, Compression};
+use crate::global_store::GlobalStore;
+use crate::root::blocks::{self, BLOCK_SIZE};
+use crate::root::merkle::Hash;
+
+/// The folder in the [global db](crate::config::Config::global_db) in which the block store is
+/// kept. The block store holds the contents dfs keeps apart from the files in the roots, like
+/// [kept versions](crate::root::history::FileVersion) of files. It is shared by all roots: every
+/// block is stored in a file named after its hash, so a block which is used in several places,
+/// in one root or in several, is stored once. The [`GlobalStore`] counts how often every block is
+/// used, and blocks which are no longer used are removed by [`Dfs::gc_blocks`].
+///
+/// Blocks are compressed as [configured](Dfs::set_compression) for the root which stores them.
+/// Every block file starts with a header: the [`Compression`] of the block, and its length.
+pub const BLOCKS_DIR: &str = "blocks";
+
+const HEADER_LEN: usize = 9;
+
+#[derive(Debug, Error)]
+pub enum BlockStoreError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+/// How much space the block store takes, see [`Dfs::block_store_stats`].
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct BlockStoreStats {
+    pub blocks: usize,
+
+    /// the total length of the blocks
+    pub size: u64,
+
+    /// how much space the blocks take on disk, after compression
+    pub stored: u64,
+}
+
+impl BlockStoreStats {
+    /// How many bytes compression saves.
+    pub fn saved(&self) -> u64 {
+        self.size.saturating_sub(self.stored)
+    }
+}
+
+/// Read the header of a block file: how the block is compressed, and its length.
+fn read_header(file: &mut impl Read) -> io::Result<Option<(Compression, u64)>> {
+    let mut header = [0; HEADER_LEN];
+    file.read_exact(&mut header)?;
+
+    let mut len = [0; 8];
+    len.copy_from_slice(&header[1..]);
+    Ok(Compression::from_tag(header[0]).map(|c| (c, u64::from_le_bytes(len))))
+}
+
+impl<GS: GlobalStore> Dfs<GS> {
+    fn block_path(&self, hash: &Hash) -> PathBuf {
+        self.cfg().global_db.join(BLOCKS_DIR).join(HEXLOWER.encode(hash))
+    }
+
+    /// Store a block, or count another use of it when it's stored already.
+    pub(crate) fn store_block(&self, hash: &Hash, block: &[u8], compression: Compression) -> Result<(), BlockStoreError<GS::Error>> {
+        // gc_blocks can't remove the block between counting it and checking it's there
+        let _lock = self.block_store_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let path = self.block_path(hash);
+        if path.exists() {
+            self.connection.add_block_ref(hash)?;
+            return Ok(())
+        }
+
+        let dir = self.cfg().global_db.join(BLOCKS_DIR);
+        fs::create_dir_all(&dir).map_err(|e| BlockStoreError::Io(dir.clone(), e))?;
+        // written under another name first, so a block is never seen half written
+        let (compression, compressed) = match compression::compress(compression, block) {
+            Some((compression, compressed)) => (compression, Some(compressed)),
+            None => (Compression::None, None),
+        };
+        let mut contents = Vec::with_capacity(HEADER_LEN + block.len());
+        contents.push(compression.tag());
+        contents.extend_from_slice(&(block.len() as u64).to_le_bytes());
+        contents.extend_from_slice(compressed.as_deref().unwrap_or(block));
+
+        let staging = dir.join(format!("{}.tmp", Uuid::new_v4()));
+        fs::write(&staging, contents).map_err(|e| BlockStoreError::Io(staging.clone(), e))?;
+        fs::rename(&staging, &path).map_err(|e| BlockStoreError::Io(path, e))?;
+
+        // only counted once it's there, a block which failed to store isn't kept around by it
+        self.connection.add_block_ref(hash)?;
+        Ok(())
+    }
+
+    /// Stop counting one use of a block. It stays in the store until it's [collected](Dfs::gc_blocks).
+    pub(crate) fn release_block(&self, hash: &Hash) -> Result<(), BlockStoreError<GS::Error>> {
+        self.connection.remove_block_ref(hash)?;
+        Ok(())
+    }
+
+    /// Read a block from the store. Returns None when it isn't stored, or the stored block is
+    /// damaged.
+    pub(crate) fn stored_block(&self, hash: &Hash) -> Result<Option<Vec<u8>>, BlockStoreError<GS::Error>> {
+        let path = self.block_path(hash);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(BlockStoreError::Io(path, e)),
+        };
+
+        let block = match read_header(&mut contents.as_slice()) {
+            Ok(Some((compression, len))) if len <= BLOCK_SIZE => {
+                compression::decompress(compression, &contents[HEADER_LEN..], len).ok()
+            }
+            _ => None,
+        };
+
+        Ok(block.filter(|block| &blocks::hash(block) == hash))
+    }
+
+    /// How many blocks the block store holds, and how much space they take.
+    pub fn block_store_stats(&self) -> Result<BlockStoreStats, BlockStoreError<GS::Error>> {
+        let mut stats = BlockStoreStats::default();
+        for path in self.block_files()? {
+            let io_err = |e| BlockStoreError::Io(path.clone(), e);
+            if parse_hash(&path).is_none() {
+                continue
+            }
+
+            let mut file = File::open(&path).map_err(io_err)?;
+            if let Ok(Some((_, len))) = read_header(&mut file) {
+                stats.blocks += 1;
+                stats.size += len;
+                stats.stored += file.metadata().map_err(io_err)?.len();
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn block_files(&self) -> Result<Vec<PathBuf>, BlockStoreError<GS::Error>> {
+        let dir = self.cfg().global_db.join(BLOCKS_DIR);
+        let files = match fs::read_dir(&dir) {
+            Ok(files) => files,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(BlockStoreError::Io(dir, e)),
+        };
+
+        files.map(|file| file.map(|f| f.path()).map_err(|e| BlockStoreError::Io(dir.clone(), e)))
+            .collect()
+    }
+
+    /// Remove all blocks from the block store which are no longer used by any root, and what's
+    /// left of blocks which were never completely written. Returns how many blocks were removed.
+    ///
+    /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use temp_testdir::TempDir;
+    /// # let td = TempDir::new("test", true);
+    /// # let mut cfg = Config::default();
+    /// # cfg.global_db = td.to_path_buf();
+    /// let dfs = Dfs::new(cfg).unwrap();
+    /// assert_eq!(dfs.gc_blocks().unwrap(), 0);
+    /// ```
+    pub fn gc_blocks(&self) -> Result<usize, BlockStoreError<GS::Error>> {
+        let mut removed = 0;
+        for path in self.block_files()? {
+            // held from checking whether the block is used until it's removed
+            let _lock = self.block_store_lock.lock().unwrap_or_else(|e| e.into_inner());
+            match parse_hash(&path) {
+                Some(hash) if self.connection.get_block_refs(&hash)? > 0 => continue,
+                Some(_) => removed += 1,
+                // a block which was never completely written
+                None => {}
+            }
+
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(BlockStoreError::Io(path, e)),
+                _ => {}
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// The hash of the block in a block file, None for files which aren't blocks.
+fn parse_hash(path: &Path) -> Option<Hash> {
+    path.file_name()
+        .and_then(|name| HEXLOWER.decode(name.to_string_lossy().as_bytes()).ok())
+        .and_then(|hash| <Hash>::try_from(hash).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use temp_testdir::TempDir;
+
+    use crate::Dfs;
+    use crate::block_store::BLOCKS_DIR;
+    use crate::compression::Compression;
+    use crate::config::Config;
+    use crate::global_store::GlobalStore;
+    use crate::root::blocks::{BLOCK_SIZE, hash};
+
+    #[test]
+    fn shared_blocks() {
+        let global = TempDir::new("block store", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+
+        let block = b"vendored ".repeat(1000);
+        let h = hash(&block);
+        dfs.store_block(&h, &block, Compression::Zstd).unwrap();
+        dfs.store_block(&h, &block, Compression::Zstd).unwrap();
+        assert_eq!(dfs.stored_block(&h).unwrap(), Some(block.clone()));
+
+        let stats = dfs.block_store_stats().unwrap();
+        assert_eq!((stats.blocks, stats.size), (1, block.len() as u64));
+        assert!(stats.saved() > 0);
+
+        // the block is kept as long as it's used
+        dfs.release_block(&h).unwrap();
+        assert_eq!(dfs.gc_blocks().unwrap(), 0);
+        dfs.release_block(&h).unwrap();
+        assert_eq!(dfs.gc_blocks().unwrap(), 1);
+        assert_eq!(dfs.stored_block(&h).unwrap(), None);
+    }
+
+    #[test]
+    fn failed_blocks_are_not_counted() {
+        let global = TempDir::new("block store failed", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+
+        // the blocks can't be written when their directory can't be created
+        fs::write(global.join(BLOCKS_DIR), "in the way").unwrap();
+        let block = b"lost ".repeat(1000);
+        let h = hash(&block);
+        assert!(dfs.store_block(&h, &block, Compression::None).is_err());
+        assert_eq!(dfs.connection.get_block_refs(&h).unwrap(), 0);
+
+        fs::remove_file(global.join(BLOCKS_DIR)).unwrap();
+        dfs.store_block(&h, &block, Compression::None).unwrap();
+        assert_eq!(dfs.connection.get_block_refs(&h).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn versions_share_blocks_between_roots() {
+        let global = TempDir::new("block store roots", true);
+        let dir_a = TempDir::new("block store a", true);
+        let dir_b = TempDir::new("block store b", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+
+        let contents: Vec<u8> = (0..BLOCK_SIZE * 2).map(|i| (i % 251) as u8).collect();
+        fs::write(dir_a.join("vendored"), &contents).unwrap();
+        fs::write(dir_b.join("vendored"), &contents).unwrap();
+
+        let root_a = dfs.new_root(&dir_a, "a").unwrap().connect().unwrap();
+        root_a.index().await.unwrap();
+        let root_b = dfs.new_root(&dir_b, "b").unwrap().connect().unwrap();
+        root_b.index().await.unwrap();
+
+        let mut ids = Vec::new();
+        for root in [&root_a, &root_b] {
+            let entry = root.get_by_path("/vendored").unwrap().unwrap();
+            assert!(root.save_version(&entry).unwrap());
+            ids.push(entry.id());
+        }
+
+        // the versions of both roots use the same blocks
+        assert_eq!(fs::read_dir(global.join(BLOCKS_DIR)).unwrap().count(), 2);
+
+        root_a.remove_versions(ids[0]).unwrap();
+        assert_eq!(dfs.gc_blocks().unwrap(), 0);
+        let version = &root_b.file_versions(ids[1]).unwrap()[0];
+        let restored = dir_b.join("restored");
+        assert!(root_b.write_version(version, &restored).unwrap());
+        assert_eq!(fs::read(&restored).unwrap(), contents);
+
+        root_b.remove_versions(ids[1]).unwrap();
+        assert_eq!(dfs.gc_blocks().unwrap(), 2);
+    }
+}
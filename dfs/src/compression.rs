@@ -0,0 +1,128 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+/// Blocks whose bytes are spread more evenly than this (in bits per byte) are taken to be
+/// compressed or encrypted already, and aren't compressed again.
+pub const MAX_ENTROPY: f64 = 7.5;
+
+/// The zstd level blocks are compressed with.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Extensions of formats which are compressed already.
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "avi", "br", "bz2", "docx", "flac", "gif", "gz", "heic", "jar", "jpeg", "jpg",
+    "lz4", "m4a", "mkv", "mov", "mp3", "mp4", "odt", "ogg", "png", "pptx", "rar", "tgz", "webm",
+    "webp", "xlsx", "xz", "zip", "zst",
+];
+
+/// How blocks are compressed, in the [block store](crate::block_store::BLOCKS_DIR) and when they
+/// are sent to peers. Set per root with [`Dfs::set_compression`](crate::Dfs::set_compression).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The compression to use for the file at `path`: none when its extension shows it's
+    /// compressed already.
+    pub fn for_path(self, path: &Path) -> Self {
+        let compressed = path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| COMPRESSED_EXTENSIONS.contains(&ext.as_str()));
+
+        if compressed { Compression::None } else { self }
+    }
+}
+
+/// How many bits of information each byte of `data` carries, from 0 (all bytes are equal) to
+/// 8 (random data).
+pub fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+
+    let len = data.len() as f64;
+    counts.iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Compress a block, and return how it was compressed. Returns None when it isn't worth it: the
+/// block looks compressed already, or doesn't get smaller.
+pub(crate) fn compress(compression: Compression, block: &[u8]) -> Option<(Compression, Vec<u8>)> {
+    match compression {
+        Compression::None => None,
+        Compression::Zstd => {
+            if entropy(block) > MAX_ENTROPY {
+                return None
+            }
+
+            let compressed = zstd::bulk::compress(block, ZSTD_LEVEL).ok()?;
+            if compressed.len() < block.len() { Some((Compression::Zstd, compressed)) } else { None }
+        }
+    }
+}
+
+/// Undo [`compress`]. Fails when the data is damaged, or would be larger than `max_len`.
+pub(crate) fn decompress(compression: Compression, data: &[u8], max_len: u64) -> io::Result<Vec<u8>> {
+    let mut block = Vec::new();
+    match compression {
+        Compression::None => block.extend_from_slice(data),
+        Compression::Zstd => {
+            zstd::Decoder::new(data)?.take(max_len + 1).read_to_end(&mut block)?;
+        }
+    }
+
+    if block.len() as u64 > max_len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "decompressed block is too large"))
+    }
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::compression::{Compression, compress, decompress, entropy};
+
+    #[test]
+    fn round_trip() {
+        let text = b"the same words, over and over again. ".repeat(100);
+        let (compression, compressed) = compress(Compression::Zstd, &text).unwrap();
+        assert_eq!(compression, Compression::Zstd);
+        assert!(compressed.len() < text.len());
+        assert_eq!(decompress(Compression::Zstd, &compressed, text.len() as u64).unwrap(), text);
+        assert!(decompress(Compression::Zstd, &compressed, 10).is_err());
+
+        // data which looks random isn't compressed
+        let noise: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        assert!(entropy(&noise) > 7.5);
+        assert_eq!(compress(Compression::Zstd, &noise), None);
+
+        assert_eq!(Compression::Zstd.for_path(Path::new("/photos/beach.JPG")), Compression::None);
+        assert_eq!(Compression::Zstd.for_path(Path::new("/notes.txt")), Compression::Zstd);
+    }
+}
@@ -1,10 +1,43 @@
 use std::path::PathBuf;
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
+use libp2p::Multiaddr;
+
+use crate::compression::Compression;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Config {
     pub local_db: PathBuf,
     pub global_db: PathBuf,
+
+    /// Name of this node, shown to peers. Only used when the local node is first created.
+    /// Defaults to the hostname, or "dfs" when it can't be found.
+    pub name: String,
+
+    /// Addresses this node listens on. Invites created through
+    /// [`Node::create_invite`](crate::network::Node::create_invite) get the addresses the node
+    /// ended up listening on instead.
+    pub listen_addresses: Vec<Multiaddr>,
+
+    /// Discover peers on the local network with mDNS.
+    pub mdns: bool,
+
+    /// How long tombstones of deleted entries are kept at most. Usually they are removed as soon
+    /// as all peers of a root have them, this is for peers which never come back.
+    /// [`Duration::MAX`] keeps them until all peers have them.
+    pub tombstone_retention: Duration,
+
+    /// How many previous versions of a file are kept when a sync replaces its contents,
+    /// see [`FileVersion`](crate::root::history::FileVersion). 0 keeps none.
+    pub versions_kept: usize,
+
+    /// How long previous versions of files are kept at most. [`Duration::MAX`] keeps them until
+    /// there are more than [`versions_kept`](Config::versions_kept).
+    pub version_retention: Duration,
+
+    /// How blocks are compressed in the block store and on the wire, for roots which don't
+    /// [set](crate::Dfs::set_compression) it themselves.
+    pub compression: Compression,
 }
 
 impl Default for Config {
@@ -15,13 +48,30 @@ impl Default for Config {
 
         data_dir.push("dfs");
 
+        let name = hostname().unwrap_or_else(|| "dfs".into());
+
         Self {
             local_db: ".dfs".into(),
-            global_db: data_dir
+            global_db: data_dir,
+            name,
+            listen_addresses: vec!["/ip4/0.0.0.0/tcp/4242".parse().expect("valid multiaddr")],
+            mdns: true,
+            tombstone_retention: Duration::from_secs(30 * 24 * 60 * 60),
+            versions_kept: 10,
+            version_retention: Duration::from_secs(30 * 24 * 60 * 60),
+            compression: Compression::Zstd,
         }
     }
 }
 
+/// The name of this machine.
+fn hostname() -> Option<String> {
+    hostname::get().ok()
+        .and_then(|name| name.into_string().ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
 pub mod test {
     use temp_testdir::TempDir;
     use crate::config::Config;
@@ -31,6 +81,8 @@ pub mod test {
         pub fn test_config(dir: &TempDir) -> Self {
             Config {
                 global_db: dir.to_path_buf(),
+                listen_addresses: vec!["/ip4/127.0.0.1/tcp/0".parse().expect("valid multiaddr")],
+                mdns: false,
                 ..Default::default()
             }
         }
@@ -1,19 +1,39 @@
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use thiserror::Error;
 
+use crate::compression::Compression;
+use crate::encryption::{EncryptionError, KeyGrant, RootKey};
 use crate::config::Config;
 use crate::global_store::GlobalStore;
 use crate::global_store::heed_store::Heed;
-use crate::peer::Peer;
-use crate::root::Root;
+use crate::peer::{Peer, PeerIdentity};
+use crate::peer::identity::{load_or_generate, IdentityError, IDENTITY_FILE};
+use crate::peer::invite::{Invite, InviteError};
+use crate::root::{Root, StorableRoot};
+use crate::root::selection::Selection;
+use crate::root::share::ShareMode;
 use uuid::Uuid;
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
 
 #[derive(Debug, Error)]
 pub enum NewDfsError<GSE> {
     #[error("db error: {0}")]
-    DbInteractionError(#[from] GSE)
+    DbInteractionError(#[from] GSE),
+
+    #[error("failed to load the identity of this node: {0}")]
+    Identity(IdentityError),
+
+    #[error("the key of this node doesn't match the local peer stored in the global store")]
+    IdentityMismatch,
+
+    #[error("the key of this node is stored as the key of a remote peer in the global store")]
+    KeyOfRemotePeer,
 }
 
 #[derive(Debug, Error)]
@@ -21,8 +41,62 @@ pub enum NewPeerError<GSE> {
     #[error("db error: {0}")]
     Sqlite(#[from] GSE),
 
-    #[error("peer with this name already exists")]
-    PeerExists
+    #[error("peer with this id or public key already exists")]
+    PeerExists,
+
+    #[error("can't add the local node as a remote peer")]
+    LocalPeer,
+}
+
+#[derive(Debug, Error)]
+pub enum GetPeerError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+}
+
+#[derive(Debug, Error)]
+pub enum ShareRootError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error("peer isn't known to this dfs (add it first)")]
+    UnknownPeer,
+
+    #[error("root isn't known to this dfs")]
+    UnknownRoot,
+
+    #[error("roots can't be shared with the local node")]
+    LocalPeer,
+}
+
+#[derive(Debug, Error)]
+pub enum CreateInviteError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error("root isn't known to this dfs")]
+    UnknownRoot,
+
+    #[error(transparent)]
+    Invite(InviteError),
+}
+
+#[derive(Debug, Error)]
+pub enum AcceptInviteError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error(transparent)]
+    Invite(InviteError),
+
+    #[error("invite isn't known to this dfs or was already redeemed")]
+    UnknownInvite,
+
+    #[error("can't accept an invite from the local node")]
+    LocalPeer,
+
+    #[error("root {0} already exists here, an invite can't change who it's shared with")]
+    RootExists(Uuid),
 }
 
 #[derive(Debug, Error)]
@@ -52,10 +126,33 @@ pub enum NewRootError<GSE> {
     RootExists
 }
 
+#[derive(Debug, Error)]
+pub enum KeyGrantError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error("root isn't known to this dfs")]
+    UnknownRoot,
+
+    #[error("the root isn't encrypted, or this node doesn't have its key")]
+    NotEncrypted,
+
+    #[error("the root isn't shared with the peer in a way that allows it to read the root")]
+    NotAuthorized,
+
+    #[error(transparent)]
+    Encryption(EncryptionError),
+}
 
 pub struct Dfs<GS = Heed>{
     cfg: Config,
+    keypair: Keypair,
+    local_peer: Peer,
     pub(crate) connection: GS,
+
+    /// held while a block is added to the [block store](crate::block_store::BLOCKS_DIR) and
+    /// while unused blocks are removed from it, so a block isn't removed while it's used again
+    pub(crate) block_store_lock: Mutex<()>,
 }
 
 impl Dfs<Heed> {
@@ -66,9 +163,29 @@ impl Dfs<Heed> {
 
 impl<GS: GlobalStore> Dfs<GS> {
     fn new_internal(cfg: Config) -> Result<Self, NewDfsError<GS::Error>> {
+        let connection = GS::new(&cfg.global_db)?;
+        let keypair = load_or_generate(&cfg.global_db.join(IDENTITY_FILE))
+            .map_err(NewDfsError::Identity)?;
+
+        let local_peer = match connection.get_local_peer()? {
+            Some(peer) if peer.public_key() == &keypair.public() => peer,
+            Some(_) => return Err(NewDfsError::IdentityMismatch),
+            None => {
+                // first time this DFS is opened: register ourselves
+                let peer = Peer::new(cfg.name.clone(), keypair.public());
+                // the peer is new, so only its key can be there already
+                connection.put_local_peer(&peer)?
+                    .to_err(|| NewDfsError::KeyOfRemotePeer)?;
+                peer
+            }
+        };
+
         Ok(Self {
-            connection: GS::new(&cfg.global_db)?,
+            connection,
+            keypair,
+            local_peer,
             cfg,
+            block_store_lock: Mutex::new(()),
         })
     }
 
@@ -89,49 +206,118 @@ impl<GS: GlobalStore> Dfs<GS> {
         &self.cfg
     }
 
-    /// Adds a new peer to the DFS. Peers are global, but not all roots are shared
-    /// with all peers.
+    /// Get the keypair of this node. Only used to authenticate this node to others.
+    pub(crate) fn keypair(&self) -> &Keypair {
+        &self.keypair
+    }
+
+    /// Get the [`PeerId`] of this node, derived from its public key. The private key is
+    /// stored next to the [`GlobalStore`] and is the same every time a DFS is opened with the same config.
     ///
     /// ```
     /// # use dfs::config::Config;
     /// # use dfs::Dfs;
     /// # use temp_testdir::TempDir;
     /// # let td = TempDir::new("test", true);
+    /// # let mut cfg = Config::default();
+    /// # cfg.global_db = td.to_path_buf();
+    /// let first = Dfs::new(cfg.clone()).unwrap().peer_id();
+    /// let second = Dfs::new(cfg).unwrap().peer_id();
+    ///
+    /// assert_eq!(first, second);
+    /// ```
+    pub fn peer_id(&self) -> PeerId {
+        self.keypair.public().into_peer_id()
+    }
+
+    /// Get the peer representing this node. It is created the first time a DFS is opened
+    /// and it's key is the one stored next to the [`GlobalStore`].
     ///
+    /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use temp_testdir::TempDir;
+    /// # let td = TempDir::new("test", true);
     /// let mut cfg = Config::default();
     /// # cfg.global_db = td.to_path_buf();
+    /// cfg.name = "laptop".to_string();
+    ///
     /// let dfs = Dfs::new(cfg).unwrap();
     ///
-    /// let peer = dfs.new_peer("jonathan".to_string()).unwrap();
+    /// assert_eq!(dfs.local_peer().name(), "laptop");
+    /// assert_eq!(dfs.local_peer().peer_id(), dfs.peer_id());
+    /// ```
+    pub fn local_peer(&self) -> &Peer {
+        &self.local_peer
+    }
+
+    /// Get the public identity of this node. Other nodes use this to add this node as a peer
+    /// with [`add_peer`](Dfs::add_peer).
+    pub fn identity(&self) -> PeerIdentity {
+        self.local_peer.identity()
+    }
+
+    /// Adds a remote peer to the DFS from the identity it exported. Peers are global,
+    /// but not all roots are shared with all peers. Only the public key of a peer is stored.
     ///
-    /// assert_eq!(peer.name(), "jonathan");
     /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use temp_testdir::TempDir;
+    /// # let td_a = TempDir::new("test a", true);
+    /// # let td_b = TempDir::new("test b", true);
+    /// let dfs_a = Dfs::new(Config::test_config(&td_a)).unwrap();
+    /// let dfs_b = Dfs::new(Config::test_config(&td_b)).unwrap();
     ///
-    /// Two peers can have the same name. Their ID and keys are then different.
+    /// let peer = dfs_a.add_peer(dfs_b.identity()).unwrap();
+    ///
+    /// assert_eq!(peer.peer_id(), dfs_b.peer_id());
+    /// assert_eq!(dfs_a.get_peers().unwrap().len(), 1);
+    /// ```
+    ///
+    /// The same peer can't be added twice, and a node can't add itself.
     ///
     /// ```
     /// # use dfs::config::Config;
     /// # use dfs::Dfs;
     /// # use temp_testdir::TempDir;
-    /// # let td = TempDir::new("test", true);
-    /// # let mut cfg = Config::default();
-    /// # cfg.global_db = td.to_path_buf();
-    /// # let dfs = Dfs::new(cfg).unwrap();
+    /// # use dfs::dfs_struct::NewPeerError;
+    /// # let td_a = TempDir::new("test a", true);
+    /// # let td_b = TempDir::new("test b", true);
+    /// # let dfs_a = Dfs::new(Config::test_config(&td_a)).unwrap();
+    /// # let dfs_b = Dfs::new(Config::test_config(&td_b)).unwrap();
+    /// dfs_a.add_peer(dfs_b.identity()).unwrap();
     ///
-    /// let peer1 = dfs.new_peer("jonathan").unwrap();
-    /// let peer2 = dfs.new_peer("jonathan").unwrap();
-    ///
-    /// assert_eq!(peer1.name(), peer2.name());
-    /// assert_ne!(peer1.id(), peer2.id());
+    /// assert!(matches!(dfs_a.add_peer(dfs_b.identity()), Err(NewPeerError::PeerExists)));
+    /// assert!(matches!(dfs_a.add_peer(dfs_a.identity()), Err(NewPeerError::LocalPeer)));
     /// ```
-    pub fn new_peer(&self, name: impl AsRef<str>) -> Result<Peer, NewPeerError<GS::Error>> {
-        let peer = Peer::new(name.as_ref().to_string());
+    pub fn add_peer(&self, identity: PeerIdentity) -> Result<Peer, NewPeerError<GS::Error>> {
+        if identity.peer_id() == self.peer_id() {
+            return Err(NewPeerError::LocalPeer)
+        }
+
+        let peer = Peer::from(identity);
         self.connection.put_peer(peer.id(), &peer, false)?
             .to_err(|| NewPeerError::PeerExists)?;
 
         Ok(peer)
     }
 
+    /// Get a peer by its uuid. This may return the local peer.
+    pub fn get_peer(&self, id: Uuid) -> Result<Option<Peer>, GetPeerError<GS::Error>> {
+        Ok(self.connection.get_peer(id)?)
+    }
+
+    /// Get all remote peers (so excluding the local peer).
+    pub fn get_peers(&self) -> Result<Vec<Peer>, GetPeerError<GS::Error>> {
+        Ok(
+            self.connection.get_all_peers()?
+                .into_iter()
+                .filter(|p| p.id() != self.local_peer.id())
+                .collect()
+        )
+    }
+
     /// Adds a new root to the DFS. Roots are folders on your filesystem which are shared by
     /// some peers.
     ///
@@ -191,8 +377,17 @@ impl<GS: GlobalStore> Dfs<GS> {
     ///     Err(NewRootError::PathIsNotDir(_))
     /// ));
     /// ```
-    pub fn new_root(&self, path: impl AsRef<Path>, name: impl AsRef<str>) -> Result<Root<GS>, NewRootError<GS::Error>> {
+    pub fn new_root(&self, path: impl AsRef<Path>, name: impl AsRef<str>) -> Result<Root<'_, GS>, NewRootError<GS::Error>> {
+        self.new_root_with_id(path, name, Uuid::new_v4())
+    }
+
+    /// Create a local copy of a root this node was invited to with [`accept_invite`](Dfs::accept_invite).
+    /// The new root has the same uuid as the root of the inviter.
+    pub fn join_root(&self, invite: &Invite, path: impl AsRef<Path>, name: impl AsRef<str>) -> Result<Root<'_, GS>, NewRootError<GS::Error>> {
+        self.new_root_with_id(path, name, invite.root())
+    }
 
+    fn new_root_with_id(&self, path: impl AsRef<Path>, name: impl AsRef<str>, id: Uuid) -> Result<Root<'_, GS>, NewRootError<GS::Error>> {
         let path = path.as_ref().to_path_buf();
 
         if !path.exists() {
@@ -203,7 +398,7 @@ impl<GS: GlobalStore> Dfs<GS> {
 
         let path = path.canonicalize()?;
 
-        let root = Root::new(self, name.as_ref().to_string(), path);
+        let root = Root::new(self, id, name.as_ref().to_string(), path);
 
         self.connection.put_root(root.id(), &root, false)
             .map_err(NewRootError::DbInteractionError)?
@@ -231,7 +426,7 @@ impl<GS: GlobalStore> Dfs<GS> {
     ///
     /// assert_eq!(root.id(), initial_root.id());
     /// ```
-    pub fn get_root_by_name(&self, name: impl AsRef<str>) -> Result<Option<Root<GS>>, GetRootError<GS::Error>> {
+    pub fn get_root_by_name(&self, name: impl AsRef<str>) -> Result<Option<Root<'_, GS>>, GetRootError<GS::Error>> {
          Ok(
              self.connection.get_root_by_name(name.as_ref())?
              .map(|r| {
@@ -259,7 +454,7 @@ impl<GS: GlobalStore> Dfs<GS> {
     ///
     /// assert_eq!(root.name(), initial_root.name());
     /// ```
-    pub fn get_root(&self, id: Uuid) -> Result<Option<Root<GS>>, GetRootError<GS::Error>> {
+    pub fn get_root(&self, id: Uuid) -> Result<Option<Root<'_, GS>>, GetRootError<GS::Error>> {
         Ok(
             self.connection.get_root(id)?
                 .map(|r| {
@@ -286,7 +481,7 @@ impl<GS: GlobalStore> Dfs<GS> {
     /// assert_eq!(roots.len(), 1);
     /// assert_eq!(roots[0].name(), initial_root.name());
     /// ```
-    pub fn get_roots(&self) -> Result<Vec<Root<GS>>, GetRootError<GS::Error>> {
+    pub fn get_roots(&self) -> Result<Vec<Root<'_, GS>>, GetRootError<GS::Error>> {
         Ok(
             self.connection.get_all_roots()?
                 .into_iter()
@@ -294,22 +489,417 @@ impl<GS: GlobalStore> Dfs<GS> {
                 .collect()
         )
     }
+
+    /// Share a root with a remote peer. When the root was already shared with this peer,
+    /// only the [`ShareMode`] is updated.
+    ///
+    /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use temp_testdir::TempDir;
+    /// use dfs::root::share::ShareMode;
+    ///
+    /// # let td_a = TempDir::new("test a", true);
+    /// # let td_b = TempDir::new("test b", true);
+    /// let dfs_a = Dfs::new(Config::test_config(&td_a)).unwrap();
+    /// let dfs_b = Dfs::new(Config::test_config(&td_b)).unwrap();
+    /// let peer = dfs_a.add_peer(dfs_b.identity()).unwrap();
+    /// let root = dfs_a.new_root(&td_a, "test").unwrap();
+    ///
+    /// dfs_a.share_root(&root, &peer, ShareMode::ReadOnly).unwrap();
+    /// assert_eq!(dfs_a.share_mode(root.id(), &peer.peer_id()).unwrap(), Some(ShareMode::ReadOnly));
+    ///
+    /// dfs_a.share_root(&root, &peer, ShareMode::ReadWrite).unwrap();
+    /// let members = dfs_a.root_peers(&root).unwrap();
+    /// assert_eq!(members.len(), 1);
+    /// assert_eq!(members[0].1, ShareMode::ReadWrite);
+    ///
+    /// assert!(dfs_a.unshare_root(&root, &peer).unwrap());
+    /// assert!(dfs_a.root_peers(&root).unwrap().is_empty());
+    /// ```
+    pub fn share_root(&self, root: &StorableRoot, peer: &Peer, mode: ShareMode) -> Result<(), ShareRootError<GS::Error>> {
+        if peer.id() == self.local_peer.id() {
+            return Err(ShareRootError::LocalPeer)
+        }
+        if self.connection.get_peer(peer.id())?.is_none() {
+            return Err(ShareRootError::UnknownPeer)
+        }
+        if self.connection.get_root(root.id())?.is_none() {
+            return Err(ShareRootError::UnknownRoot)
+        }
+
+        self.connection.put_root_member(root.id(), peer.id(), mode)?;
+        Ok(())
+    }
+
+    /// Stop sharing a root with a peer. Returns whether the root was shared with the peer.
+    pub fn unshare_root(&self, root: &StorableRoot, peer: &Peer) -> Result<bool, ShareRootError<GS::Error>> {
+        Ok(self.connection.remove_root_member(root.id(), peer.id())?)
+    }
+
+    /// Get all peers a root is shared with, together with the mode it is shared in.
+    pub fn root_peers(&self, root: &StorableRoot) -> Result<Vec<(Peer, ShareMode)>, GetPeerError<GS::Error>> {
+        let mut res = Vec::new();
+        for (peer_id, mode) in self.connection.get_root_members(root.id())? {
+            if let Some(peer) = self.connection.get_peer(peer_id)? {
+                res.push((peer, mode));
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Create an invite for a root. The invite is returned as a signed token which can be
+    /// given to another node out of band. That node can use [`accept_invite`](Dfs::accept_invite)
+    /// to register this node as its peer. Once it contacts this node, it hands back the invite
+    /// and this node [redeems](Dfs::redeem_invite) it, sharing the root with it in `mode`.
+    ///
+    /// The invitee dials this node at the [configured](Config::listen_addresses) addresses, leaving
+    /// out those with an unspecified ip or port 0, which can't be dialed. While a
+    /// [`Node`](crate::network::Node) is running, create invites with
+    /// [`Node::create_invite`](crate::network::Node::create_invite) instead, which uses the
+    /// addresses the node actually listens on.
+    ///
+    /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use temp_testdir::TempDir;
+    /// # use std::time::Duration;
+    /// use dfs::root::share::ShareMode;
+    ///
+    /// # let td_a = TempDir::new("test a", true);
+    /// # let td_b = TempDir::new("test b", true);
+    /// let dfs_a = Dfs::new(Config::test_config(&td_a)).unwrap();
+    /// let dfs_b = Dfs::new(Config::test_config(&td_b)).unwrap();
+    /// let root = dfs_a.new_root(&td_a, "test").unwrap();
+    ///
+    /// let token = dfs_a.create_invite(&root, ShareMode::ReadOnly, Duration::from_secs(3600)).unwrap();
+    ///
+    /// // on the other node
+    /// let invite = dfs_b.accept_invite(&token).unwrap();
+    /// assert_eq!(invite.root(), root.id());
+    /// assert_eq!(dfs_b.share_mode(root.id(), &dfs_a.peer_id()).unwrap(), Some(ShareMode::ReceiveOnly));
+    ///
+    /// // back on the inviting node, once the invitee presents the invite
+    /// dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+    /// assert_eq!(dfs_a.share_mode(root.id(), &dfs_b.peer_id()).unwrap(), Some(ShareMode::ReadOnly));
+    /// ```
+    pub fn create_invite(&self, root: &StorableRoot, mode: ShareMode, expiry: Duration) -> Result<String, CreateInviteError<GS::Error>> {
+        self.create_invite_at(root, mode, self.cfg().listen_addresses.iter().cloned(), expiry)
+    }
+
+    /// [Create an invite](Dfs::create_invite) with which the invitee dials this node at
+    /// `addresses`. Addresses which can't be dialed are left out.
+    pub(crate) fn create_invite_at(&self, root: &StorableRoot, mode: ShareMode, addresses: impl IntoIterator<Item = Multiaddr>, expiry: Duration) -> Result<String, CreateInviteError<GS::Error>> {
+        if self.connection.get_root(root.id())?.is_none() {
+            return Err(CreateInviteError::UnknownRoot)
+        }
+
+        let invite = Invite::new(
+            self.identity(),
+            addresses.into_iter().filter(is_dialable).collect(),
+            root.id(),
+            root.name().to_string(),
+            mode,
+            expiry
+        );
+
+        let token = invite.to_token(&self.keypair)
+            .map_err(CreateInviteError::Invite)?;
+        self.connection.put_invite(&invite)?;
+
+        Ok(token)
+    }
+
+    /// Accept an invite token created by another node. This verifies the token, adds the
+    /// inviter as a peer (remembering the addresses in the invite) and records that the root is
+    /// shared with it. Use [`join_root`](Dfs::join_root) to create the local copy of the root.
+    ///
+    /// Invites which were tampered with or have expired are rejected. Anyone can sign an invite
+    /// for any root, so invites for roots which already exist here are rejected as well: they
+    /// would make their signer a member of the root. Roots which exist are shared with
+    /// [`share_root`](Dfs::share_root) instead.
+    ///
+    /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use temp_testdir::TempDir;
+    /// # let td = TempDir::new("test", true);
+    /// # let dfs = Dfs::new(Config::test_config(&td)).unwrap();
+    /// assert!(dfs.accept_invite("NOTATOKEN").is_err());
+    /// ```
+    pub fn accept_invite(&self, token: &str) -> Result<Invite, AcceptInviteError<GS::Error>> {
+        let invite = Invite::from_token(token)
+            .map_err(AcceptInviteError::Invite)?;
+
+        if invite.inviter().peer_id() == self.peer_id() {
+            return Err(AcceptInviteError::LocalPeer)
+        }
+        if self.connection.get_root(invite.root())?.is_some() {
+            return Err(AcceptInviteError::RootExists(invite.root()))
+        }
+
+        let mut peer = self.connection.get_peer_by_peer_id(&invite.inviter().peer_id())?
+            .unwrap_or_else(|| Peer::from(invite.inviter().clone()));
+        for address in invite.addresses() {
+            peer.add_address(address.clone());
+        }
+        self.connection.put_peer(peer.id(), &peer, true)?;
+
+        self.connection.put_root_member(invite.root(), peer.id(), invite.mode().inverse())?;
+
+        Ok(invite)
+    }
+
+    /// Redeem an invite this node created, once the invited node presents it together with
+    /// its identity. The invited node is added as a peer and the root is shared with it.
+    /// Every invite can be redeemed only once. Invites which are rejected, because they expired
+    /// or are presented by this node itself, aren't used up.
+    ///
+    /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use dfs::dfs_struct::AcceptInviteError;
+    /// # use temp_testdir::TempDir;
+    /// # use std::time::Duration;
+    /// # use dfs::root::share::ShareMode;
+    /// # let td_a = TempDir::new("test a", true);
+    /// # let td_b = TempDir::new("test b", true);
+    /// # let dfs_a = Dfs::new(Config::test_config(&td_a)).unwrap();
+    /// # let dfs_b = Dfs::new(Config::test_config(&td_b)).unwrap();
+    /// # let root = dfs_a.new_root(&td_a, "test").unwrap();
+    /// let token = dfs_a.create_invite(&root, ShareMode::ReadOnly, Duration::from_secs(3600)).unwrap();
+    /// let invite = dfs_b.accept_invite(&token).unwrap();
+    ///
+    /// let res = dfs_a.redeem_invite(invite.id(), dfs_a.identity());
+    /// assert!(matches!(res, Err(AcceptInviteError::LocalPeer)));
+    ///
+    /// dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+    /// let res = dfs_a.redeem_invite(invite.id(), dfs_b.identity());
+    /// assert!(matches!(res, Err(AcceptInviteError::UnknownInvite)));
+    /// ```
+    pub fn redeem_invite(&self, id: Uuid, identity: PeerIdentity) -> Result<Peer, AcceptInviteError<GS::Error>> {
+        if identity.peer_id() == self.peer_id() {
+            return Err(AcceptInviteError::LocalPeer)
+        }
+
+        // expired invites are kept, they can't be redeemed anyway
+        let mut expired = false;
+        let invite = self.connection.take_invite(id, |invite| {
+            expired = invite.is_expired();
+            !expired
+        })?.ok_or(AcceptInviteError::UnknownInvite)?;
+        if expired {
+            return Err(AcceptInviteError::Invite(InviteError::Expired))
+        }
+
+        let peer = match self.connection.get_peer_by_peer_id(&identity.peer_id())? {
+            Some(peer) => peer,
+            None => {
+                let peer = Peer::from(identity);
+                self.connection.put_peer(peer.id(), &peer, false)?;
+                peer
+            }
+        };
+
+        self.connection.put_root_member(invite.root(), peer.id(), invite.mode())?;
+
+        Ok(peer)
+    }
+
+    /// Keep only part of a root on disk on this node, see [`Selection`]. Takes effect during
+    /// the next [sync](crate::sync::sync) of the root: entries which are no longer selected are
+    /// removed from disk (once they are in sync with the peer), newly selected ones are downloaded.
+    ///
+    /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use temp_testdir::TempDir;
+    /// # use dfs::root::selection::Selection;
+    /// # let tempdir = TempDir::new("test", true);
+    /// # let dfs = Dfs::new(Config::test_config(&tempdir)).unwrap();
+    /// # let root_dir = TempDir::new("test root", true);
+    /// let root = dfs.new_root(&root_dir, "photos").unwrap();
+    /// assert_eq!(dfs.selection(root.id()).unwrap(), Selection::default());
+    ///
+    /// let selection = Selection::default().include("/2021");
+    /// dfs.set_selection(&root, &selection).unwrap();
+    /// assert_eq!(dfs.selection(root.id()).unwrap(), selection);
+    /// ```
+    pub fn set_selection(&self, root: &StorableRoot, selection: &Selection) -> Result<(), ShareRootError<GS::Error>> {
+        if self.connection.get_root(root.id())?.is_none() {
+            return Err(ShareRootError::UnknownRoot)
+        }
+
+        self.connection.put_selection(root.id(), self.local_peer.id(), selection)?;
+        Ok(())
+    }
+
+    /// The parts of a root this node keeps on disk. Everything, unless a selection was set
+    /// with [`set_selection`](Dfs::set_selection).
+    pub fn selection(&self, root: Uuid) -> Result<Selection, GetRootError<GS::Error>> {
+        Ok(self.connection.get_selection(root, self.local_peer.id())?.unwrap_or_default())
+    }
+
+    /// Set how the blocks of a root are compressed, in the block store and when they are sent
+    /// to peers. Files which are compressed already are never compressed again.
+    ///
+    /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use temp_testdir::TempDir;
+    /// # use dfs::compression::Compression;
+    /// # let tempdir = TempDir::new("test", true);
+    /// # let dfs = Dfs::new(Config::test_config(&tempdir)).unwrap();
+    /// # let root_dir = TempDir::new("test root", true);
+    /// let root = dfs.new_root(&root_dir, "media").unwrap();
+    /// assert_eq!(dfs.compression(root.id()).unwrap(), Compression::Zstd);
+    ///
+    /// dfs.set_compression(&root, Compression::None).unwrap();
+    /// assert_eq!(dfs.compression(root.id()).unwrap(), Compression::None);
+    /// ```
+    pub fn set_compression(&self, root: &StorableRoot, compression: Compression) -> Result<(), ShareRootError<GS::Error>> {
+        if self.connection.get_root(root.id())?.is_none() {
+            return Err(ShareRootError::UnknownRoot)
+        }
+
+        self.connection.put_compression(root.id(), compression)?;
+        Ok(())
+    }
+
+    /// How the blocks of a root are compressed. The [configured](Config::compression) default,
+    /// unless it was set with [`set_compression`](Dfs::set_compression).
+    pub fn compression(&self, root: Uuid) -> Result<Compression, GetRootError<GS::Error>> {
+        Ok(self.connection.get_compression(root)?.unwrap_or(self.cfg().compression))
+    }
+
+    /// Encrypt a root: generate the [`RootKey`] with which it's sealed for peers it's shared with
+    /// [encrypted](ShareMode::Encrypted). Peers which may read the root get the key with
+    /// [`grant_root_key`](Dfs::grant_root_key). Nothing changes for roots which are encrypted already.
+    pub fn encrypt_root(&self, root: &StorableRoot) -> Result<(), ShareRootError<GS::Error>> {
+        if self.connection.get_root(root.id())?.is_none() {
+            return Err(ShareRootError::UnknownRoot)
+        }
+
+        if self.connection.get_root_key(root.id())?.is_none() {
+            self.connection.put_root_key(root.id(), &RootKey::generate())?;
+        }
+        Ok(())
+    }
+
+    /// Whether this node has the key of the root, so it can read the root when it's relayed
+    /// through peers which only have it encrypted.
+    pub fn is_encrypted(&self, root: Uuid) -> Result<bool, GetRootError<GS::Error>> {
+        Ok(self.root_key(root)?.is_some())
+    }
+
+    pub(crate) fn root_key(&self, root: Uuid) -> Result<Option<RootKey>, GS::Error> {
+        self.connection.get_root_key(root)
+    }
+
+    /// Hand the key of an encrypted root to a peer the root is shared with, wrapped with the
+    /// public key of the peer. The grant is returned as a signed token, which the peer
+    /// [accepts](Dfs::accept_root_key). Peers which only get the root
+    /// [encrypted](ShareMode::Encrypted) can't be granted the key.
+    ///
+    /// ```
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use dfs::root::share::ShareMode;
+    /// # use temp_testdir::TempDir;
+    /// # use std::time::Duration;
+    /// # let td_a = TempDir::new("test a", true);
+    /// # let td_b = TempDir::new("test b", true);
+    /// # let dfs_a = Dfs::new(Config::test_config(&td_a)).unwrap();
+    /// # let dfs_b = Dfs::new(Config::test_config(&td_b)).unwrap();
+    /// let root = dfs_a.new_root(&td_a, "secrets").unwrap();
+    /// dfs_a.encrypt_root(&root).unwrap();
+    ///
+    /// let invite = dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap();
+    /// let invite = dfs_b.accept_invite(&invite).unwrap();
+    /// let peer = dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+    ///
+    /// let grant = dfs_a.grant_root_key(&root, &peer).unwrap();
+    /// assert_eq!(dfs_b.accept_root_key(&grant).unwrap(), root.id());
+    /// assert!(dfs_b.is_encrypted(root.id()).unwrap());
+    /// ```
+    pub fn grant_root_key(&self, root: &StorableRoot, peer: &Peer) -> Result<String, KeyGrantError<GS::Error>> {
+        if self.connection.get_root(root.id())?.is_none() {
+            return Err(KeyGrantError::UnknownRoot)
+        }
+        let key = self.connection.get_root_key(root.id())?.ok_or(KeyGrantError::NotEncrypted)?;
+
+        match self.connection.get_root_member(root.id(), peer.id())? {
+            Some(ShareMode::Encrypted) | None => return Err(KeyGrantError::NotAuthorized),
+            Some(_) => {}
+        }
+
+        key.grant(root.id(), &self.keypair, self.identity(), peer.public_key())
+            .map_err(KeyGrantError::Encryption)
+    }
+
+    /// Accept the key of an encrypted root granted by a peer with
+    /// [`grant_root_key`](Dfs::grant_root_key). The peer must share the root with us, other than
+    /// [encrypted](ShareMode::Encrypted). Returns the id of the root.
+    pub fn accept_root_key(&self, token: &str) -> Result<Uuid, KeyGrantError<GS::Error>> {
+        let grant = KeyGrant::from_token(token).map_err(KeyGrantError::Encryption)?;
+
+        let sender = self.connection.get_peer_by_peer_id(&grant.sender().peer_id())?
+            .ok_or(KeyGrantError::NotAuthorized)?;
+        match self.connection.get_root_member(grant.root(), sender.id())? {
+            Some(ShareMode::Encrypted) | None => return Err(KeyGrantError::NotAuthorized),
+            Some(_) => {}
+        }
+
+        let key = grant.open(&self.keypair).map_err(KeyGrantError::Encryption)?;
+        self.connection.put_root_key(grant.root(), &key)?;
+        Ok(grant.root())
+    }
+
+    /// Look up how a root is shared with the peer with this [`PeerId`]. Every path
+    /// that exchanges data of a root with a peer must check this first. Returns None
+    /// when the peer is unknown or the root isn't shared with it.
+    pub fn share_mode(&self, root: Uuid, peer: &PeerId) -> Result<Option<ShareMode>, GetPeerError<GS::Error>> {
+        match self.connection.get_peer_by_peer_id(peer)? {
+            Some(peer) => Ok(self.connection.get_root_member(root, peer.id())?),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Whether another node can dial `address`, which isn't the case for addresses which are only
+/// meaningful to listen on.
+fn is_dialable(address: &Multiaddr) -> bool {
+    address.iter().all(|protocol| match protocol {
+        Protocol::Ip4(ip) => !ip.is_unspecified(),
+        Protocol::Ip6(ip) => !ip.is_unspecified(),
+        Protocol::Tcp(port) | Protocol::Udp(port) => port != 0,
+        _ => true,
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use temp_testdir::TempDir;
 
     use crate::config::Config;
     use crate::Dfs;
+    use crate::dfs_struct::{AcceptInviteError, NewDfsError, ShareRootError};
+    use crate::global_store::GlobalStore;
+    use crate::global_store::heed_store::Heed;
+    use crate::peer::Peer;
+    use crate::peer::identity::{load_or_generate, IDENTITY_FILE};
+    use crate::peer::invite::Invite;
+    use crate::root::share::ShareMode;
 
     #[test]
     fn root_same_path() {
         let root_a_dir = TempDir::new("test a", true);
         let global = TempDir::new("global", true);
 
-        let mut cfg = Config::default();
-        cfg.global_db = global.as_ref().to_path_buf();
+        let cfg = Config::test_config(&global);
 
         {
             let dfs = Dfs::new(cfg.clone()).unwrap();
@@ -325,8 +915,7 @@ mod tests {
         let root_b_dir = TempDir::new("test b", true);
         let global = TempDir::new("global", true);
 
-        let mut cfg = Config::default();
-        cfg.global_db = global.as_ref().to_path_buf();
+        let cfg = Config::test_config(&global);
 
         {
             let dfs = Dfs::new(cfg.clone()).unwrap();
@@ -342,8 +931,7 @@ mod tests {
         let root_b_dir = TempDir::new("test b", true);
         let global = TempDir::new("global", true);
 
-        let mut cfg = Config::default();
-        cfg.global_db = global.as_ref().to_path_buf();
+        let cfg = Config::test_config(&global);
 
         {
             let dfs = Dfs::new(cfg.clone()).unwrap();
@@ -359,8 +947,7 @@ mod tests {
         let root_b_dir = TempDir::new("test b", true);
         let global = TempDir::new("global", true);
 
-        let mut cfg = Config::default();
-        cfg.global_db = global.as_ref().to_path_buf();
+        let cfg = Config::test_config(&global);
 
         {
             let dfs = Dfs::new(cfg.clone()).unwrap();
@@ -378,4 +965,91 @@ mod tests {
             let _b = dfs.get_root_by_name("b").unwrap();
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn persistent_local_peer() {
+        let global = TempDir::new("global local peer", true);
+        let cfg = Config::test_config(&global);
+
+        let local = {
+            let dfs = Dfs::new(cfg.clone()).unwrap();
+            dfs.local_peer().id()
+        };
+
+        let dfs = Dfs::new(cfg).unwrap();
+        assert_eq!(dfs.local_peer().id(), local);
+        assert!(dfs.get_peers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn local_key_of_remote_peer() {
+        let global = TempDir::new("global local key of remote peer", true);
+
+        // the key of this node was imported as a peer before this node was first opened
+        let keypair = load_or_generate(&global.join(IDENTITY_FILE)).unwrap();
+        {
+            let store = Heed::new(&global).unwrap();
+            let peer = Peer::new("imported".to_string(), keypair.public());
+            store.put_peer(peer.id(), &peer, false).unwrap();
+        }
+
+        let res = Dfs::new(Config::test_config(&global));
+        assert!(matches!(res, Err(NewDfsError::KeyOfRemotePeer)));
+    }
+
+    #[test]
+    fn share_root_with_unknown_peer() {
+        let root_dir = TempDir::new("test share", true);
+        let global_a = TempDir::new("global share a", true);
+        let global_b = TempDir::new("global share b", true);
+
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+        let root = dfs_a.new_root(&root_dir, "a").unwrap();
+
+        // dfs_b's peer was never added to dfs_a
+        assert!(matches!(
+            dfs_a.share_root(&root, dfs_b.local_peer(), ShareMode::ReadWrite),
+            Err(ShareRootError::UnknownPeer)
+        ));
+        assert!(matches!(
+            dfs_a.share_root(&root, dfs_a.local_peer(), ShareMode::ReadWrite),
+            Err(ShareRootError::LocalPeer)
+        ));
+        assert_eq!(dfs_a.share_mode(root.id(), &dfs_b.peer_id()).unwrap(), None);
+    }
+
+    #[test]
+    fn invite_addresses_are_dialable() {
+        let root_dir = TempDir::new("test invite addresses", true);
+        let global = TempDir::new("global invite addresses", true);
+        let mut cfg = Config::test_config(&global);
+        cfg.listen_addresses = ["/ip4/0.0.0.0/tcp/4242", "/ip6/::/tcp/4242", "/ip4/127.0.0.1/tcp/0", "/ip4/127.0.0.1/tcp/4242"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let dfs = Dfs::new(cfg).unwrap();
+        let root = dfs.new_root(&root_dir, "a").unwrap();
+
+        let token = dfs.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap();
+        let invite = Invite::from_token(&token).unwrap();
+        assert_eq!(invite.addresses(), &["/ip4/127.0.0.1/tcp/4242".parse().unwrap()]);
+    }
+
+    #[test]
+    fn invites_for_existing_roots_are_rejected() {
+        let root_dir = TempDir::new("test invite existing root", true);
+        let global_a = TempDir::new("global invite existing a", true);
+        let global_b = TempDir::new("global invite existing b", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+        let root = dfs_a.new_root(&root_dir, "a").unwrap();
+
+        // b signs an invite for the root of a, to be let in by accepting it
+        let invite = Invite::new(dfs_b.identity(), Vec::new(), root.id(), "a".to_string(), ShareMode::ReadWrite, Duration::from_secs(60));
+        let token = invite.to_token(&dfs_b.keypair).unwrap();
+        assert!(matches!(dfs_a.accept_invite(&token), Err(AcceptInviteError::RootExists(id)) if id == root.id()));
+        assert_eq!(dfs_a.share_mode(root.id(), &dfs_b.peer_id()).unwrap(), None);
+        assert!(dfs_a.connection.get_peer_by_peer_id(&dfs_b.peer_id()).unwrap().is_none());
+    }
+}
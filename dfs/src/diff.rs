@@ -0,0 +1,354 @@
+//! Compare two states of the index of a root: the [`LocalStore`] with what is on disk
+//! ([`ConnectedRoot::changes_on_disk`]), our copy with that of a peer
+//! ([`Node::changes`](crate::network::Node::changes)), or two
+//! [snapshots](ConnectedRoot::diff_snapshots). The [`Change`]s describe how to get from the
+//! old state to the new one.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::global_store::GlobalStore;
+use crate::root::{ConnectedRoot, GetDirEntryError, UnsafePath};
+use crate::root::blocks::hash_file;
+use crate::root::dir_entry::StorableDirEntry;
+use crate::root::local_store::LocalStore;
+use crate::root::merkle::Hash;
+
+#[derive(Debug, Error)]
+pub enum DiffError<LSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] LSE),
+
+    #[error("{0}")]
+    UnsafePath(UnsafePath),
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+impl<LSE> From<GetDirEntryError<LSE>> for DiffError<LSE> {
+    fn from(e: GetDirEntryError<LSE>) -> Self {
+        match e {
+            GetDirEntryError::DbInteractionError(e) => DiffError::DbInteractionError(e),
+        }
+    }
+}
+
+/// A difference between two states of a root. Directories are never modified: changes to what
+/// is in them are changes of their own. When a directory is added, removed or moved, what is in
+/// it isn't listed separately.
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// An entry which is only in the new state.
+    Added(StorableDirEntry),
+
+    /// An entry which is only in the old state.
+    Removed(StorableDirEntry),
+
+    /// A file of which the contents differ.
+    Modified {
+        old: StorableDirEntry,
+        new: StorableDirEntry,
+    },
+
+    /// An entry which is at another path in the new state. Entries are matched up by id, and
+    /// files also by their contents, since the same file has another id at every peer. The
+    /// contents of a file matched up by id may have changed too.
+    Moved {
+        old: StorableDirEntry,
+        new: StorableDirEntry,
+    },
+
+    /// A file which became a directory, or the other way around.
+    TypeChanged {
+        old: StorableDirEntry,
+        new: StorableDirEntry,
+    },
+}
+
+impl Change {
+    /// The path of the entry in the new state, or the old one for removed entries.
+    pub fn path(&self) -> &Path {
+        self.entry().path()
+    }
+
+    /// The id of the entry in the new state, or the old one for removed entries.
+    pub fn id(&self) -> Uuid {
+        self.entry().id()
+    }
+
+    fn entry(&self) -> &StorableDirEntry {
+        match self {
+            Change::Added(new) => new,
+            Change::Removed(old) => old,
+            Change::Modified { new, .. } | Change::Moved { new, .. } | Change::TypeChanged { new, .. } => new,
+        }
+    }
+}
+
+/// The changes from the entries in `old` to those in `new`, ordered by path. Tombstones
+/// count as entries which aren't there.
+pub fn changes(old: Vec<StorableDirEntry>, new: Vec<StorableDirEntry>) -> Vec<Change> {
+    let by_path = |entries: Vec<StorableDirEntry>| entries.into_iter()
+        .filter(|e| !e.is_deleted())
+        .map(|e| (e.path().to_path_buf(), e))
+        .collect::<BTreeMap<_, _>>();
+    let mut old = by_path(old);
+    let new = by_path(new);
+
+    let mut changes = Vec::new();
+    let mut added = Vec::new();
+    for (path, new) in new {
+        match old.remove(&path) {
+            Some(old) if old.is_dir() != new.is_dir() => changes.push(Change::TypeChanged { old, new }),
+            Some(old) if old.is_file() && old.hash() != new.hash() => changes.push(Change::Modified { old, new }),
+            Some(_) => {}
+            None => added.push(new),
+        }
+    }
+    let removed: Vec<_> = old.into_values().collect();
+
+    let (moves, added, removed) = moves(added, removed);
+    changes.extend(moves);
+    changes.extend(added.into_iter().map(Change::Added));
+    changes.extend(removed.into_iter().map(Change::Removed));
+
+    let mut changes = collapse(changes);
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    changes
+}
+
+/// Match up removed and added entries: first by id, then files by their contents. Returns the
+/// moves, and the entries which are still added and removed.
+fn moves(added: Vec<StorableDirEntry>, removed: Vec<StorableDirEntry>) -> (Vec<Change>, Vec<StorableDirEntry>, Vec<StorableDirEntry>) {
+    let mut removed_by_id: HashMap<Uuid, StorableDirEntry> = removed.into_iter()
+        .map(|e| (e.id(), e))
+        .collect();
+
+    let mut moves = Vec::new();
+    let mut rest = Vec::new();
+    for new in added {
+        match removed_by_id.remove(&new.id()) {
+            Some(old) if old.is_dir() == new.is_dir() => moves.push(Change::Moved { old, new }),
+            Some(old) => {
+                removed_by_id.insert(old.id(), old);
+                rest.push(new);
+            }
+            None => rest.push(new),
+        }
+    }
+
+    // empty files all have the same contents, so they can't be told apart
+    let mut removed_by_hash: HashMap<Hash, Vec<StorableDirEntry>> = HashMap::new();
+    let mut removed = Vec::new();
+    for old in removed_by_id.into_values() {
+        if old.is_file() && old.size() > 0 {
+            removed_by_hash.entry(*old.hash()).or_default().push(old);
+        } else {
+            removed.push(old);
+        }
+    }
+
+    let mut added = Vec::new();
+    for new in rest {
+        match removed_by_hash.get_mut(new.hash()).filter(|_| new.is_file()).and_then(Vec::pop) {
+            Some(old) => moves.push(Change::Moved { old, new }),
+            None => added.push(new),
+        }
+    }
+    removed.extend(removed_by_hash.into_values().flatten());
+
+    (moves, added, removed)
+}
+
+/// Leave out the changes which follow from a change of a directory above them: entries in
+/// added, removed or replaced directories, and entries which moved along with their directory.
+fn collapse(changes: Vec<Change>) -> Vec<Change> {
+    let mut added_dirs = HashSet::new();
+    let mut removed_dirs = HashSet::new();
+    let mut moved_dirs = HashMap::new();
+    for change in &changes {
+        match change {
+            Change::Added(new) if new.is_dir() => { added_dirs.insert(new.path().to_path_buf()); }
+            Change::Removed(old) if old.is_dir() => { removed_dirs.insert(old.path().to_path_buf()); }
+            Change::TypeChanged { old, new } => {
+                if old.is_dir() {
+                    removed_dirs.insert(old.path().to_path_buf());
+                } else {
+                    added_dirs.insert(new.path().to_path_buf());
+                }
+            }
+            Change::Moved { old, new } if old.is_dir() => { moved_dirs.insert(old.path().to_path_buf(), new.path().to_path_buf()); }
+            _ => {}
+        }
+    }
+
+    let under = |dirs: &HashSet<PathBuf>, path: &Path| path.ancestors().skip(1).any(|a| dirs.contains(a));
+    changes.into_iter()
+        .filter(|change| match change {
+            Change::Added(new) => !under(&added_dirs, new.path()),
+            Change::Removed(old) => !under(&removed_dirs, old.path()),
+            Change::Moved { old, new } => !old.path().ancestors().skip(1).any(|a| {
+                matches!(moved_dirs.get(a), Some(to) if old.path().strip_prefix(a).map(|rest| to.join(rest)).ok().as_deref() == Some(new.path()))
+            }),
+            _ => true,
+        })
+        .collect()
+}
+
+impl<'dfs, GS: GlobalStore, LS: LocalStore> ConnectedRoot<'dfs, GS, LS> {
+    /// The changes made on disk since the root was last [indexed](ConnectedRoot::index), without
+    /// indexing it. Entries which aren't on disk here, like
+    /// [placeholders](crate::root::dir_entry::Presence::Placeholder), don't count as changed.
+    ///
+    /// ```rust
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// # use std::fs;
+    /// # use std::path::Path;
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use dfs::diff::Change;
+    /// # use dfs::test::populated_tempdir;
+    /// # let tempdir = populated_tempdir("changes on disk");
+    /// # let global = populated_tempdir("changes on disk global");
+    /// # let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+    /// # let root = dfs.new_root(&tempdir, "test").unwrap().connect().unwrap();
+    /// root.index().await.unwrap();
+    /// fs::rename(tempdir.join("test.txt"), tempdir.join("a/moved.txt")).unwrap();
+    ///
+    /// let changes = root.changes_on_disk().unwrap();
+    /// assert_eq!(changes.len(), 1);
+    /// assert!(matches!(&changes[0], Change::Moved { old, new } if old.path() == Path::new("/test.txt") && new.path() == Path::new("/a/moved.txt")));
+    /// # }
+    /// ```
+    pub fn changes_on_disk(&self) -> Result<Vec<Change>, DiffError<LS::Error>> {
+        let stored: Vec<_> = self.manifest(None, None)?
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| !e.is_deleted())
+            .collect();
+        let stored_by_path: HashMap<&Path, &StorableDirEntry> = stored.iter()
+            .map(|e| (e.path(), e))
+            .collect();
+
+        let mut on_disk = Vec::new();
+        let mut seen = HashSet::new();
+        let mut todo = vec![PathBuf::from("/")];
+        while let Some(dir) = todo.pop() {
+            let dir_on_disk = self.path_on_disk(&dir).map_err(DiffError::UnsafePath)?;
+            let entries = fs::read_dir(&dir_on_disk).map_err(|e| DiffError::Io(dir_on_disk.clone(), e))?;
+            for entry in entries {
+                let entry = entry.map_err(|e| DiffError::Io(dir_on_disk.clone(), e))?;
+                let disk_path = entry.path();
+                if disk_path == self.local_db_path() {
+                    continue
+                }
+
+                let path = dir.join(entry.file_name());
+                let is_dir = disk_path.is_dir();
+                let stored = stored_by_path.get(path.as_path()).filter(|s| s.is_dir() == is_dir);
+                let mut found = match stored {
+                    Some(stored) => (*stored).clone(),
+                    None => StorableDirEntry::new(Uuid::new_v4(), path.clone(), None, is_dir, Hash::default()),
+                };
+
+                if is_dir {
+                    todo.push(path.clone());
+                } else if !(found.is_placeholder() && disk_path.metadata().map(|m| m.len() == 0).unwrap_or(false)) {
+                    let hashes = hash_file(&disk_path).map_err(|e| DiffError::Io(disk_path.clone(), e))?;
+                    if &hashes.hash != found.hash() {
+                        found.set_contents(hashes);
+                    }
+                }
+
+                seen.insert(path);
+                on_disk.push(found);
+            }
+        }
+
+        // the top, and entries which aren't on disk on purpose
+        on_disk.extend(stored.iter()
+            .filter(|s| s.is_root() || (s.is_excluded() && !seen.contains(s.path())))
+            .cloned());
+
+        Ok(changes(stored, on_disk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use uuid::Uuid;
+
+    use crate::diff::{Change, changes};
+    use crate::root::blocks::FileHashes;
+    use crate::root::dir_entry::StorableDirEntry;
+    use crate::root::merkle::Hash;
+
+    fn entry(id: Uuid, path: &str, is_dir: bool, contents: &str) -> StorableDirEntry {
+        let mut entry = StorableDirEntry::new(id, PathBuf::from(path), None, is_dir, Hash::default());
+        if !is_dir {
+            entry.set_contents(FileHashes {
+                hash: *blake3::hash(contents.as_bytes()).as_bytes(),
+                blocks: Vec::new(),
+                size: contents.len() as u64,
+            });
+        }
+        entry
+    }
+
+    fn summary(changes: &[Change]) -> Vec<(&'static str, &Path)> {
+        changes.iter()
+            .map(|c| (match c {
+                Change::Added(_) => "added",
+                Change::Removed(_) => "removed",
+                Change::Modified { .. } => "modified",
+                Change::Moved { .. } => "moved",
+                Change::TypeChanged { .. } => "type changed",
+            }, c.path()))
+            .collect()
+    }
+
+    #[test]
+    fn kinds_of_changes() {
+        let ids: Vec<_> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let old = vec![
+            entry(ids[0], "/", true, ""),
+            entry(ids[1], "/docs", true, ""),
+            entry(Uuid::new_v4(), "/docs/a", false, "a"),
+            entry(Uuid::new_v4(), "/b", false, "b"),
+            entry(Uuid::new_v4(), "/c", false, "c"),
+            entry(Uuid::new_v4(), "/d", false, "d"),
+            entry(ids[2], "/old", true, ""),
+            entry(Uuid::new_v4(), "/old/e", false, "e"),
+        ];
+        let new = vec![
+            entry(ids[0], "/", true, ""),
+            // moved along with its directory
+            entry(ids[1], "/papers", true, ""),
+            entry(Uuid::new_v4(), "/papers/a", false, "a"),
+            entry(Uuid::new_v4(), "/b", false, "changed"),
+            // the same contents, but another id: moved
+            entry(Uuid::new_v4(), "/c2", false, "c"),
+            entry(Uuid::new_v4(), "/d", true, ""),
+            entry(ids[3], "/new", true, ""),
+            entry(Uuid::new_v4(), "/new/f", false, "f"),
+        ];
+
+        assert_eq!(summary(&changes(old, new)), vec![
+            ("modified", Path::new("/b")),
+            ("moved", Path::new("/c2")),
+            ("type changed", Path::new("/d")),
+            ("added", Path::new("/new")),
+            ("removed", Path::new("/old")),
+            ("moved", Path::new("/papers")),
+        ]);
+    }
+}
@@ -0,0 +1,426 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use data_encoding::BASE32_NOPAD;
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::identity::error::SigningError;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha512};
+use thiserror::Error;
+use uuid::Uuid;
+use x25519_dalek::{X25519_BASEPOINT_BYTES, x25519};
+
+use crate::peer::PeerIdentity;
+use crate::root::dir_entry::StorableDirEntry;
+use crate::root::merkle::Hash;
+
+const NONCE_LEN: usize = 24;
+
+/// Context of the key derivation for [`KeyGrant`]s, see [`blake3::derive_key`].
+const GRANT_CONTEXT: &str = "dfs 2021-10 root key grant";
+
+/// Contexts of the keys derived from a [`RootKey`], one for every use of it.
+const ENTRY_CONTEXT: &str = "dfs 2021-11 root key entries";
+const BLOCK_CONTEXT: &str = "dfs 2021-11 root key blocks";
+const ADDRESS_CONTEXT: &str = "dfs 2021-11 root key block addresses";
+const NAME_CONTEXT: &str = "dfs 2021-11 root key names";
+const NAME_NONCE_CONTEXT: &str = "dfs 2021-11 root key name nonces";
+
+#[derive(Debug, Error)]
+pub enum EncryptionError {
+    #[error("only ed25519 keys can be used for encryption")]
+    UnsupportedKey,
+
+    #[error("the public key isn't a valid curve point")]
+    InvalidKey,
+
+    #[error("failed to decrypt: the key is wrong or the data was tampered with")]
+    Decrypt,
+
+    #[error("sealed name isn't valid base32: {0}")]
+    Encoding(#[from] data_encoding::DecodeError),
+
+    #[error("{0:?} isn't a single file name")]
+    InvalidName(String),
+
+    #[error("key grant or sealed entry is malformed: {0}")]
+    Bincode(#[from] bincode::Error),
+
+    #[error("failed to sign key grant: {0}")]
+    Signing(#[from] SigningError),
+
+    #[error("the signature of the key grant doesn't match the sender")]
+    InvalidSignature,
+}
+
+/// The symmetric key with which the contents and names of an encrypted root are sealed. Every
+/// peer which may read the root has it, see [`Dfs::encrypt_root`](crate::Dfs::encrypt_root).
+/// Peers with which the root is shared [encrypted](crate::root::share::ShareMode::Encrypted)
+/// only get sealed data, which they can store and forward, but not read.
+///
+/// ```
+/// # use std::path::Path;
+/// # use dfs::encryption::RootKey;
+/// # use uuid::Uuid;
+/// let key = RootKey::generate();
+/// let root = Uuid::new_v4();
+/// let hash = [1; 32];
+///
+/// let sealed = key.seal_block(root, &hash, b"secret");
+/// assert_eq!(key.open_block(root, &hash, &sealed).unwrap(), b"secret");
+/// assert!(RootKey::generate().open_block(root, &hash, &sealed).is_err());
+/// // a block is bound to its root and hash
+/// assert!(key.open_block(root, &[2; 32], &sealed).is_err());
+///
+/// let path = key.seal_path(Path::new("/taxes/2021.pdf"));
+/// assert!(!path.to_string_lossy().contains("taxes"));
+/// assert_eq!(key.open_path(&path).unwrap(), Path::new("/taxes/2021.pdf"));
+/// ```
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct RootKey([u8; 32]);
+
+impl fmt::Debug for RootKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RootKey(..)")
+    }
+}
+
+impl RootKey {
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&Key::from(self.0))
+    }
+
+    /// The key for one use of this key, so the same key is never used for two purposes.
+    fn subkey(&self, context: &str) -> RootKey {
+        RootKey(blake3::derive_key(context, &self.0))
+    }
+
+    /// Encrypt and authenticate `data`, and `aad` with it, which isn't part of the result.
+    fn seal(&self, nonce: [u8; NONCE_LEN], data: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut sealed = nonce.to_vec();
+        sealed.extend(self.cipher().encrypt(&XNonce::from(nonce), Payload { msg: data, aad }).expect("encrypting in memory can't fail"));
+        sealed
+    }
+
+    fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        if sealed.len() < NONCE_LEN {
+            return Err(EncryptionError::Decrypt)
+        }
+
+        let (nonce, data) = sealed.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at the nonce length");
+        self.cipher().decrypt(&XNonce::from(nonce), Payload { msg: data, aad }).map_err(|_| EncryptionError::Decrypt)
+    }
+
+    /// Encrypt the block with hash `hash` of a file in root `root`. The block is authenticated
+    /// as well, together with the root and the hash: tampering, or handing out another block, is
+    /// detected when it's [opened](RootKey::open_block).
+    pub fn seal_block(&self, root: Uuid, hash: &Hash, block: &[u8]) -> Vec<u8> {
+        self.subkey(BLOCK_CONTEXT).seal(rand::random(), block, &block_aad(root, hash))
+    }
+
+    pub fn open_block(&self, root: Uuid, hash: &Hash, sealed: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.subkey(BLOCK_CONTEXT).open(sealed, &block_aad(root, hash))
+    }
+
+    /// The address of the block with hash `hash`, under which peers which only have the root
+    /// encrypted know it. It's a keyed hash, so they can tell blocks apart, but can't find out
+    /// whether a file they know the contents of is in the root.
+    pub fn block_address(&self, hash: &Hash) -> Hash {
+        *blake3::keyed_hash(&self.subkey(ADDRESS_CONTEXT).0, hash).as_bytes()
+    }
+
+    /// Encrypt a file name. Unlike blocks, names are sealed deterministically, so the same name
+    /// is always sealed the same way and peers without the key can still tell entries apart.
+    pub fn seal_name(&self, name: &str) -> String {
+        let nonce = blake3::keyed_hash(&self.subkey(NAME_NONCE_CONTEXT).0, name.as_bytes());
+        let nonce = nonce.as_bytes()[..NONCE_LEN].try_into().expect("hash is longer than a nonce");
+        BASE32_NOPAD.encode(&self.subkey(NAME_CONTEXT).seal(nonce, name.as_bytes(), &[]))
+    }
+
+    /// Open a name sealed with [`seal_name`](RootKey::seal_name). Fails unless it opens to a single
+    /// file name, so `..`, `.` or names with separators are never opened.
+    pub fn open_name(&self, sealed: &str) -> Result<String, EncryptionError> {
+        let name = self.subkey(NAME_CONTEXT).open(&BASE32_NOPAD.decode(sealed.as_bytes())?, &[])?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+
+        let mut components = Path::new(&name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(normal)), None) if normal == name.as_str() => Ok(name),
+            _ => Err(EncryptionError::InvalidName(name)),
+        }
+    }
+
+    /// Seal every name in a root relative path.
+    pub fn seal_path(&self, path: &Path) -> PathBuf {
+        path.components()
+            .map(|c| match c {
+                Component::Normal(name) => self.seal_name(&name.to_string_lossy()).into(),
+                c => c.as_os_str().to_os_string(),
+            })
+            .collect()
+    }
+
+    /// Open every name in a path sealed with [`seal_path`](RootKey::seal_path). Besides the names,
+    /// the path may only have a leading `/`.
+    pub fn open_path(&self, path: &Path) -> Result<PathBuf, EncryptionError> {
+        path.components()
+            .map(|c| match c {
+                Component::Normal(name) => self.open_name(&name.to_string_lossy()).map(Into::into),
+                Component::RootDir => Ok(c.as_os_str().to_os_string()),
+                c => Err(EncryptionError::InvalidName(c.as_os_str().to_string_lossy().into_owned())),
+            })
+            .collect()
+    }
+
+    /// The entry of root `root` as it's sent to peers which may only have the root encrypted:
+    /// sealed as a whole, bound to the root and to the id of the entry. Only the addresses of
+    /// the blocks of files are left, so the peers can fetch and keep them.
+    pub(crate) fn seal_entry(&self, root: Uuid, entry: &StorableDirEntry) -> SealedEntry {
+        let plain = bincode::serialize(entry).expect("serializing an entry can't fail");
+        let blocks = if entry.is_file() && !entry.is_deleted() {
+            entry.blocks().iter().map(|hash| self.block_address(hash)).collect()
+        } else {
+            Vec::new()
+        };
+
+        SealedEntry {
+            id: entry.id(),
+            blocks,
+            sealed: self.subkey(ENTRY_CONTEXT).seal(rand::random(), &plain, &entry_aad(root, entry.id())),
+        }
+    }
+
+    /// Open an entry sealed with [`seal_entry`](RootKey::seal_entry). Fails when it was sealed for
+    /// another root, or under another id.
+    pub(crate) fn open_entry(&self, root: Uuid, sealed: &SealedEntry) -> Result<StorableDirEntry, EncryptionError> {
+        let plain = self.subkey(ENTRY_CONTEXT).open(&sealed.sealed, &entry_aad(root, sealed.id))?;
+        let entry: StorableDirEntry = bincode::deserialize(&plain)?;
+        if entry.id() != sealed.id {
+            return Err(EncryptionError::Decrypt)
+        }
+
+        Ok(entry)
+    }
+
+    /// Hand this key to the peer with public key `recipient`, see [`KeyGrant`].
+    pub(crate) fn grant(&self, root: Uuid, sender: &Keypair, identity: PeerIdentity, recipient: &PublicKey) -> Result<String, EncryptionError> {
+        let recipient = montgomery(recipient)?;
+        let ephemeral: [u8; 32] = rand::random();
+        let ephemeral_public = x25519(ephemeral, X25519_BASEPOINT_BYTES);
+        let wrapping = RootKey(grant_key(root, x25519(ephemeral, recipient), ephemeral_public, recipient));
+
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let key = wrapping.cipher()
+            .encrypt(&XNonce::from(nonce), Payload { msg: &self.0, aad: root.as_bytes() })
+            .expect("encrypting in memory can't fail");
+
+        let grant = bincode::serialize(&KeyGrant {
+            root,
+            sender: identity,
+            ephemeral: ephemeral_public,
+            nonce,
+            key,
+        })?;
+        let signature = sender.sign(&grant)?;
+
+        Ok(BASE32_NOPAD.encode(&bincode::serialize(&SignedGrant { grant, signature })?))
+    }
+}
+
+/// An entry of an encrypted root, as peers which only have the root encrypted get and keep it,
+/// see [`SealedRoot`](crate::network::sealed::SealedRoot). All they can see of it is its id,
+/// and for files the [addresses](RootKey::block_address) of their blocks.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SealedEntry {
+    id: Uuid,
+    blocks: Vec<Hash>,
+    sealed: Vec<u8>,
+}
+
+impl SealedEntry {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The addresses of the blocks of the file, empty for directories and deleted entries.
+    pub fn blocks(&self) -> &[Hash] {
+        &self.blocks
+    }
+
+    /// The sealed entry, which can only be read with the key of the root.
+    pub fn sealed(&self) -> &[u8] {
+        &self.sealed
+    }
+}
+
+/// The key of a root, wrapped for a single peer: only the peer it was created for can open it.
+/// A grant is created with [`Dfs::grant_root_key`](crate::Dfs::grant_root_key) and handed to
+/// the peer as a token, like an [`Invite`](crate::peer::invite::Invite). It is signed by the
+/// peer which created it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyGrant {
+    root: Uuid,
+    sender: PeerIdentity,
+    /// public half of the x25519 key the sender generated for this grant
+    ephemeral: [u8; 32],
+    nonce: [u8; NONCE_LEN],
+    /// the root key, sealed with the key both sides derive
+    key: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedGrant {
+    grant: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl KeyGrant {
+    /// Decode a token created with [`Dfs::grant_root_key`](crate::Dfs::grant_root_key), and
+    /// verify its signature.
+    pub fn from_token(token: &str) -> Result<Self, EncryptionError> {
+        let bytes = BASE32_NOPAD.decode(token.trim().as_bytes())?;
+        let signed: SignedGrant = bincode::deserialize(&bytes)?;
+        let grant: KeyGrant = bincode::deserialize(&signed.grant)?;
+
+        if !grant.sender.public_key().verify(&signed.grant, &signed.signature) {
+            return Err(EncryptionError::InvalidSignature)
+        }
+
+        Ok(grant)
+    }
+
+    pub fn root(&self) -> Uuid {
+        self.root
+    }
+
+    /// The peer which created the grant.
+    pub fn sender(&self) -> &PeerIdentity {
+        &self.sender
+    }
+
+    /// Unwrap the key with the keypair of the peer the grant was created for.
+    pub(crate) fn open(&self, keypair: &Keypair) -> Result<RootKey, EncryptionError> {
+        let secret = match keypair {
+            Keypair::Ed25519(keypair) => {
+                // the x25519 secret belonging to an ed25519 key, as in RFC 8032
+                let hash = Sha512::digest(keypair.secret().as_ref());
+                let mut secret = [0; 32];
+                secret.copy_from_slice(&hash[..32]);
+                secret
+            }
+            #[allow(unreachable_patterns)]
+            _ => return Err(EncryptionError::UnsupportedKey),
+        };
+        let recipient = montgomery(&keypair.public())?;
+        let wrapping = RootKey(grant_key(self.root, x25519(secret, self.ephemeral), self.ephemeral, recipient));
+
+        let key = wrapping.cipher()
+            .decrypt(&XNonce::from(self.nonce), Payload { msg: &self.key, aad: self.root.as_bytes() })
+            .map_err(|_| EncryptionError::Decrypt)?;
+
+        Ok(RootKey(key.try_into().map_err(|_| EncryptionError::Decrypt)?))
+    }
+}
+
+/// The x25519 public key belonging to an ed25519 public key.
+fn montgomery(key: &PublicKey) -> Result<[u8; 32], EncryptionError> {
+    match key {
+        PublicKey::Ed25519(key) => CompressedEdwardsY(key.encode())
+            .decompress()
+            .map(|point| point.to_montgomery().to_bytes())
+            .ok_or(EncryptionError::InvalidKey),
+        #[allow(unreachable_patterns)]
+        _ => Err(EncryptionError::UnsupportedKey),
+    }
+}
+
+fn entry_aad(root: Uuid, entry: Uuid) -> Vec<u8> {
+    let mut aad = root.as_bytes().to_vec();
+    aad.extend_from_slice(entry.as_bytes());
+    aad
+}
+
+fn block_aad(root: Uuid, hash: &Hash) -> Vec<u8> {
+    let mut aad = root.as_bytes().to_vec();
+    aad.extend_from_slice(hash);
+    aad
+}
+
+fn grant_key(root: Uuid, shared: [u8; 32], ephemeral: [u8; 32], recipient: [u8; 32]) -> [u8; 32] {
+    let mut material = shared.to_vec();
+    material.extend_from_slice(&ephemeral);
+    material.extend_from_slice(&recipient);
+    material.extend_from_slice(root.as_bytes());
+    blake3::derive_key(GRANT_CONTEXT, &material)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use libp2p::identity::Keypair;
+    use uuid::Uuid;
+
+    use crate::encryption::{EncryptionError, KeyGrant, RootKey};
+    use crate::peer::PeerIdentity;
+    use crate::root::dir_entry::StorableDirEntry;
+
+    #[test]
+    fn grants() {
+        let sender = Keypair::generate_ed25519();
+        let recipient = Keypair::generate_ed25519();
+        let identity = PeerIdentity::new("sender".to_string(), sender.public());
+        let root = Uuid::new_v4();
+        let key = RootKey::generate();
+
+        let token = key.grant(root, &sender, identity, &recipient.public()).unwrap();
+        let grant = KeyGrant::from_token(&token).unwrap();
+        assert_eq!(grant.root(), root);
+        assert_eq!(grant.sender().peer_id(), sender.public().into_peer_id());
+        assert_eq!(grant.open(&recipient).unwrap(), key);
+
+        // nobody else can open it
+        let other = Keypair::generate_ed25519();
+        assert!(matches!(grant.open(&other), Err(EncryptionError::Decrypt)));
+
+        // names are sealed the same way every time
+        assert_eq!(key.seal_name("notes"), key.seal_name("notes"));
+        assert_ne!(key.seal_name("notes"), RootKey::generate().seal_name("notes"));
+    }
+
+    #[test]
+    fn sealing() {
+        let key = RootKey::generate();
+        let root = Uuid::new_v4();
+
+        // only single file names are opened
+        for name in ["..", ".", "a/b", ""] {
+            assert!(matches!(key.open_name(&key.seal_name(name)), Err(EncryptionError::InvalidName(_))));
+        }
+        assert!(key.open_path(Path::new("/../x")).is_err());
+
+        // entries are bound to their root and id
+        let entry = StorableDirEntry::new(Uuid::new_v4(), "/taxes".into(), Some(root), true, [1; 32]);
+        let sealed = key.seal_entry(root, &entry);
+        let opened = key.open_entry(root, &sealed).unwrap();
+        assert_eq!((opened.id(), opened.path()), (entry.id(), entry.path()));
+        assert!(key.open_entry(Uuid::new_v4(), &sealed).is_err());
+        let mut moved = sealed.clone();
+        moved.id = Uuid::new_v4();
+        assert!(key.open_entry(root, &moved).is_err());
+        assert!(!sealed.sealed().windows(5).any(|w| w == b"taxes"));
+
+        // block addresses are keyed
+        assert_ne!(key.block_address(&[1; 32]), [1; 32]);
+        assert_ne!(key.block_address(&[1; 32]), RootKey::generate().block_address(&[1; 32]));
+    }
+}
@@ -0,0 +1,392 @@
+//! Mount a root as a read-only FUSE filesystem, on Linux with the `fuse` feature. The filesystem
+//! is served through the [`Vfs`] of the root: directories list the entries in the [`LocalStore`],
+//! attributes come from the stored metadata and contents from the files on disk. Files which
+//! aren't on disk, like [placeholders](crate::root::dir_entry::Presence::Placeholder), can't be
+//! read through the mount.
+//!
+//! The kernel is spoken to with [`fuser`]. Mounting goes through the `fusermount` binary of FUSE
+//! when the process isn't allowed to mount filesystems itself.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use fuser::{FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, Request, Session, SessionUnmounter, FUSE_ROOT_ID};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::global_store::GlobalStore;
+use crate::root::ConnectedRoot;
+use crate::root::dir_entry::StorableDirEntry;
+use crate::root::local_store::LocalStore;
+use crate::root::vfs::{FileHandle, Vfs, VfsError};
+
+/// How long the kernel may cache entries and attributes. The index can change while the root
+/// is mounted.
+const TTL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Error)]
+pub enum FuseError {
+    #[error("failed to mount at {0:?}: {1}")]
+    Mount(PathBuf, io::Error),
+
+    #[error("failed to talk to the kernel: {0}")]
+    Io(io::Error),
+}
+
+/// A root mounted with FUSE. The kernel's requests are answered while the mount is
+/// [served](Mount::serve), until it is unmounted, for example with an
+/// [unmounter](Mount::unmounter) or `fusermount -u`. It is unmounted when the mount is dropped
+/// as well.
+///
+/// ```no_run
+/// # use dfs::config::Config;
+/// # use dfs::Dfs;
+/// # use dfs::fuse::Mount;
+/// # use temp_testdir::TempDir;
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// # let tempdir = TempDir::new("test", true);
+/// # let dfs = Dfs::new(Config::test_config(&tempdir)).unwrap();
+/// # let root_dir = TempDir::new("test root", true);
+/// let root = dfs.new_root(&root_dir, "photos").unwrap().connect().unwrap();
+/// root.index().await.unwrap();
+///
+/// // serves the root until `fusermount -u /mnt/photos` is run, or it's unmounted otherwise
+/// Mount::new(&root, "/mnt/photos").unwrap().serve().unwrap();
+/// # }
+/// ```
+pub struct Mount<'a, 'dfs, GS: GlobalStore, LS: LocalStore> {
+    session: Session<RootFs<'a, 'dfs, GS, LS>>,
+}
+
+impl<'a, 'dfs, GS: GlobalStore, LS: LocalStore> Mount<'a, 'dfs, GS, LS> {
+    /// Mount `root` read-only at `mountpoint`, which is an existing directory.
+    pub fn new(root: &'a ConnectedRoot<'dfs, GS, LS>, mountpoint: impl AsRef<Path>) -> Result<Self, FuseError> {
+        let mountpoint = mountpoint.as_ref();
+        let options = [
+            MountOption::FSName("dfs".to_string()),
+            MountOption::Subtype("dfs".to_string()),
+            MountOption::RO,
+            MountOption::NoSuid,
+            MountOption::NoDev,
+            MountOption::DefaultPermissions,
+        ];
+        let session = Session::new(RootFs::new(root), mountpoint, &options)
+            .map_err(|e| FuseError::Mount(mountpoint.to_path_buf(), e))?;
+
+        Ok(Self {
+            session,
+        })
+    }
+
+    pub fn mountpoint(&self) -> &Path {
+        self.session.mountpoint()
+    }
+
+    /// Something to unmount the filesystem with from another thread, after which
+    /// [`serve`](Mount::serve) returns. Files which are still open keep working until they are
+    /// closed.
+    pub fn unmounter(&mut self) -> SessionUnmounter {
+        self.session.unmount_callable()
+    }
+
+    /// Answer the requests of the kernel with the entries and contents of the root. Returns once
+    /// the filesystem is unmounted.
+    pub fn serve(mut self) -> Result<(), FuseError> {
+        self.session.run().map_err(FuseError::Io)
+    }
+}
+
+/// The filesystem of a mounted root: which inode the kernel knows each entry by, and the open
+/// files.
+struct RootFs<'a, 'dfs, GS, LS> {
+    root: &'a ConnectedRoot<'dfs, GS, LS>,
+
+    /// The entries the kernel knows, by inode. Inodes are never reused.
+    nodes: HashMap<u64, Uuid>,
+    node_ids: HashMap<Uuid, u64>,
+
+    files: HashMap<u64, FileHandle>,
+    next_file: u64,
+
+    uid: u32,
+    gid: u32,
+    /// the times of all entries, since they aren't stored
+    mounted: SystemTime,
+}
+
+impl<'a, 'dfs, GS: GlobalStore, LS: LocalStore> RootFs<'a, 'dfs, GS, LS> {
+    fn new(root: &'a ConnectedRoot<'dfs, GS, LS>) -> Self {
+        // safe, these can't fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+        Self {
+            root,
+            nodes: vec![(FUSE_ROOT_ID, root.id())].into_iter().collect(),
+            node_ids: vec![(root.id(), FUSE_ROOT_ID)].into_iter().collect(),
+            files: HashMap::new(),
+            next_file: 1,
+            uid,
+            gid,
+            mounted: SystemTime::now(),
+        }
+    }
+
+    /// The id of the entry the kernel knows by inode `node`.
+    fn entry_id(&self, node: u64) -> Result<Uuid, i32> {
+        self.nodes.get(&node).copied().ok_or(libc::ENOENT)
+    }
+
+    /// The inode of the entry with id `id`, which is assigned the first time the kernel sees it.
+    fn node_id(&mut self, id: Uuid) -> u64 {
+        if let Some(node) = self.node_ids.get(&id) {
+            return *node
+        }
+
+        let node = self.nodes.len() as u64 + FUSE_ROOT_ID;
+        self.nodes.insert(node, id);
+        self.node_ids.insert(id, node);
+        node
+    }
+
+    fn attr(&self, node: u64, entry: &StorableDirEntry) -> FileAttr {
+        let (kind, perm, nlink) = if entry.is_dir() {
+            (FileType::Directory, 0o555, 2)
+        } else {
+            (FileType::RegularFile, 0o444, 1)
+        };
+
+        FileAttr {
+            ino: node,
+            size: entry.size(),
+            // blocks of 512 bytes
+            blocks: entry.size().div_ceil(512),
+            atime: self.mounted,
+            mtime: self.mounted,
+            ctime: self.mounted,
+            crtime: self.mounted,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    fn lookup_entry(&mut self, parent: u64, name: &OsStr) -> Result<(u64, StorableDirEntry), i32> {
+        let entry = self.root.lookup(self.entry_id(parent)?, name)
+            .map_err(errno)?
+            .ok_or(libc::ENOENT)?;
+        Ok((self.node_id(entry.id()), entry))
+    }
+
+    fn getattr_entry(&self, node: u64) -> Result<StorableDirEntry, i32> {
+        self.root.getattr(self.entry_id(node)?).map_err(errno)
+    }
+
+    fn open_file(&mut self, node: u64, flags: i32) -> Result<u64, i32> {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EROFS)
+        }
+
+        let file = self.root.open(self.entry_id(node)?, false).map_err(errno)?;
+        let handle = self.next_file;
+        self.next_file += 1;
+        self.files.insert(handle, file);
+
+        Ok(handle)
+    }
+
+    fn read_file(&mut self, handle: u64, offset: i64, size: u32) -> Result<Vec<u8>, i32> {
+        let offset = u64::try_from(offset).map_err(|_| libc::EINVAL)?;
+        let root = self.root;
+        let file = self.files.get_mut(&handle).ok_or(libc::EBADF)?;
+
+        // the kernel expects the whole range, unless the file ends before
+        let mut buf = vec![0; size as usize];
+        let mut filled = 0;
+        while filled < buf.len() {
+            match root.read(file, offset + filled as u64, &mut buf[filled..]).map_err(errno)? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        buf.truncate(filled);
+
+        Ok(buf)
+    }
+
+    /// The listing of a directory: `.`, `..` and then the entries of [`Vfs::readdir`], which
+    /// are ordered by id, so the offsets stay the same as long as the directory doesn't change.
+    fn listing(&mut self, node: u64) -> Result<Vec<(u64, FileType, OsString)>, i32> {
+        let dir = self.getattr_entry(node)?;
+        let children = self.root.readdir(dir.id()).map_err(errno)?;
+        let parent = match dir.parent_id() {
+            Some(parent) => self.node_id(parent),
+            None => node,
+        };
+
+        let mut listing = vec![
+            (node, FileType::Directory, OsString::from(".")),
+            (parent, FileType::Directory, OsString::from("..")),
+        ];
+        for child in &children {
+            let kind = if child.is_dir() { FileType::Directory } else { FileType::RegularFile };
+            listing.push((self.node_id(child.id()), kind, child.name().to_os_string()));
+        }
+
+        Ok(listing)
+    }
+}
+
+impl<GS: GlobalStore, LS: LocalStore> Filesystem for RootFs<'_, '_, GS, LS> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_entry(parent, name) {
+            Ok((node, entry)) => reply.entry(&TTL, &self.attr(node, &entry), 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, node: u64, reply: ReplyAttr) {
+        match self.getattr_entry(node) {
+            Ok(entry) => reply.attr(&TTL, &self.attr(node, &entry)),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, node: u64, flags: i32, reply: ReplyOpen) {
+        match self.open_file(node, flags) {
+            Ok(handle) => reply.opened(handle, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn read(&mut self, _req: &Request<'_>, _node: u64, handle: u64, offset: i64, size: u32, _flags: i32, _lock_owner: Option<u64>, reply: ReplyData) {
+        match self.read_file(handle, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn release(&mut self, _req: &Request<'_>, _node: u64, handle: u64, _flags: i32, _lock_owner: Option<u64>, _flush: bool, reply: ReplyEmpty) {
+        match self.files.remove(&handle).map(|file| self.root.release(file)) {
+            Some(Err(e)) => reply.error(errno(e)),
+            _ => reply.ok(),
+        }
+    }
+
+    fn opendir(&mut self, _req: &Request<'_>, node: u64, _flags: i32, reply: ReplyOpen) {
+        // directories are listed from the store every time, so they need no handle
+        match self.getattr_entry(node) {
+            Ok(entry) if entry.is_dir() => reply.opened(0, 0),
+            Ok(_) => reply.error(libc::ENOTDIR),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, node: u64, _handle: u64, offset: i64, mut reply: ReplyDirectory) {
+        let listing = match self.listing(node) {
+            Ok(listing) => listing,
+            Err(errno) => return reply.error(errno),
+        };
+
+        for (index, (node, kind, name)) in listing.into_iter().enumerate().skip(offset.max(0) as usize) {
+            // the offset of the next entry, stop when the reply is full
+            if reply.add(node, index as i64 + 1, kind, name) {
+                break
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// The errno the kernel is answered with when `e` happens.
+fn errno<LSE: std::fmt::Debug>(e: VfsError<LSE>) -> i32 {
+    match e {
+        VfsError::NotFound(_) | VfsError::NoSuchName(_) => libc::ENOENT,
+        VfsError::Exists(_) => libc::EEXIST,
+        VfsError::NotADirectory(_) => libc::ENOTDIR,
+        VfsError::IsADirectory(_) => libc::EISDIR,
+        VfsError::InvalidName(_) => libc::EINVAL,
+        VfsError::ReadOnly => libc::EBADF,
+        VfsError::NotLocal(_) => libc::EIO,
+        VfsError::Io(_, e) => e.raw_os_error().unwrap_or(libc::EIO),
+        VfsError::DbInteractionError(e) => {
+            log::error!("failed to answer the kernel: {:?}", e);
+            libc::EIO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::panic;
+    use std::thread;
+
+    use temp_testdir::TempDir;
+
+    use crate::config::Config;
+    use crate::Dfs;
+    use crate::fuse::{FuseError, Mount};
+    use crate::test::populated_tempdir;
+
+    #[tokio::test]
+    async fn mount() {
+        let root_dir = populated_tempdir("fuse root");
+        let global = TempDir::new("global fuse", true);
+        let mountpoint = TempDir::new("fuse mountpoint", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+
+        let root = dfs.new_root(&root_dir, "a").unwrap().connect().unwrap();
+        root.index().await.unwrap();
+
+        let mut mount = match Mount::new(&root, &mountpoint) {
+            Ok(mount) => mount,
+            // without permission to mount, and without fusermount to do it instead
+            Err(FuseError::Mount(_, e)) if matches!(e.kind(), io::ErrorKind::PermissionDenied | io::ErrorKind::NotFound) => {
+                eprintln!("skipping, can't mount: {}", e);
+                return
+            }
+            Err(e) => panic!("{}", e),
+        };
+        let mut unmounter = mount.unmounter();
+        let path = mountpoint.to_path_buf();
+        let reader = thread::spawn(move || {
+            let res = panic::catch_unwind(|| {
+                let mut names: Vec<_> = fs::read_dir(&path).unwrap()
+                    .map(|e| e.unwrap().file_name().into_string().unwrap())
+                    .collect();
+                names.sort();
+
+                let is_dir = fs::metadata(path.join("a")).unwrap().is_dir();
+                let test = fs::read_to_string(path.join("test.txt")).unwrap();
+                let ipsum = fs::read_to_string(path.join("a/ipsum.txt")).unwrap();
+                let missing = fs::metadata(path.join("missing")).is_err();
+                let read_only = fs::write(path.join("new"), "new").is_err();
+                (names, is_dir, test, ipsum, missing, read_only)
+            });
+            unmounter.unmount().unwrap();
+            res
+        });
+
+        mount.serve().unwrap();
+        let (names, is_dir, test, ipsum, missing, read_only) = reader.join().unwrap().unwrap();
+
+        // the local db of the root isn't part of it
+        assert_eq!(names, vec!["a", "test.txt"]);
+        assert!(is_dir);
+        assert_eq!(test, fs::read_to_string(root_dir.join("test.txt")).unwrap());
+        assert_eq!(ipsum, fs::read_to_string(root_dir.join("a/ipsum.txt")).unwrap());
+        assert!(missing);
+        assert!(read_only);
+    }
+}
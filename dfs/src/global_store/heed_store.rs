@@ -1,21 +1,43 @@
 use std::path::Path;
 
-use heed::{Database, Env, EnvOpenOptions};
+use heed::{Database, Env, EnvOpenOptions, RwTxn};
 use heed::types::SerdeBincode;
+use libp2p::PeerId;
 use uuid::Uuid;
 
+use crate::compression::Compression;
+use crate::encryption::RootKey;
 use crate::global_store::{GlobalStore, PutStatus};
 use crate::peer::Peer;
+use crate::peer::invite::Invite;
 use crate::root::StorableRoot;
+use crate::root::merkle::Hash;
+use crate::root::selection::Selection;
+use crate::root::share::ShareMode;
 
 /// GlobalStore implementation using the Heed key-value store.
 pub struct Heed {
     env: Env,
     peers: Database<SerdeBincode<Uuid>, SerdeBincode<Peer>>,
+    peer_ids: Database<SerdeBincode<Vec<u8>>, SerdeBincode<Uuid>>,
     roots: Database<SerdeBincode<Uuid>, SerdeBincode<StorableRoot>>,
     root_names: Database<SerdeBincode<String>, SerdeBincode<Uuid>>,
+    meta: Database<SerdeBincode<String>, SerdeBincode<Uuid>>,
+    /// (root, peer) -> mode
+    root_members: Database<SerdeBincode<(Uuid, Uuid)>, SerdeBincode<ShareMode>>,
+    invites: Database<SerdeBincode<Uuid>, SerdeBincode<Invite>>,
+    /// (root, peer) -> selection
+    selections: Database<SerdeBincode<(Uuid, Uuid)>, SerdeBincode<Selection>>,
+    /// root -> how its blocks are compressed
+    compression: Database<SerdeBincode<Uuid>, SerdeBincode<Compression>>,
+    /// root -> the key of the root, for encrypted roots
+    root_keys: Database<SerdeBincode<Uuid>, SerdeBincode<RootKey>>,
+    /// block hash -> how often the block is used
+    block_refs: Database<SerdeBincode<Hash>, SerdeBincode<u64>>,
 }
 
+const LOCAL_PEER_KEY: &str = "local_peer";
+
 impl GlobalStore for Heed {
     type Error = heed::Error;
 
@@ -31,14 +53,22 @@ impl GlobalStore for Heed {
     /// ```
     fn new(path: &Path) -> Result<Self, Self::Error> {
         let env = EnvOpenOptions::new()
-            .max_dbs(3)
+            .max_dbs(11)
             .open(path)?;
 
 
         Ok(Self {
             peers: env.create_database(Some("peers"))?,
+            peer_ids: env.create_database(Some("peer_ids"))?,
             roots: env.create_database(Some("roots"))?,
             root_names: env.create_database(Some("roots_names"))?,
+            meta: env.create_database(Some("meta"))?,
+            root_members: env.create_database(Some("root_members"))?,
+            invites: env.create_database(Some("invites"))?,
+            selections: env.create_database(Some("selections"))?,
+            compression: env.create_database(Some("compression"))?,
+            root_keys: env.create_database(Some("root_keys"))?,
+            block_refs: env.create_database(Some("block_refs"))?,
             env,
         })
     }
@@ -49,12 +79,13 @@ impl GlobalStore for Heed {
     /// # use temp_testdir::TempDir;
     /// # use dfs::global_store::heed_store::Heed;
     /// # use dfs::peer::Peer;
+    /// # use libp2p::identity::Keypair;
     /// use dfs::global_store::GlobalStore;
     ///
     /// let tempdir = TempDir::new("test", true);
     /// let store = Heed::new(&tempdir).unwrap();
     ///
-    /// let peer = Peer::new("jonathan".to_string());
+    /// let peer = Peer::new("jonathan".to_string(), Keypair::generate_ed25519().public());
     ///
     /// assert!(store.put_peer(peer.id(), &peer, false).is_ok());
     /// ```
@@ -68,29 +99,46 @@ impl GlobalStore for Heed {
     /// # use dfs::global_store::heed_store::Heed;
     /// # use dfs::global_store::GlobalStore;
     /// # use dfs::peer::Peer;
+    /// # use libp2p::identity::Keypair;
     /// use dfs::global_store::PutStatus;
     ///
     /// # let tempdir = TempDir::new("test", true);
     /// # let store = Heed::new(&tempdir).unwrap();
     ///
-    /// let peer = Peer::new("jonathan".to_string());
+    /// let peer = Peer::new("jonathan".to_string(), Keypair::generate_ed25519().public());
     /// assert_eq!(store.put_peer(peer.id(), &peer, false).unwrap(), PutStatus::Ok);
     /// assert_eq!(store.put_peer(peer.id(), &peer, false).unwrap(), PutStatus::Exists);
     /// assert_eq!(store.put_peer(peer.id(), &peer, true).unwrap(), PutStatus::Ok);
     /// ```
     ///
+    /// Peers are also unique by their [`PeerId`](libp2p::PeerId). Adding the same
+    /// public key twice under a different uuid results in [`Exists`] too.
+    ///
+    /// When an overwrite changes the key of a peer, it can no longer be found by its old [`PeerId`](libp2p::PeerId).
+    ///
+    /// ```
+    /// # use temp_testdir::TempDir;
+    /// # use dfs::global_store::heed_store::Heed;
+    /// # use dfs::global_store::GlobalStore;
+    /// # use dfs::peer::Peer;
+    /// # use libp2p::identity::Keypair;
+    /// # let tempdir = TempDir::new("test", true);
+    /// # let store = Heed::new(&tempdir).unwrap();
+    /// let peer = Peer::new("jonathan".to_string(), Keypair::generate_ed25519().public());
+    /// store.put_peer(peer.id(), &peer, false).unwrap();
+    ///
+    /// let rekeyed = Peer::new("jonathan".to_string(), Keypair::generate_ed25519().public());
+    /// store.put_peer(peer.id(), &rekeyed, true).unwrap();
+    ///
+    /// assert!(store.get_peer_by_peer_id(&peer.peer_id()).unwrap().is_none());
+    /// assert!(store.get_peer_by_peer_id(&rekeyed.peer_id()).unwrap().is_some());
+    /// ```
     fn put_peer(&self, id: Uuid, peer: &Peer, overwrite: bool) -> Result<PutStatus, Self::Error> {
-
         let mut txn = self.env.write_txn()?;
-
-        if !overwrite && self.peers.get(&txn, &id)?.is_some() {
-            return Ok(PutStatus::Exists)
-        }
-
-        self.peers.put(&mut txn, &id, peer)?;
+        let status = self.put_peer_in(&mut txn, id, peer, overwrite)?;
         txn.commit()?;
 
-        Ok(PutStatus::Ok)
+        Ok(status)
     }
 
     fn get_peer(&self, id: Uuid) -> Result<Option<Peer>, Self::Error> {
@@ -99,6 +147,15 @@ impl GlobalStore for Heed {
         Ok(res)
     }
 
+    fn get_peer_by_peer_id(&self, peer_id: &PeerId) -> Result<Option<Peer>, Self::Error> {
+        let txn = self.env.read_txn()?;
+        if let Some(id) = self.peer_ids.get(&txn, &peer_id.to_bytes())? {
+            Ok(self.peers.get(&txn, &id)?)
+        } else {
+            Ok(None)
+        }
+    }
+
     fn get_all_peers(&self) -> Result<Vec<Peer>, Self::Error> {
         let txn = self.env.read_txn()?;
 
@@ -108,6 +165,28 @@ impl GlobalStore for Heed {
         Ok(peers)
     }
 
+    fn put_local_peer(&self, peer: &Peer) -> Result<PutStatus, Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        let status = self.put_peer_in(&mut txn, peer.id(), peer, false)?;
+        if status.exists() {
+            return Ok(status)
+        }
+
+        self.meta.put(&mut txn, &LOCAL_PEER_KEY.to_string(), &peer.id())?;
+        txn.commit()?;
+
+        Ok(status)
+    }
+
+    fn get_local_peer(&self) -> Result<Option<Peer>, Self::Error> {
+        let txn = self.env.read_txn()?;
+        if let Some(id) = self.meta.get(&txn, &LOCAL_PEER_KEY.to_string())? {
+            Ok(self.peers.get(&txn, &id)?)
+        } else {
+            Ok(None)
+        }
+    }
+
     fn put_root(&self, id: Uuid, root: &StorableRoot, overwrite: bool) -> Result<PutStatus, Self::Error> {
         let mut txn = self.env.write_txn()?;
 
@@ -149,4 +228,174 @@ impl GlobalStore for Heed {
             .collect::<Result<_, _>>()?;
         Ok(roots)
     }
+
+    fn put_root_member(&self, root: Uuid, peer: Uuid, mode: ShareMode) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        self.root_members.put(&mut txn, &(root, peer), &mode)?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    fn remove_root_member(&self, root: Uuid, peer: Uuid) -> Result<bool, Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        let removed = self.root_members.delete(&mut txn, &(root, peer))?;
+        txn.commit()?;
+
+        Ok(removed)
+    }
+
+    fn get_root_member(&self, root: Uuid, peer: Uuid) -> Result<Option<ShareMode>, Self::Error> {
+        let txn = self.env.read_txn()?;
+        let res = self.root_members.get(&txn, &(root, peer))?;
+        Ok(res)
+    }
+
+    fn get_root_members(&self, root: Uuid) -> Result<Vec<(Uuid, ShareMode)>, Self::Error> {
+        let txn = self.env.read_txn()?;
+
+        let mut members = Vec::new();
+        for i in self.root_members.iter(&txn)? {
+            let ((root_id, peer_id), mode) = i?;
+            if root_id == root {
+                members.push((peer_id, mode));
+            }
+        }
+
+        Ok(members)
+    }
+
+    fn put_selection(&self, root: Uuid, peer: Uuid, selection: &Selection) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        self.selections.put(&mut txn, &(root, peer), selection)?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    fn get_selection(&self, root: Uuid, peer: Uuid) -> Result<Option<Selection>, Self::Error> {
+        let txn = self.env.read_txn()?;
+        let res = self.selections.get(&txn, &(root, peer))?;
+        Ok(res)
+    }
+
+    fn put_compression(&self, root: Uuid, compression: Compression) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        self.compression.put(&mut txn, &root, &compression)?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    fn get_compression(&self, root: Uuid) -> Result<Option<Compression>, Self::Error> {
+        let txn = self.env.read_txn()?;
+        let res = self.compression.get(&txn, &root)?;
+        Ok(res)
+    }
+
+    fn put_root_key(&self, root: Uuid, key: &RootKey) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        self.root_keys.put(&mut txn, &root, key)?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    fn get_root_key(&self, root: Uuid) -> Result<Option<RootKey>, Self::Error> {
+        let txn = self.env.read_txn()?;
+        let res = self.root_keys.get(&txn, &root)?;
+        Ok(res)
+    }
+
+    fn add_block_ref(&self, hash: &Hash) -> Result<u64, Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        let refs = self.block_refs.get(&txn, hash)?.unwrap_or(0) + 1;
+        self.block_refs.put(&mut txn, hash, &refs)?;
+        txn.commit()?;
+
+        Ok(refs)
+    }
+
+    fn remove_block_ref(&self, hash: &Hash) -> Result<u64, Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        let refs = self.block_refs.get(&txn, hash)?.unwrap_or(0).saturating_sub(1);
+        if refs == 0 {
+            self.block_refs.delete(&mut txn, hash)?;
+        } else {
+            self.block_refs.put(&mut txn, hash, &refs)?;
+        }
+        txn.commit()?;
+
+        Ok(refs)
+    }
+
+    fn get_block_refs(&self, hash: &Hash) -> Result<u64, Self::Error> {
+        let txn = self.env.read_txn()?;
+        let res = self.block_refs.get(&txn, hash)?.unwrap_or(0);
+        Ok(res)
+    }
+
+    fn put_invite(&self, invite: &Invite) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        self.invites.put(&mut txn, &invite.id(), invite)?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    fn get_invites(&self) -> Result<Vec<Invite>, Self::Error> {
+        let txn = self.env.read_txn()?;
+
+        let invites = self.invites.iter(&txn)?
+            .map(|i| i.map(|i| i.1))
+            .collect::<Result<_, _>>()?;
+        Ok(invites)
+    }
+
+    fn take_invite(&self, id: Uuid, redeem: impl FnOnce(&Invite) -> bool) -> Result<Option<Invite>, Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        let res = self.invites.get(&txn, &id)?;
+        if res.as_ref().is_some_and(redeem) {
+            self.invites.delete(&mut txn, &id)?;
+            txn.commit()?;
+        }
+
+        Ok(res)
+    }
+}
+
+impl Heed {
+    /// [`put_peer`](GlobalStore::put_peer) as part of a larger transaction.
+    fn put_peer_in(&self, txn: &mut RwTxn, id: Uuid, peer: &Peer, overwrite: bool) -> Result<PutStatus, heed::Error> {
+        let peer_id = peer.peer_id().to_bytes();
+        let previous = self.peers.get(txn, &id)?;
+
+        if !overwrite && (
+            previous.is_some()
+                || self.peer_ids.get(txn, &peer_id)?.is_some()
+        ) {
+            return Ok(PutStatus::Exists)
+        }
+
+        if let Some(previous) = previous {
+            let previous_id = previous.peer_id().to_bytes();
+            if previous_id != peer_id {
+                self.peer_ids.delete(txn, &previous_id)?;
+            }
+        }
+
+        self.peers.put(txn, &id, peer)?;
+        self.peer_ids.put(txn, &peer_id, &id)?;
+
+        Ok(PutStatus::Ok)
+    }
+}
+
+impl Drop for Heed {
+    fn drop(&mut self) {
+        // heed keeps every opened environment alive in a global map. Without this,
+        // the environment is never closed and a store reopened at the same path
+        // would silently share the old one.
+        let _ = self.env.clone().prepare_for_closing();
+    }
 }
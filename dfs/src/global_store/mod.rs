@@ -1,8 +1,16 @@
 use uuid::Uuid;
 
+use crate::compression::Compression;
+use crate::encryption::RootKey;
 use crate::peer::Peer;
+use crate::peer::invite::Invite;
 use crate::root::StorableRoot;
+use crate::root::merkle::Hash;
+use crate::root::selection::Selection;
+use crate::root::share::ShareMode;
+use std::fmt::Debug;
 use std::path::Path;
+use libp2p::PeerId;
 
 pub mod heed_store;
 
@@ -34,16 +42,59 @@ impl PutStatus {
 /// TODO: GlobalStore systemwide or per-user?
 /// TODO: Do we allow overlapping roots (maybe from different users)
 pub trait GlobalStore: Sized + Sync {
-    type Error;
+    type Error: Debug;
 
     fn new(path: &Path) -> Result<Self, Self::Error>;
 
     fn put_peer(&self, id: Uuid, peer: &Peer, overwrite: bool) -> Result<PutStatus, Self::Error>;
     fn get_peer(&self, id: Uuid) -> Result<Option<Peer>, Self::Error>;
+    fn get_peer_by_peer_id(&self, peer_id: &PeerId) -> Result<Option<Peer>, Self::Error>;
     fn get_all_peers(&self) -> Result<Vec<Peer>, Self::Error>;
 
+    /// Store the peer and mark it as the local node, both at once. Like [`put_peer`](GlobalStore::put_peer)
+    /// without overwriting: nothing changes when the peer, or its key, is stored already.
+    fn put_local_peer(&self, peer: &Peer) -> Result<PutStatus, Self::Error>;
+    fn get_local_peer(&self) -> Result<Option<Peer>, Self::Error>;
+
     fn put_root(&self, id: Uuid, root: &StorableRoot, overwrite: bool) -> Result<PutStatus, Self::Error>;
     fn get_root(&self, id: Uuid) -> Result<Option<StorableRoot>, Self::Error>;
     fn get_root_by_name(&self, name: &str) -> Result<Option<StorableRoot>, Self::Error>;
     fn get_all_roots(&self) -> Result<Vec<StorableRoot>, Self::Error>;
+
+    /// Share the root with the peer, or change the mode if it was already shared.
+    fn put_root_member(&self, root: Uuid, peer: Uuid, mode: ShareMode) -> Result<(), Self::Error>;
+    /// Returns whether the peer was a member of the root.
+    fn remove_root_member(&self, root: Uuid, peer: Uuid) -> Result<bool, Self::Error>;
+    fn get_root_member(&self, root: Uuid, peer: Uuid) -> Result<Option<ShareMode>, Self::Error>;
+    fn get_root_members(&self, root: Uuid) -> Result<Vec<(Uuid, ShareMode)>, Self::Error>;
+
+    /// Set which parts of a root the peer keeps on disk.
+    fn put_selection(&self, root: Uuid, peer: Uuid, selection: &Selection) -> Result<(), Self::Error>;
+    /// Returns None when no selection was set, in which case the peer keeps the whole root.
+    fn get_selection(&self, root: Uuid, peer: Uuid) -> Result<Option<Selection>, Self::Error>;
+
+    /// Set how the blocks of a root are compressed.
+    fn put_compression(&self, root: Uuid, compression: Compression) -> Result<(), Self::Error>;
+    /// Returns None when it wasn't set, in which case the [configured](crate::config::Config::compression) default is used.
+    fn get_compression(&self, root: Uuid) -> Result<Option<Compression>, Self::Error>;
+
+    /// Store the key of an encrypted root.
+    fn put_root_key(&self, root: Uuid, key: &RootKey) -> Result<(), Self::Error>;
+    /// Returns None when the root isn't encrypted, or we only have it encrypted.
+    fn get_root_key(&self, root: Uuid) -> Result<Option<RootKey>, Self::Error>;
+
+    /// Count another use of a block in the [block store](crate::block_store). Returns the new count.
+    fn add_block_ref(&self, hash: &Hash) -> Result<u64, Self::Error>;
+    /// Count one use less of a block. Returns the new count, which doesn't go below 0.
+    fn remove_block_ref(&self, hash: &Hash) -> Result<u64, Self::Error>;
+    /// How often a block is used. Blocks which aren't used can be removed from the block store.
+    fn get_block_refs(&self, hash: &Hash) -> Result<u64, Self::Error>;
+
+    /// Store an invite created by this node until it is redeemed.
+    fn put_invite(&self, invite: &Invite) -> Result<(), Self::Error>;
+    /// All pending invites, in no particular order.
+    fn get_invites(&self) -> Result<Vec<Invite>, Self::Error>;
+    /// Get a pending invite, and remove it when `redeem` accepts it, both at once. Invites can
+    /// only be redeemed once.
+    fn take_invite(&self, id: Uuid, redeem: impl FnOnce(&Invite) -> bool) -> Result<Option<Invite>, Self::Error>;
 }
\ No newline at end of file
@@ -1,11 +1,19 @@
 pub use dfs_struct::*;
 
 pub mod root;
+pub mod block_store;
+pub mod compression;
+pub mod encryption;
 pub mod config;
+pub mod diff;
+#[cfg(feature = "fuse")]
+pub mod fuse;
 
 pub mod dfs_struct;
 pub mod peer;
 pub mod global_store;
+pub mod network;
+pub mod sync;
 
 pub mod test;
 
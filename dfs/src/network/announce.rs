@@ -0,0 +1,62 @@
+use libp2p::gossipsub::error::PublishError;
+use libp2p::gossipsub::{Gossipsub, GossipsubConfig, IdentTopic, MessageAuthenticity};
+use libp2p::identity::Keypair;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::network::shared::SharedRoot;
+use crate::root::merkle::Hash;
+
+#[derive(Debug, Error)]
+pub enum AnnounceError {
+    #[error("the root isn't served by this node")]
+    NotServed,
+
+    #[error("failed to read the local root: {0}")]
+    Local(String),
+
+    #[error("failed to publish the announcement: {0:?}")]
+    Publish(PublishError),
+}
+
+/// Published over gossipsub when the index of a root changed, so peers sharing the root
+/// know they have to sync, without having to ask every peer for its merkle hashes.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct Announcement {
+    pub root: Uuid,
+
+    /// the merkle hash of the top of the root after the change
+    pub hash: Hash,
+
+    /// increases with every announcement of a node, so older announcements which arrive
+    /// late (or twice) can be ignored
+    pub sequence: u64,
+}
+
+impl Announcement {
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("failed to serialize announcement")
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bincode::deserialize(bytes).ok()
+    }
+}
+
+/// The topic on which changes to a root are announced. Only members of the root subscribe to it.
+pub fn topic(root: Uuid) -> IdentTopic {
+    IdentTopic::new(format!("/dfs/changes/{}", root))
+}
+
+/// Announcements are signed with the key of the node, so the peer which made a change is known
+/// even when the announcement was relayed by another peer.
+pub(crate) fn gossipsub(keypair: &Keypair) -> Result<Gossipsub, &'static str> {
+    Gossipsub::new(MessageAuthenticity::Signed(keypair.clone()), GossipsubConfig::default())
+}
+
+/// The merkle hash of the top of a root, None when it was never indexed.
+pub(crate) fn root_hash(root: &dyn SharedRoot) -> Result<Option<Hash>, String> {
+    Ok(root.entries(None, Some(0))?
+        .and_then(|entries| entries.first().map(|e| *e.hash())))
+}
@@ -0,0 +1,122 @@
+use libp2p::{Multiaddr, NetworkBehaviour, PeerId};
+use libp2p::gossipsub::{Gossipsub, GossipsubEvent};
+use libp2p::mdns::{Mdns, MdnsConfig, MdnsEvent};
+use libp2p::ping::{Ping, PingConfig, PingEvent};
+use libp2p::request_response::{ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent};
+use libp2p::swarm::toggle::Toggle;
+use std::io;
+use std::iter;
+
+use crate::network::blocks::{BLOCK_PROTOCOL, BlockCodec, BlockRequest, BlockResponse, UNCOMPRESSED_BLOCK_PROTOCOL};
+use crate::network::manifest::{MANIFEST_PROTOCOL, ManifestCodec, ManifestRequest, ManifestResponse};
+use crate::network::pairing::{PAIRING_PROTOCOL, PairingCodec, PairingRequest, PairingResponse};
+
+/// The protocols spoken by a dfs [`Node`](crate::network::Node).
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "BehaviourEvent", event_process = false)]
+pub struct Behaviour {
+    /// Keeps connections to peers alive while there is nothing else to do.
+    ping: Ping,
+
+    /// Discovers peers on the local network. Disabled when [`Config::mdns`](crate::config::Config::mdns) is false.
+    mdns: Toggle<Mdns>,
+
+    /// Exchanges the entries of shared roots.
+    pub(crate) manifest: RequestResponse<ManifestCodec>,
+
+    /// Transfers blocks of files.
+    pub(crate) blocks: RequestResponse<BlockCodec>,
+
+    /// Redeems invites of nodes which aren't peers yet.
+    pub(crate) pairing: RequestResponse<PairingCodec>,
+
+    /// Announces changes to shared roots, see [`Announcement`](crate::network::announce::Announcement).
+    pub(crate) announcements: Gossipsub,
+}
+
+impl Behaviour {
+    pub(crate) async fn new(mdns: bool, announcements: Gossipsub) -> io::Result<Self> {
+        let mdns = if mdns {
+            Some(Mdns::new(MdnsConfig::default()).await?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            ping: Ping::new(PingConfig::new().with_keep_alive(true)),
+            mdns: mdns.into(),
+            manifest: RequestResponse::new(
+                ManifestCodec::default(),
+                iter::once((MANIFEST_PROTOCOL, ProtocolSupport::Full)),
+                RequestResponseConfig::default(),
+            ),
+            blocks: RequestResponse::new(
+                BlockCodec,
+                vec![
+                    (BLOCK_PROTOCOL, ProtocolSupport::Full),
+                    (UNCOMPRESSED_BLOCK_PROTOCOL, ProtocolSupport::Full),
+                ],
+                RequestResponseConfig::default(),
+            ),
+            pairing: RequestResponse::new(
+                PairingCodec::default(),
+                iter::once((PAIRING_PROTOCOL, ProtocolSupport::Full)),
+                RequestResponseConfig::default(),
+            ),
+            announcements,
+        })
+    }
+
+    /// Remember an address of a peer, so requests to it dial it when we aren't connected.
+    pub(crate) fn add_address(&mut self, peer: &PeerId, address: Multiaddr) {
+        self.manifest.add_address(peer, address.clone());
+        self.blocks.add_address(peer, address.clone());
+        self.pairing.add_address(peer, address);
+    }
+}
+
+#[derive(Debug)]
+pub enum BehaviourEvent {
+    Ping(PingEvent),
+    Mdns(Box<MdnsEvent>),
+    Manifest(Box<RequestResponseEvent<ManifestRequest, ManifestResponse>>),
+    Blocks(Box<RequestResponseEvent<BlockRequest, BlockResponse>>),
+    Pairing(Box<RequestResponseEvent<PairingRequest, PairingResponse>>),
+    Announcements(Box<GossipsubEvent>),
+}
+
+impl From<PingEvent> for BehaviourEvent {
+    fn from(e: PingEvent) -> Self {
+        BehaviourEvent::Ping(e)
+    }
+}
+
+impl From<MdnsEvent> for BehaviourEvent {
+    fn from(e: MdnsEvent) -> Self {
+        BehaviourEvent::Mdns(Box::new(e))
+    }
+}
+
+impl From<RequestResponseEvent<ManifestRequest, ManifestResponse>> for BehaviourEvent {
+    fn from(e: RequestResponseEvent<ManifestRequest, ManifestResponse>) -> Self {
+        BehaviourEvent::Manifest(Box::new(e))
+    }
+}
+
+impl From<RequestResponseEvent<BlockRequest, BlockResponse>> for BehaviourEvent {
+    fn from(e: RequestResponseEvent<BlockRequest, BlockResponse>) -> Self {
+        BehaviourEvent::Blocks(Box::new(e))
+    }
+}
+
+impl From<RequestResponseEvent<PairingRequest, PairingResponse>> for BehaviourEvent {
+    fn from(e: RequestResponseEvent<PairingRequest, PairingResponse>) -> Self {
+        BehaviourEvent::Pairing(Box::new(e))
+    }
+}
+
+impl From<GossipsubEvent> for BehaviourEvent {
+    fn from(e: GossipsubEvent) -> Self {
+        BehaviourEvent::Announcements(Box::new(e))
+    }
+}
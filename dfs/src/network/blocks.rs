@@ -0,0 +1,438 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use data_encoding::HEXLOWER;
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::PeerId;
+use libp2p::request_response::{OutboundFailure, RequestResponseCodec};
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::compression::{self, Compression};
+use crate::encryption::RootKey;
+use crate::global_store::GlobalStore;
+use crate::network::Node;
+use crate::network::codec::{self, Protocol};
+use crate::network::shared::SharedRoot;
+use crate::root::UnsafePath;
+use crate::root::blocks::{self, BLOCK_SIZE};
+use crate::root::dir_entry::StorableDirEntry;
+use crate::root::merkle::Hash;
+use crate::root::share::ShareMode;
+
+/// The protocol with which peers fetch blocks of files from each other. Blocks may be sent
+/// compressed, as [configured](crate::Dfs::set_compression) for their root.
+pub const BLOCK_PROTOCOL: Protocol = Protocol("/dfs/block/1.1.0");
+
+/// The protocol [`BLOCK_PROTOCOL`] was before blocks could be compressed. It's negotiated with
+/// peers which don't know about compression, and blocks are sent to them as they are.
+pub const UNCOMPRESSED_BLOCK_PROTOCOL: Protocol = Protocol("/dfs/block/1.0.0");
+
+/// How many block requests may be outstanding to a single peer during a download.
+pub const MAX_IN_FLIGHT: usize = 4;
+
+/// Partial downloads are staged in this folder inside the [local db](crate::config::Config::local_db) of a root.
+pub const PARTIAL_DIR: &str = "partial";
+
+#[derive(Debug, Error)]
+pub enum DownloadError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error("the root isn't served by this node")]
+    NotServed,
+
+    #[error("the root isn't shared with any of the peers in a way that allows receiving from them")]
+    NotShared,
+
+    #[error("only files can be downloaded")]
+    NotAFile,
+
+    #[error("none of the peers could provide block {0}")]
+    Unavailable(usize),
+
+    #[error("the downloaded file doesn't match its hash")]
+    Corrupt,
+
+    #[error("the entry claims {0} bytes, more than its blocks can hold")]
+    TooLarge(u64),
+
+    #[error("{0}")]
+    UnsafePath(UnsafePath),
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockRequest {
+    pub root: Uuid,
+
+    /// the hash of the block. Between peers of which one only has the root
+    /// [encrypted](crate::root::share::ShareMode::Encrypted), its [address](RootKey::block_address).
+    pub hash: Hash,
+
+    /// the id of an entry the block is used in, and the index of the block in it. Peers which
+    /// only have the root encrypted ask for blocks this way, as they only know their addresses.
+    pub location: Option<(Uuid, u32)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum BlockResponse {
+    Block(Vec<u8>),
+
+    /// The root isn't shared with the requesting peer, or only shared such that we don't send to it.
+    Denied,
+
+    /// The root isn't served by the node, or none of its files contain the block.
+    NotFound,
+
+    Error(String),
+
+    /// A block, compressed. Only sent over [`BLOCK_PROTOCOL`].
+    Compressed(Compression, Vec<u8>),
+
+    /// A block [sealed](RootKey::seal_block) with the key of the root, sent by and to peers which
+    /// only have the root encrypted.
+    Sealed(Vec<u8>),
+}
+
+/// What happened during a [download](Node::download).
+#[derive(Debug, Default)]
+pub struct DownloadReport {
+    /// blocks which were already downloaded by an earlier, interrupted, download
+    pub reused: usize,
+
+    /// blocks which were found on this node, in the block store or in a file of a served root
+    pub local: usize,
+
+    /// the number of blocks fetched from each peer
+    pub fetched: HashMap<PeerId, usize>,
+
+    /// how many bytes less were transferred because blocks were compressed
+    pub saved: u64,
+}
+
+/// Sends requests and responses as length prefixed bincode, like
+/// [`BincodeCodec`](crate::network::codec::BincodeCodec). Compressed blocks are unpacked before
+/// they're sent over [`UNCOMPRESSED_BLOCK_PROTOCOL`], so whether peers get compressed blocks is
+/// negotiated per connection.
+#[derive(Debug, Clone, Default)]
+pub struct BlockCodec;
+
+#[async_trait]
+impl RequestResponseCodec for BlockCodec {
+    type Protocol = Protocol;
+    type Request = BlockRequest;
+    type Response = BlockResponse;
+
+    async fn read_request<T>(&mut self, _: &Protocol, io: &mut T) -> io::Result<BlockRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        codec::read(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &Protocol, io: &mut T) -> io::Result<BlockResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        codec::read(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &Protocol, io: &mut T, req: BlockRequest) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = codec::encode(&req)?;
+        codec::write(io, bytes).await
+    }
+
+    async fn write_response<T>(&mut self, protocol: &Protocol, io: &mut T, res: BlockResponse) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let res = match res {
+            BlockResponse::Compressed(compression, data) if protocol.0 != BLOCK_PROTOCOL.0 => {
+                match compression::decompress(compression, &data, BLOCK_SIZE) {
+                    Ok(block) => BlockResponse::Block(block),
+                    Err(e) => BlockResponse::Error(format!("failed to decompress block: {}", e)),
+                }
+            }
+            res => res,
+        };
+
+        let bytes = codec::encode(&res)?;
+        codec::write(io, bytes).await
+    }
+}
+
+/// Answer a request for the block with hash `hash` of a root which the requesting peer may
+/// receive, or with its address for sealed roots. With a `key`, the block is sealed with it.
+pub(crate) fn block(root: &dyn SharedRoot, hash: &Hash, key: Option<&RootKey>) -> BlockResponse {
+    match (root.block(hash), key) {
+        (Ok(Some(block)), _) if root.sealed() => BlockResponse::Sealed(block),
+        (Ok(Some(block)), Some(key)) => BlockResponse::Sealed(key.seal_block(root.root_id(), hash, &block)),
+        (Ok(Some(block)), None) => {
+            let compression = root.block_compression(hash).unwrap_or_else(|e| {
+                log::warn!("failed to look up the compression of a block: {}", e);
+                Compression::None
+            });
+
+            match compression::compress(compression, &block) {
+                Some((compression, compressed)) => BlockResponse::Compressed(compression, compressed),
+                None => BlockResponse::Block(block),
+            }
+        }
+        (Ok(None), _) => BlockResponse::NotFound,
+        (Err(e), _) => BlockResponse::Error(e),
+    }
+}
+
+/// The hash of the block a peer which only has the root encrypted asks for, looked up where the
+/// request says it's used. None when it isn't used there, or its address doesn't match.
+pub(crate) fn sealed_block_hash(root: &dyn SharedRoot, key: &RootKey, request: &BlockRequest) -> Result<Option<Hash>, String> {
+    let (entry, index) = match request.location {
+        Some(location) => location,
+        None => return Ok(None),
+    };
+
+    let hash = root.entries(Some(entry), Some(0))?
+        .and_then(|entries| entries.first().and_then(|e| e.blocks().get(index as usize).copied()));
+    Ok(hash.filter(|hash| key.block_address(hash) == request.hash))
+}
+
+impl<'dfs, GS: GlobalStore> Node<'dfs, GS> {
+    /// Download a file of a served root from peers, and put it in place in the root. The entry
+    /// is the one of the peers (see [`fetch_manifest`](Node::fetch_manifest)), which determines
+    /// the path and contents of the file.
+    ///
+    /// Blocks are requested from all the peers at once, a few at a time per peer. Every block is
+    /// checked against its hash, and when a peer fails to provide a block, it is requested from
+    /// the others. The file is staged in the local db of the root while downloading, and only
+    /// renamed into place when it's complete and matches its hash. When a download is interrupted,
+    /// the next download of the same contents continues where it left off.
+    ///
+    /// This doesn't update the [`LocalStore`](crate::root::local_store::LocalStore) of the root.
+    pub async fn download(&mut self, root: Uuid, entry: &StorableDirEntry, peers: &[PeerId]) -> Result<DownloadReport, DownloadError<GS::Error>> {
+        let local = *self.roots.get(&root).ok_or(DownloadError::NotServed)?;
+        if entry.is_dir() {
+            return Err(DownloadError::NotAFile)
+        }
+        // the size comes from the peers, the staged file is allocated with it
+        if entry.size() > entry.blocks().len() as u64 * BLOCK_SIZE {
+            return Err(DownloadError::TooLarge(entry.size()))
+        }
+        let destination = local.disk_path(entry.path()).map_err(DownloadError::UnsafePath)?;
+
+        let key = self.dfs.root_key(root)?;
+
+        // peers which only have the root encrypted are asked for blocks by their address
+        let mut sources = Vec::new();
+        let mut sealed = HashSet::new();
+        for peer in peers {
+            match self.share_mode(root, peer)? {
+                Some(ShareMode::Encrypted) if key.is_some() => {
+                    sources.push(*peer);
+                    sealed.insert(*peer);
+                }
+                Some(ShareMode::Encrypted) => {}
+                Some(mode) if mode.receives() => sources.push(*peer),
+                _ => {}
+            }
+        }
+        if sources.is_empty() {
+            return Err(DownloadError::NotShared)
+        }
+
+        let staging_dir = local.local_db().join(PARTIAL_DIR);
+        fs::create_dir_all(&staging_dir).map_err(|e| DownloadError::Io(staging_dir.clone(), e))?;
+        let staging = staging_dir.join(HEXLOWER.encode(entry.hash()));
+        let io_err = |e| DownloadError::Io(staging.clone(), e);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&staging)
+            .map_err(io_err)?;
+        file.set_len(entry.size()).map_err(io_err)?;
+
+        let mut report = DownloadReport::default();
+        let mut todo = VecDeque::new();
+        for (index, hash) in entry.blocks().iter().enumerate() {
+            if blocks::read_block(&staging, index as u32, hash).map_err(io_err)?.is_some() {
+                report.reused += 1;
+            } else if let Some(block) = self.local_block(hash) {
+                file.seek(SeekFrom::Start(index as u64 * BLOCK_SIZE)).map_err(io_err)?;
+                file.write_all(&block).map_err(io_err)?;
+                report.local += 1;
+            } else {
+                todo.push_back(index);
+            }
+        }
+
+        let mut tried: HashMap<usize, HashSet<PeerId>> = HashMap::new();
+        let mut in_flight: HashMap<PeerId, usize> = HashMap::new();
+        let mut pending = HashMap::new();
+
+        while !todo.is_empty() || !pending.is_empty() {
+            let mut busy = VecDeque::new();
+            while let Some(index) = todo.pop_front() {
+                let tried = tried.entry(index).or_default();
+                let candidates: Vec<_> = sources.iter().filter(|p| !tried.contains(p)).collect();
+                if candidates.is_empty() {
+                    return Err(DownloadError::Unavailable(index))
+                }
+
+                let peer = candidates.into_iter()
+                    .filter(|p| in_flight.get(p).copied().unwrap_or(0) < MAX_IN_FLIGHT)
+                    .min_by_key(|p| in_flight.get(p).copied().unwrap_or(0));
+
+                match peer {
+                    Some(peer) => {
+                        let hash = entry.blocks()[index];
+                        let request = match &key {
+                            Some(key) if sealed.contains(peer) => BlockRequest {
+                                root,
+                                hash: key.block_address(&hash),
+                                location: Some((entry.id(), index as u32)),
+                            },
+                            _ => BlockRequest { root, hash, location: None },
+                        };
+                        let request_id = self.swarm.behaviour_mut().blocks.send_request(peer, request);
+                        *in_flight.entry(*peer).or_default() += 1;
+                        pending.insert(request_id, (index, *peer));
+                    }
+                    None => busy.push_back(index),
+                }
+            }
+            todo = busy;
+
+            let request_id = loop {
+                if let Some(id) = pending.keys().find(|id| self.block_responses.contains_key(id)) {
+                    break *id
+                }
+                self.poll_once().await;
+            };
+
+            let (index, peer) = pending.remove(&request_id).expect("response to unknown request");
+            *in_flight.entry(peer).or_default() -= 1;
+
+            let sealing = key.as_ref().map(|key| (key, root, &entry.blocks()[index]));
+            let response = match unpack(self.block_responses.remove(&request_id).expect("response disappeared"), sealing) {
+                Ok((block, sent)) if blocks::hash(&block) == entry.blocks()[index] => {
+                    file.seek(SeekFrom::Start(index as u64 * BLOCK_SIZE)).map_err(io_err)?;
+                    file.write_all(&block).map_err(io_err)?;
+                    *report.fetched.entry(peer).or_default() += 1;
+                    report.saved += (block.len() as u64).saturating_sub(sent as u64);
+                    continue
+                }
+                Ok((block, _)) => Ok(BlockResponse::Block(block)),
+                Err(response) => response,
+            };
+
+            log_failure(peer, index, response);
+            tried.entry(index).or_default().insert(peer);
+            todo.push_back(index);
+        }
+
+        file.sync_all().map_err(io_err)?;
+        drop(file);
+
+        if blocks::hash_file(&staging).map_err(io_err)?.hash != *entry.hash() {
+            let _ = fs::remove_file(&staging);
+            return Err(DownloadError::Corrupt)
+        }
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).map_err(|e| DownloadError::Io(parent.to_path_buf(), e))?;
+        }
+        fs::rename(&staging, &destination).map_err(|e| DownloadError::Io(destination.clone(), e))?;
+
+        Ok(report)
+    }
+
+    /// Find a block on this node, so it isn't fetched from peers again. Identical files, like
+    /// vendored ones, are often in several roots.
+    fn local_block(&self, hash: &Hash) -> Option<Vec<u8>> {
+        match self.dfs.stored_block(hash) {
+            Ok(Some(block)) => return Some(block),
+            Ok(None) => {}
+            Err(e) => log::debug!("failed to read a block from the block store: {:?}", e),
+        }
+
+        self.roots.values()
+            .filter_map(|root| root.block(hash).ok().flatten())
+            .find(|block| &blocks::hash(block) == hash)
+    }
+}
+
+/// The block in a response, and how many bytes were sent for it. Sealed blocks are opened with
+/// `key`, as the block with the hash of the root with the id in it. Responses without a block
+/// are handed back.
+fn unpack(response: Result<BlockResponse, OutboundFailure>, key: Option<(&RootKey, Uuid, &Hash)>) -> Result<(Vec<u8>, usize), Result<BlockResponse, OutboundFailure>> {
+    match response {
+        Ok(BlockResponse::Block(block)) => {
+            let sent = block.len();
+            Ok((block, sent))
+        }
+        Ok(BlockResponse::Compressed(compression, data)) => match compression::decompress(compression, &data, BLOCK_SIZE) {
+            Ok(block) => Ok((block, data.len())),
+            Err(e) => Err(Ok(BlockResponse::Error(format!("failed to decompress block: {}", e)))),
+        },
+        Ok(BlockResponse::Sealed(sealed)) => match key.map(|(key, root, hash)| key.open_block(root, hash, &sealed)) {
+            Some(Ok(block)) => Ok((block, sealed.len())),
+            Some(Err(e)) => Err(Ok(BlockResponse::Error(format!("failed to open block: {}", e)))),
+            None => Err(Ok(BlockResponse::Error("got a sealed block without having the key of the root".to_string()))),
+        },
+        response => Err(response),
+    }
+}
+
+fn log_failure(peer: PeerId, index: usize, response: Result<BlockResponse, OutboundFailure>) {
+    match response {
+        Ok(BlockResponse::Block(_)) => log::warn!("{} sent block {} with the wrong hash", peer, index),
+        Ok(BlockResponse::Error(e)) => log::warn!("{} failed to read block {}: {}", peer, index, e),
+        Ok(response) => log::debug!("{} couldn't provide block {}: {:?}", peer, index, response),
+        Err(e) => log::debug!("requesting block {} from {} failed: {}", index, peer, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::io::Cursor;
+    use libp2p::request_response::RequestResponseCodec;
+
+    use crate::compression::{self, Compression};
+    use crate::network::blocks::{BLOCK_PROTOCOL, BlockCodec, BlockResponse, UNCOMPRESSED_BLOCK_PROTOCOL, unpack};
+
+    #[tokio::test]
+    async fn negotiated_compression() {
+        let block = b"the same words, over and over again. ".repeat(1000);
+        let (_, compressed) = compression::compress(Compression::Zstd, &block).unwrap();
+
+        let mut sizes = Vec::new();
+        for protocol in [BLOCK_PROTOCOL, UNCOMPRESSED_BLOCK_PROTOCOL] {
+            let mut io = Cursor::new(Vec::new());
+            let response = BlockResponse::Compressed(Compression::Zstd, compressed.clone());
+            BlockCodec.write_response(&protocol, &mut io, response).await.unwrap();
+            let sent = io.into_inner();
+            sizes.push(sent.len());
+
+            let response = BlockCodec.read_response(&protocol, &mut Cursor::new(sent)).await.unwrap();
+            match (&response, protocol.0 == BLOCK_PROTOCOL.0) {
+                (BlockResponse::Compressed(..), true) | (BlockResponse::Block(_), false) => {}
+                _ => panic!("unexpected response over {}: {:?}", protocol.0, response),
+            }
+            assert_eq!(unpack(Ok(response), None).unwrap().0, block);
+        }
+
+        assert!(sizes[0] < sizes[1]);
+    }
+}
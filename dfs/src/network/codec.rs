@@ -0,0 +1,98 @@
+use std::io;
+use std::marker::PhantomData;
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use libp2p::core::ProtocolName;
+use libp2p::request_response::RequestResponseCodec;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Largest message any dfs protocol will read.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Name of a request-response protocol spoken by dfs nodes.
+#[derive(Debug, Clone, Copy)]
+pub struct Protocol(pub &'static str);
+
+impl ProtocolName for Protocol {
+    fn protocol_name(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+/// A [`RequestResponseCodec`] which sends requests and responses as length prefixed bincode.
+#[derive(Debug)]
+pub struct BincodeCodec<Req, Resp> {
+    phantom: PhantomData<fn() -> (Req, Resp)>,
+}
+
+impl<Req, Resp> Default for BincodeCodec<Req, Resp> {
+    fn default() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Req, Resp> Clone for BincodeCodec<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+pub(crate) async fn read<T: DeserializeOwned>(io: &mut (impl AsyncRead + Unpin + Send)) -> io::Result<T> {
+    let bytes = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+    bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(crate) fn encode<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(crate) async fn write(io: &mut (impl AsyncWrite + Unpin + Send), bytes: Vec<u8>) -> io::Result<()> {
+    write_length_prefixed(io, bytes).await?;
+    io.close().await
+}
+
+#[async_trait]
+impl<Req, Resp> RequestResponseCodec for BincodeCodec<Req, Resp>
+where
+    Req: Serialize + DeserializeOwned + Send,
+    Resp: Serialize + DeserializeOwned + Send,
+{
+    type Protocol = Protocol;
+    type Request = Req;
+    type Response = Resp;
+
+    async fn read_request<T>(&mut self, _: &Protocol, io: &mut T) -> io::Result<Req>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &Protocol, io: &mut T) -> io::Result<Resp>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &Protocol, io: &mut T, req: Req) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = encode(&req)?;
+        write(io, bytes).await
+    }
+
+    async fn write_response<T>(&mut self, _: &Protocol, io: &mut T, res: Resp) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = encode(&res)?;
+        write(io, bytes).await
+    }
+}
@@ -0,0 +1,186 @@
+use libp2p::request_response::OutboundFailure;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::encryption::{EncryptionError, RootKey, SealedEntry};
+use crate::network::codec::{BincodeCodec, Protocol};
+use crate::network::shared::SharedRoot;
+use crate::root::UnsafePath;
+use crate::root::dir_entry::StorableDirEntry;
+
+/// The protocol with which peers exchange the entries of a root.
+pub const MANIFEST_PROTOCOL: Protocol = Protocol("/dfs/manifest/1.0.0");
+
+/// The maximum number of entries sent in one [`ManifestResponse::Page`]. Requests asking
+/// for more get a page of this size.
+pub const MAX_PAGE_SIZE: u32 = 1024;
+
+/// How often fetching the entries of a root starts over, when the peer moved or removed the entry
+/// a page continues after, before giving up.
+pub const MAX_RESTARTS: u32 = 3;
+
+pub type ManifestCodec = BincodeCodec<ManifestRequest, ManifestResponse>;
+
+#[derive(Debug, Error)]
+pub enum ManifestError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error("the root isn't shared with this peer in a way that allows receiving from it")]
+    NotShared,
+
+    #[error("the peer refused to send the manifest")]
+    Denied,
+
+    #[error("the peer doesn't have the requested root or entry")]
+    NotFound,
+
+    #[error("the root kept changing at the peer while its entries were fetched")]
+    Changing,
+
+    #[error("the peer failed to create the manifest: {0}")]
+    Remote(String),
+
+    #[error("the root isn't served by this node")]
+    NotServed,
+
+    #[error("failed to list local entries: {0}")]
+    Local(String),
+
+    #[error("request failed: {0}")]
+    Request(OutboundFailure),
+
+    #[error("failed to open the sealed entries: {0}")]
+    Encryption(EncryptionError),
+
+    #[error("the root is only shared encrypted with the peer, and this node doesn't have its key")]
+    NoKey,
+
+    #[error("the peer sent the entries sealed when it shouldn't, or the other way around")]
+    WrongSealing,
+
+    #[error("the peer sent an entry outside the root: {0}")]
+    UnsafePath(UnsafePath),
+}
+
+/// Ask a peer for a page of the entries of a root. The entries are ordered as
+/// described in [`ConnectedRoot::manifest`](crate::root::ConnectedRoot::manifest).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestRequest {
+    pub root: Uuid,
+
+    /// only list the entries under this entry. The whole root when None.
+    pub subtree: Option<Uuid>,
+
+    /// only list entries up to this many levels below the top of the subtree.
+    pub depth: Option<u32>,
+
+    /// continue after the entry with this id, the last one of the previous page. The first page
+    /// when None.
+    pub after: Option<Uuid>,
+
+    /// maximum number of entries in the page, capped to [`MAX_PAGE_SIZE`]
+    pub limit: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ManifestResponse {
+    Page {
+        entries: Vec<StorableDirEntry>,
+
+        /// whether there are entries after this page
+        more: bool,
+    },
+
+    /// A page of [sealed](RootKey::seal_entry) entries, sent to and by peers which only have the
+    /// root [encrypted](crate::root::share::ShareMode::Encrypted).
+    SealedPage {
+        entries: Vec<SealedEntry>,
+        more: bool,
+    },
+
+    /// The root isn't shared with the requesting peer, or only shared such that we don't send to it.
+    Denied,
+
+    /// The root isn't served by the node, or the subtree doesn't exist. For later pages also when
+    /// the entry to continue after was moved out of the subtree or removed since.
+    NotFound,
+
+    Error(String),
+}
+
+/// The entries of a [`ManifestResponse::Page`] or a [`ManifestResponse::SealedPage`].
+pub(crate) trait PageEntry: Sized {
+    fn id(&self) -> Uuid;
+
+    /// The entries in a page and whether more follow. Responses which aren't pages of this kind
+    /// are handed back.
+    fn from_response(response: ManifestResponse) -> Result<(Vec<Self>, bool), ManifestResponse>;
+}
+
+impl PageEntry for StorableDirEntry {
+    fn id(&self) -> Uuid {
+        StorableDirEntry::id(self)
+    }
+
+    fn from_response(response: ManifestResponse) -> Result<(Vec<Self>, bool), ManifestResponse> {
+        match response {
+            ManifestResponse::Page { entries, more } => Ok((entries, more)),
+            response => Err(response),
+        }
+    }
+}
+
+impl PageEntry for SealedEntry {
+    fn id(&self) -> Uuid {
+        SealedEntry::id(self)
+    }
+
+    fn from_response(response: ManifestResponse) -> Result<(Vec<Self>, bool), ManifestResponse> {
+        match response {
+            ManifestResponse::SealedPage { entries, more } => Ok((entries, more)),
+            response => Err(response),
+        }
+    }
+}
+
+/// Answer a request for a root which the requesting peer may receive. With a `key`, the
+/// entries are sealed with it. Sealed roots can only be listed whole.
+pub(crate) fn page(root: &dyn SharedRoot, request: &ManifestRequest, key: Option<&RootKey>) -> ManifestResponse {
+    let limit = request.limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    if root.sealed() {
+        if request.subtree.is_some() || request.depth.is_some() {
+            return ManifestResponse::Error("only whole sealed roots can be listed".to_string())
+        }
+
+        // one more, to know whether this is the last page
+        return match root.sealed_entries_page(request.after, limit + 1) {
+            Ok(Some(mut entries)) => {
+                let more = entries.len() > limit;
+                entries.truncate(limit);
+                ManifestResponse::SealedPage { entries, more }
+            }
+            Ok(None) => ManifestResponse::NotFound,
+            Err(e) => ManifestResponse::Error(e),
+        }
+    }
+
+    // one more, to know whether this is the last page
+    let mut entries = match root.entries_page(request.subtree, request.depth, request.after, limit + 1) {
+        Ok(Some(entries)) => entries,
+        Ok(None) => return ManifestResponse::NotFound,
+        Err(e) => return ManifestResponse::Error(e),
+    };
+
+    let more = entries.len() > limit;
+    entries.truncate(limit);
+
+    match key {
+        Some(key) => ManifestResponse::SealedPage {
+            entries: entries.iter().map(|entry| key.seal_entry(root.root_id(), entry)).collect(),
+            more,
+        },
+        None => ManifestResponse::Page { entries, more },
+    }
+}
@@ -0,0 +1,1367 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::StreamExt;
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::upgrade;
+use libp2p::gossipsub::GossipsubEvent;
+use libp2p::gossipsub::error::PublishError;
+use libp2p::mdns::MdnsEvent;
+use libp2p::noise::{self, NoiseConfig, X25519Spec};
+use libp2p::request_response::{OutboundFailure, RequestId, RequestResponseEvent, RequestResponseMessage};
+use libp2p::swarm::{SwarmBuilder, SwarmEvent};
+use libp2p::tcp::TcpConfig;
+use libp2p::yamux::YamuxConfig;
+use libp2p::{Multiaddr, PeerId, Swarm, Transport};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{AcceptInviteError, CreateInviteError, Dfs};
+use crate::diff::{self, Change};
+use crate::encryption::{RootKey, SealedEntry};
+use crate::global_store::GlobalStore;
+use crate::network::announce::{AnnounceError, Announcement};
+use crate::network::behaviour::{Behaviour, BehaviourEvent};
+use crate::network::blocks::{BlockRequest, BlockResponse};
+use crate::network::manifest::{MAX_PAGE_SIZE, MAX_RESTARTS, ManifestError, ManifestRequest, ManifestResponse, PageEntry};
+use crate::network::pairing::{PairingError, PairingRequest, PairingResponse};
+use crate::network::shared::{SharedRoot, subtree_of};
+use crate::peer::Peer;
+use crate::peer::invite::Invite;
+use crate::root::{StorableRoot, check_path};
+use crate::root::dir_entry::StorableDirEntry;
+use crate::root::merkle::{self, Difference, Hash};
+use crate::root::share::ShareMode;
+
+pub mod announce;
+pub mod behaviour;
+pub mod blocks;
+pub mod codec;
+pub mod manifest;
+pub mod pairing;
+pub mod sealed;
+pub mod shared;
+
+#[derive(Debug, Error)]
+pub enum NetworkError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error("failed to create noise keys: {0}")]
+    Noise(noise::NoiseError),
+
+    #[error("failed to start mdns: {0}")]
+    Mdns(io::Error),
+
+    #[error("failed to set up gossipsub: {0}")]
+    Gossipsub(&'static str),
+
+    #[error("failed to listen on {0}: {1}")]
+    Listen(Multiaddr, String),
+
+    #[error("peer has no known addresses")]
+    NoAddresses,
+
+    #[error("failed to dial {0}: {1}")]
+    Dial(Multiaddr, String),
+}
+
+/// How long a peer which isn't known may stay connected without redeeming an invite.
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a peer whose invite was refused stays connected, to receive the answer.
+const REFUSED_GRACE: Duration = Duration::from_secs(1);
+
+/// Error returned by the transport when a peer which isn't known in the
+/// [`GlobalStore`] tries to connect.
+#[derive(Debug, Error)]
+#[error("peer {0} isn't a known peer")]
+pub struct UnknownPeer(PeerId);
+
+/// Events emitted by a [`Node`].
+#[derive(Debug)]
+pub enum NodeEvent {
+    /// The node started listening on a new address.
+    Listening(Multiaddr),
+
+    /// A connection to a peer was established. Peers which aren't known only get connected while
+    /// this node has pending invites, to [pair](Node::pair) with it.
+    Connected(PeerId),
+
+    /// The last connection to a peer was closed.
+    Disconnected(PeerId),
+
+    /// Someone tried to connect to us, but the connection failed or was
+    /// refused because they aren't a known peer.
+    IncomingConnectionFailed(Multiaddr),
+
+    /// Dialing an address failed.
+    DialFailed(Multiaddr),
+
+    /// A node redeemed an invite of this node, and is now a known peer.
+    Paired(PeerId),
+
+    /// A known peer was discovered on the local network.
+    Discovered(PeerId, Multiaddr),
+
+    /// A peer [announced](Node::announce) a change to a served root, and its copy of the root now
+    /// differs from ours. [Syncing](crate::sync::sync) with the peer picks up the change.
+    RootChanged(PeerId, Uuid),
+}
+
+/// A dfs node on the network. The node runs with the identity of the local peer and
+/// only accepts connections from (and makes connections to) peers known in the [`GlobalStore`],
+/// and, while it has pending invites, from nodes which want to redeem one.
+pub struct Node<'dfs, GS> {
+    dfs: &'dfs Dfs<GS>,
+    swarm: Swarm<Behaviour>,
+
+    /// Peers we accept connections from. Shared with the transport, which checks
+    /// it right after the noise handshake authenticated the remote.
+    known_peers: Arc<RwLock<HashSet<PeerId>>>,
+
+    /// Until when unknown peers may connect, to present an invite: when the last pending invite
+    /// expires, in seconds since the unix epoch, or 0 without pending invites. Shared with the
+    /// transport like `known_peers`.
+    pairing: Arc<AtomicU64>,
+
+    /// Unknown peers which connected to present an invite, and by when they are disconnected
+    /// unless they redeemed one.
+    unpaired: HashMap<PeerId, Instant>,
+
+    /// Events which were produced but not yet returned from [`next_event`](Node::next_event).
+    pending_events: VecDeque<NodeEvent>,
+
+    /// The roots this node serves to peers they are shared with, by root id.
+    roots: HashMap<Uuid, &'dfs dyn SharedRoot>,
+
+    /// Responses to manifest requests we sent, until they are picked up.
+    manifest_responses: HashMap<RequestId, Result<ManifestResponse, OutboundFailure>>,
+
+    /// Responses to block requests we sent, until they are picked up.
+    block_responses: HashMap<RequestId, Result<BlockResponse, OutboundFailure>>,
+
+    /// Responses to pairing requests we sent, until they are picked up.
+    pairing_responses: HashMap<RequestId, Result<PairingResponse, OutboundFailure>>,
+
+    /// The hash we last announced for each served root.
+    announced: HashMap<Uuid, Hash>,
+
+    /// The sequence number of our last announcement.
+    sequence: u64,
+
+    /// The sequence number of the last announcement received from a peer, by root.
+    received: HashMap<(PeerId, Uuid), u64>,
+}
+
+impl<'dfs, GS: GlobalStore> Node<'dfs, GS> {
+    /// Create a node and start listening on the addresses in the [`Config`](crate::config::Config).
+    /// Must be called from within a tokio runtime.
+    pub async fn new(dfs: &'dfs Dfs<GS>) -> Result<Self, NetworkError<GS::Error>> {
+        let keypair = dfs.keypair();
+        let known_peers = Arc::new(RwLock::new(HashSet::new()));
+        let pairing = Arc::new(AtomicU64::new(0));
+
+        let noise_keys = noise::Keypair::<X25519Spec>::new().into_authentic(keypair)
+            .map_err(NetworkError::Noise)?;
+        let filter = Arc::clone(&known_peers);
+        let accept_unknown = Arc::clone(&pairing);
+
+        let transport = TcpConfig::new()
+            .nodelay(true)
+            .upgrade(upgrade::Version::V1)
+            .authenticate(NoiseConfig::xx(noise_keys).into_authenticated())
+            .multiplex(YamuxConfig::default())
+            .timeout(Duration::from_secs(20))
+            .and_then(move |(peer_id, muxer), _| {
+                let known = filter.read()
+                    .map(|known| known.contains(&peer_id))
+                    .unwrap_or(false);
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(u64::MAX);
+                let pairing = now <= accept_unknown.load(Ordering::SeqCst);
+
+                async move {
+                    if known {
+                        Ok((peer_id, StreamMuxerBox::new(muxer)))
+                    } else if pairing {
+                        log::info!("accepted connection with unknown peer {}, which may present an invite", peer_id);
+                        Ok((peer_id, StreamMuxerBox::new(muxer)))
+                    } else {
+                        log::info!("refused connection with unknown peer {}", peer_id);
+                        Err(UnknownPeer(peer_id))
+                    }
+                }
+            })
+            .boxed();
+
+        let announcements = announce::gossipsub(keypair)
+            .map_err(NetworkError::Gossipsub)?;
+        let behaviour = Behaviour::new(dfs.cfg().mdns, announcements).await
+            .map_err(NetworkError::Mdns)?;
+
+        let swarm = SwarmBuilder::new(transport, behaviour, dfs.peer_id())
+            .executor(Box::new(|fut| {
+                tokio::spawn(fut);
+            }))
+            .build();
+
+        let mut node = Self {
+            dfs,
+            swarm,
+            known_peers,
+            pairing,
+            unpaired: HashMap::new(),
+            pending_events: VecDeque::new(),
+            roots: HashMap::new(),
+            manifest_responses: HashMap::new(),
+            block_responses: HashMap::new(),
+            pairing_responses: HashMap::new(),
+            announced: HashMap::new(),
+            // start from the time, so peers which remember our announcements from before
+            // a restart don't ignore the new ones
+            sequence: SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            received: HashMap::new(),
+        };
+
+        node.refresh_peers()?;
+
+        for address in &dfs.cfg().listen_addresses {
+            node.swarm.listen_on(address.clone())
+                .map_err(|e| NetworkError::Listen(address.clone(), e.to_string()))?;
+        }
+
+        Ok(node)
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    /// The addresses this node is actually listening on. When listening on port 0,
+    /// these contain the port that was picked.
+    pub fn listen_addresses(&self) -> Vec<Multiaddr> {
+        self.swarm.listeners().cloned().collect()
+    }
+
+    /// Reload the set of known peers, and whether there are pending invites, from the
+    /// [`GlobalStore`]. Call this after adding peers or creating invites while the node is
+    /// running. The known addresses of the peers are used to dial them when a request is sent
+    /// to a peer we aren't connected to.
+    pub fn refresh_peers(&mut self) -> Result<(), NetworkError<GS::Error>> {
+        let local = self.dfs.peer_id();
+        let peers: Vec<_> = self.dfs.connection.get_all_peers()?.into_iter()
+            .filter(|p| p.peer_id() != local)
+            .collect();
+
+        {
+            let mut known = self.known_peers.write().expect("known peers lock poisoned");
+            known.clear();
+            known.extend(peers.iter().map(Peer::peer_id));
+        }
+        self.refresh_pairing()?;
+
+        for peer in &peers {
+            for address in peer.addresses() {
+                self.swarm.behaviour_mut().add_address(&peer.peer_id(), address.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Accept connections of unknown peers until the last pending invite expires.
+    fn refresh_pairing(&self) -> Result<(), GS::Error> {
+        let invites = self.dfs.connection.get_invites()?;
+        let pairing_until = invites.iter()
+            .filter(|i| !i.is_expired())
+            .map(Invite::expires)
+            .max()
+            .unwrap_or(0);
+        self.pairing.store(pairing_until, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// [Create an invite](Dfs::create_invite) for a root, with which the invitee dials this node
+    /// at the addresses it actually listens on. Unknown peers may connect to present it from now on.
+    pub fn create_invite(&mut self, root: &StorableRoot, mode: ShareMode, expiry: Duration) -> Result<String, CreateInviteError<GS::Error>> {
+        let token = self.dfs.create_invite_at(root, mode, self.listen_addresses(), expiry)?;
+        self.refresh_pairing()?;
+        Ok(token)
+    }
+
+    /// Dial all known addresses of a peer.
+    pub fn dial(&mut self, peer: &Peer) -> Result<(), NetworkError<GS::Error>> {
+        if peer.addresses().is_empty() {
+            return Err(NetworkError::NoAddresses)
+        }
+
+        for address in peer.addresses() {
+            self.dial_addr(address.clone())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn dial_addr(&mut self, address: Multiaddr) -> Result<(), NetworkError<GS::Error>> {
+        self.swarm.dial_addr(address.clone())
+            .map_err(|e| NetworkError::Dial(address, format!("{:?}", e)))
+    }
+
+    pub fn is_connected(&self, peer: &PeerId) -> bool {
+        self.swarm.is_connected(peer)
+    }
+
+    /// Serve a root to the peers it is shared with. Peers may only request the root
+    /// when it is shared with them in a [`ShareMode`] which [sends](ShareMode::sends).
+    ///
+    /// The node also listens for [announcements](Node::announce) of changes to the root.
+    pub fn serve(&mut self, root: &'dfs dyn SharedRoot) {
+        let id = root.root_id();
+        self.roots.insert(id, root);
+
+        if let Err(e) = self.swarm.behaviour_mut().announcements.subscribe(&announce::topic(id)) {
+            log::error!("failed to subscribe to changes of root {}: {:?}", id, e);
+        }
+    }
+
+    /// Tell the peers sharing a served root about its current merkle hash, if it changed since
+    /// the last announcement. Peers whose copy differs get a [`NodeEvent::RootChanged`].
+    /// Call this after indexing the root; [`sync`](crate::sync::sync) does so itself.
+    ///
+    /// Returns whether an announcement was sent.
+    pub fn announce(&mut self, root: Uuid) -> Result<bool, AnnounceError> {
+        let hash = match self.served_hash(root)? {
+            Some(hash) => hash,
+            None => return Ok(false),
+        };
+
+        if self.announced.get(&root) == Some(&hash) {
+            return Ok(false)
+        }
+
+        self.publish(root, hash)
+    }
+
+    /// Announce a served root like [`announce`](Node::announce), also when it didn't change since
+    /// the last announcement. [`sync`](crate::sync::sync) uses this to have a peer which lacks our
+    /// changes sync with us.
+    ///
+    /// Returns whether an announcement was sent.
+    pub fn reannounce(&mut self, root: Uuid) -> Result<bool, AnnounceError> {
+        match self.served_hash(root)? {
+            Some(hash) => self.publish(root, hash),
+            None => Ok(false),
+        }
+    }
+
+    fn served_hash(&self, root: Uuid) -> Result<Option<Hash>, AnnounceError> {
+        let served = self.roots.get(&root).ok_or(AnnounceError::NotServed)?;
+        announce::root_hash(*served).map_err(AnnounceError::Local)
+    }
+
+    fn publish(&mut self, root: Uuid, hash: Hash) -> Result<bool, AnnounceError> {
+        self.sequence += 1;
+        let announcement = Announcement { root, hash, sequence: self.sequence };
+
+        let res = self.swarm.behaviour_mut().announcements.publish(announce::topic(root), announcement.to_bytes());
+        match res {
+            Ok(_) => {
+                self.announced.insert(root, hash);
+                Ok(true)
+            }
+            // nobody to tell, the peers learn about it when they subscribe
+            Err(PublishError::InsufficientPeers) => Ok(false),
+            Err(e) => Err(AnnounceError::Publish(e)),
+        }
+    }
+
+    /// Drive the node until something interesting happens.
+    pub async fn next_event(&mut self) -> NodeEvent {
+        loop {
+            if let Some(event) = self.pending_events.pop_front() {
+                return event
+            }
+
+            self.poll_once().await;
+        }
+    }
+
+    /// Wait for and process a single event of the swarm. Events interesting to the user
+    /// of the node are queued in `pending_events`.
+    async fn poll_once(&mut self) {
+        let event = match self.unpaired.values().min().copied() {
+            Some(deadline) => tokio::select! {
+                event = self.swarm.select_next_some() => event,
+                _ = tokio::time::sleep_until(deadline.into()) => return self.disconnect_unpaired(),
+            },
+            None => self.swarm.select_next_some().await,
+        };
+
+        let event = match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                log::info!("listening on {}", address);
+                NodeEvent::Listening(address)
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, num_established, .. } if num_established.get() == 1 => {
+                log::info!("connected to {}", peer_id);
+                if !self.known_peers.read().expect("known peers lock poisoned").contains(&peer_id) {
+                    self.unpaired.insert(peer_id, Instant::now() + PAIRING_TIMEOUT);
+                }
+                NodeEvent::Connected(peer_id)
+            }
+            SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                log::info!("disconnected from {}", peer_id);
+                self.unpaired.remove(&peer_id);
+                NodeEvent::Disconnected(peer_id)
+            }
+            SwarmEvent::IncomingConnectionError { send_back_addr, error, .. } => {
+                log::debug!("incoming connection from {} failed: {:?}", send_back_addr, error);
+                NodeEvent::IncomingConnectionFailed(send_back_addr)
+            }
+            SwarmEvent::UnreachableAddr { address, error, .. }
+            | SwarmEvent::UnknownPeerUnreachableAddr { address, error } => {
+                log::debug!("failed to dial {}: {:?}", address, error);
+                NodeEvent::DialFailed(address)
+            }
+            SwarmEvent::Behaviour(event) => return self.handle_behaviour_event(event),
+            _ => return,
+        };
+
+        self.pending_events.push_back(event);
+    }
+
+    fn handle_behaviour_event(&mut self, event: BehaviourEvent) {
+        match event {
+            BehaviourEvent::Ping(_) => {}
+            BehaviourEvent::Mdns(event) => if let MdnsEvent::Discovered(discovered) = *event {
+                for (peer_id, address) in discovered {
+                    if let Err(e) = self.discovered(peer_id, address) {
+                        log::error!("failed to process discovered peer {}: {:?}", peer_id, e);
+                    }
+                }
+            }
+            BehaviourEvent::Manifest(event) => match *event {
+                RequestResponseEvent::Message { peer, message: RequestResponseMessage::Request { request, channel, .. } } => {
+                    let response = self.answer_manifest(peer, &request);
+                    if self.swarm.behaviour_mut().manifest.send_response(channel, response).is_err() {
+                        log::debug!("{} went away before receiving the manifest it requested", peer);
+                    }
+                }
+                RequestResponseEvent::Message { message: RequestResponseMessage::Response { request_id, response }, .. } => {
+                    self.manifest_responses.insert(request_id, Ok(response));
+                }
+                RequestResponseEvent::OutboundFailure { request_id, error, .. } => {
+                    self.manifest_responses.insert(request_id, Err(error));
+                }
+                RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                    log::debug!("failed to answer manifest request of {}: {}", peer, error);
+                }
+                RequestResponseEvent::ResponseSent { .. } => {}
+            }
+            BehaviourEvent::Blocks(event) => match *event {
+                RequestResponseEvent::Message { peer, message: RequestResponseMessage::Request { request, channel, .. } } => {
+                    let response = self.answer_block(peer, &request);
+                    if self.swarm.behaviour_mut().blocks.send_response(channel, response).is_err() {
+                        log::debug!("{} went away before receiving the block it requested", peer);
+                    }
+                }
+                RequestResponseEvent::Message { message: RequestResponseMessage::Response { request_id, response }, .. } => {
+                    self.block_responses.insert(request_id, Ok(response));
+                }
+                RequestResponseEvent::OutboundFailure { request_id, error, .. } => {
+                    self.block_responses.insert(request_id, Err(error));
+                }
+                RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                    log::debug!("failed to answer block request of {}: {}", peer, error);
+                }
+                RequestResponseEvent::ResponseSent { .. } => {}
+            }
+            BehaviourEvent::Pairing(event) => match *event {
+                RequestResponseEvent::Message { peer, message: RequestResponseMessage::Request { request, channel, .. } } => {
+                    let response = self.answer_pairing(peer, request);
+                    if self.swarm.behaviour_mut().pairing.send_response(channel, response).is_err() {
+                        log::debug!("{} went away before learning whether its invite was redeemed", peer);
+                    }
+                }
+                RequestResponseEvent::Message { message: RequestResponseMessage::Response { request_id, response }, .. } => {
+                    self.pairing_responses.insert(request_id, Ok(response));
+                }
+                RequestResponseEvent::OutboundFailure { request_id, error, .. } => {
+                    self.pairing_responses.insert(request_id, Err(error));
+                }
+                RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                    log::debug!("failed to answer pairing request of {}: {}", peer, error);
+                }
+                RequestResponseEvent::ResponseSent { .. } => {}
+            }
+            BehaviourEvent::Announcements(event) => match *event {
+                GossipsubEvent::Message { message, .. } => {
+                    match (message.source, Announcement::from_bytes(&message.data)) {
+                        (Some(source), Some(announcement)) if message.topic == announce::topic(announcement.root).hash() => {
+                            self.announcement(source, announcement);
+                        }
+                        _ => log::debug!("ignoring invalid announcement on {}", message.topic),
+                    }
+                }
+                // tell peers which just joined where we are, so they sync if they are behind
+                GossipsubEvent::Subscribed { peer_id, topic } => {
+                    let root = self.roots.keys().copied().find(|r| announce::topic(*r).hash() == topic);
+                    // unknown peers which may still present an invite don't get to know the root
+                    if let Some(root) = root.filter(|_| !self.unpaired.contains_key(&peer_id)) {
+                        log::debug!("{} subscribed to changes of root {}", peer_id, root);
+                        let res = match self.served_hash(root) {
+                            Ok(Some(hash)) => self.publish(root, hash).map(|_| ()),
+                            Ok(None) => Ok(()),
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = res {
+                            log::error!("failed to announce root {}: {}", root, e);
+                        }
+                    }
+                }
+                GossipsubEvent::Unsubscribed { .. } => {}
+            }
+        }
+    }
+
+    /// Handle an announcement published by `source`. Announcements of roots we don't serve, or
+    /// which we don't receive from the peer, are ignored, as are those older than the last one.
+    fn announcement(&mut self, source: PeerId, announcement: Announcement) {
+        if source == self.peer_id() {
+            return
+        }
+
+        match self.share_mode(announcement.root, &source) {
+            Ok(Some(mode)) if mode.receives() => {}
+            Ok(_) => return,
+            Err(e) => {
+                log::error!("failed to check the share mode of {}: {:?}", source, e);
+                return
+            }
+        }
+
+        let last = self.received.entry((source, announcement.root)).or_default();
+        if announcement.sequence <= *last {
+            return
+        }
+        *last = announcement.sequence;
+
+        match self.served_hash(announcement.root) {
+            Ok(hash) if hash != Some(announcement.hash) => {
+                log::info!("{} announced a change to root {}", source, announcement.root);
+                self.pending_events.push_back(NodeEvent::RootChanged(source, announcement.root));
+            }
+            Ok(_) | Err(AnnounceError::NotServed) => {}
+            Err(e) => log::error!("failed to compare root {} with the announcement of {}: {}", announcement.root, source, e),
+        }
+    }
+
+    fn share_mode(&self, root: Uuid, peer: &PeerId) -> Result<Option<ShareMode>, GS::Error> {
+        match self.dfs.connection.get_peer_by_peer_id(peer)? {
+            Some(peer) => self.dfs.connection.get_root_member(root, peer.id()),
+            None => Ok(None),
+        }
+    }
+
+    /// Redeem the invite presented by `peer`. Unknown peers which don't present a valid invite
+    /// are disconnected shortly after they received the answer.
+    fn answer_pairing(&mut self, peer: PeerId, request: PairingRequest) -> PairingResponse {
+        let res = if request.identity.peer_id() == peer {
+            self.dfs.redeem_invite(request.invite, request.identity)
+        } else {
+            Err(AcceptInviteError::UnknownInvite)
+        };
+
+        match res {
+            Ok(_) => {
+                log::info!("{} redeemed invite {}", peer, request.invite);
+                self.unpaired.remove(&peer);
+                if let Err(e) = self.refresh_peers() {
+                    log::error!("failed to reload the known peers: {:?}", e);
+                }
+                self.pending_events.push_back(NodeEvent::Paired(peer));
+                PairingResponse::Paired
+            }
+            Err(AcceptInviteError::DbInteractionError(e)) => PairingResponse::Error(format!("{:?}", e)),
+            Err(e) => {
+                log::info!("refused invite {} of {}: {:?}", request.invite, peer, e);
+                if let Some(deadline) = self.unpaired.get_mut(&peer) {
+                    *deadline = (*deadline).min(Instant::now() + REFUSED_GRACE);
+                }
+                PairingResponse::Denied
+            }
+        }
+    }
+
+    /// Disconnect the unknown peers which didn't redeem an invite in time.
+    fn disconnect_unpaired(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self.unpaired.iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        for peer in expired {
+            log::info!("disconnecting {}, which didn't redeem an invite", peer);
+            self.unpaired.remove(&peer);
+            let _ = self.swarm.disconnect_peer_id(peer);
+        }
+    }
+
+    fn answer_manifest(&self, peer: PeerId, request: &ManifestRequest) -> ManifestResponse {
+        match self.share_mode(request.root, &peer) {
+            Ok(Some(mode)) if mode.sends() => {}
+            Ok(_) => {
+                log::info!("refused manifest of root {} to {}", request.root, peer);
+                return ManifestResponse::Denied
+            }
+            Err(e) => return ManifestResponse::Error(format!("{:?}", e)),
+        }
+
+        let root = match self.roots.get(&request.root) {
+            Some(root) => *root,
+            None => return ManifestResponse::NotFound,
+        };
+        match self.sealing_key(root, &peer) {
+            Ok(key) => manifest::page(root, request, key.as_ref()),
+            Err(e) => ManifestResponse::Error(e),
+        }
+    }
+
+    fn answer_block(&self, peer: PeerId, request: &BlockRequest) -> BlockResponse {
+        match self.share_mode(request.root, &peer) {
+            Ok(Some(mode)) if mode.sends() => {}
+            Ok(_) => {
+                log::info!("refused block of root {} to {}", request.root, peer);
+                return BlockResponse::Denied
+            }
+            Err(e) => return BlockResponse::Error(format!("{:?}", e)),
+        }
+
+        let root = match self.roots.get(&request.root) {
+            Some(root) => *root,
+            None => return BlockResponse::NotFound,
+        };
+        let key = match self.sealing_key(root, &peer) {
+            Ok(key) => key,
+            Err(e) => return BlockResponse::Error(e),
+        };
+        // peers which only have the root encrypted know the block by its address, and where it's used
+        let hash = match &key {
+            Some(key) => match blocks::sealed_block_hash(root, key, request) {
+                Ok(Some(hash)) => hash,
+                Ok(None) => return BlockResponse::NotFound,
+                Err(e) => return BlockResponse::Error(e),
+            },
+            None => request.hash,
+        };
+
+        blocks::block(root, &hash, key.as_ref())
+    }
+
+    /// The key to seal what is sent to `peer` with, when the root is shared with it
+    /// [encrypted](ShareMode::Encrypted). Roots which are sealed already are sent as they are.
+    fn sealing_key(&self, root: &dyn SharedRoot, peer: &PeerId) -> Result<Option<RootKey>, String> {
+        if root.sealed() {
+            return Ok(None)
+        }
+
+        match self.share_mode(root.root_id(), peer) {
+            Ok(Some(ShareMode::Encrypted)) => match self.dfs.root_key(root.root_id()) {
+                Ok(Some(key)) => Ok(Some(key)),
+                Ok(None) => Err("the root is shared encrypted, but isn't encrypted".to_string()),
+                Err(e) => Err(format!("{:?}", e)),
+            },
+            Ok(_) => Ok(None),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    /// Fetch all entries of a root (or of the subtree under the entry with id `subtree`, up to
+    /// `depth` levels deep) from a connected peer. The root must be shared with the peer in a
+    /// [`ShareMode`] which [receives](ShareMode::receives) from it. The entries are ordered as
+    /// described in [`ConnectedRoot::manifest`](crate::root::ConnectedRoot::manifest).
+    ///
+    /// The entries are fetched in pages. When the peer moves or removes the entry a page continues
+    /// after in the meantime, fetching starts over. Peers which only have the root
+    /// [encrypted](ShareMode::Encrypted) send all entries sealed, which are opened with the key
+    /// of the root; the subtree is picked out of them here.
+    ///
+    /// While waiting for the peer, the node keeps processing other events, which are
+    /// returned from [`next_event`](Node::next_event) later.
+    pub async fn fetch_manifest(&mut self, peer: PeerId, root: Uuid, subtree: Option<Uuid>, depth: Option<u32>) -> Result<Vec<StorableDirEntry>, ManifestError<GS::Error>> {
+        let key = match self.share_mode(root, &peer)? {
+            Some(ShareMode::Encrypted) => Some(self.dfs.root_key(root)?.ok_or(ManifestError::NoKey)?),
+            Some(mode) if mode.receives() => None,
+            _ => return Err(ManifestError::NotShared),
+        };
+
+        let entries = match key {
+            Some(key) => {
+                let sealed: Vec<SealedEntry> = self.fetch_pages(peer, root, None, None).await?;
+                let entries = sealed.iter()
+                    .map(|entry| key.open_entry(root, entry))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(ManifestError::Encryption)?;
+                subtree_of(&entries, subtree, depth).ok_or(ManifestError::NotFound)?
+            }
+            None => self.fetch_pages(peer, root, subtree, depth).await?,
+        };
+
+        // nothing the peer sends may lead outside the root, or into its local db
+        for entry in &entries {
+            check_path(entry.path(), &self.dfs.cfg().local_db).map_err(ManifestError::UnsafePath)?;
+        }
+        Ok(entries)
+    }
+
+    /// Fetch all entries of a root sealed, as a peer which only has it encrypted gets them, see
+    /// [`SealedRoot`](sealed::SealedRoot).
+    pub(crate) async fn fetch_sealed_manifest(&mut self, peer: PeerId, root: Uuid) -> Result<Vec<SealedEntry>, ManifestError<GS::Error>> {
+        match self.share_mode(root, &peer)? {
+            Some(mode) if mode.receives() => self.fetch_pages(peer, root, None, None).await,
+            _ => Err(ManifestError::NotShared),
+        }
+    }
+
+    /// Fetch the pages of a manifest, of entries as they're sent: sealed or not.
+    async fn fetch_pages<E: PageEntry>(&mut self, peer: PeerId, root: Uuid, subtree: Option<Uuid>, depth: Option<u32>) -> Result<Vec<E>, ManifestError<GS::Error>> {
+        let mut entries = Vec::new();
+        // the id of the last entry received
+        let mut after = None;
+        let mut restarts = 0;
+        loop {
+            let request_id = self.swarm.behaviour_mut().manifest.send_request(&peer, ManifestRequest {
+                root,
+                subtree,
+                depth,
+                after,
+                limit: MAX_PAGE_SIZE,
+            });
+
+            let response = loop {
+                if let Some(response) = self.manifest_responses.remove(&request_id) {
+                    break response.map_err(ManifestError::Request)?
+                }
+                self.poll_once().await;
+            };
+
+            match E::from_response(response) {
+                Ok((page, more)) => {
+                    let empty = page.is_empty();
+                    after = page.last().map(E::id).or(after);
+                    entries.extend(page);
+
+                    if empty || !more {
+                        return Ok(entries)
+                    }
+                }
+                Err(ManifestResponse::Page { .. }) | Err(ManifestResponse::SealedPage { .. }) => return Err(ManifestError::WrongSealing),
+                // the entry to continue after was moved or removed since the previous page
+                Err(ManifestResponse::NotFound) if after.is_some() => {
+                    if restarts == MAX_RESTARTS {
+                        return Err(ManifestError::Changing)
+                    }
+                    restarts += 1;
+                    entries.clear();
+                    after = None;
+                }
+                Err(ManifestResponse::Denied) => return Err(ManifestError::Denied),
+                Err(ManifestResponse::NotFound) => return Err(ManifestError::NotFound),
+                Err(ManifestResponse::Error(e)) => return Err(ManifestError::Remote(e)),
+            }
+        }
+    }
+
+    /// Present an invite, accepted with [`Dfs::accept_invite`], to the inviter, which redeems it
+    /// and shares the root with this node. The inviter is dialed at the addresses in the invite
+    /// when we aren't connected to it.
+    pub async fn pair(&mut self, invite: &Invite) -> Result<(), PairingError<GS::Error>> {
+        let inviter = invite.inviter().peer_id();
+        self.known_peers.write().expect("known peers lock poisoned").insert(inviter);
+        for address in invite.addresses() {
+            self.swarm.behaviour_mut().add_address(&inviter, address.clone());
+        }
+
+        let request_id = self.swarm.behaviour_mut().pairing.send_request(&inviter, PairingRequest {
+            invite: invite.id(),
+            identity: self.dfs.identity(),
+        });
+
+        let response = loop {
+            if let Some(response) = self.pairing_responses.remove(&request_id) {
+                break response.map_err(PairingError::Request)?
+            }
+            self.poll_once().await;
+        };
+
+        match response {
+            PairingResponse::Paired => Ok(()),
+            PairingResponse::Denied => Err(PairingError::Denied),
+            PairingResponse::Error(e) => Err(PairingError::Remote(e)),
+        }
+    }
+
+    /// Find the entries which differ between our copy of a served root and that of a peer.
+    /// Starting at the top, only directories whose merkle hashes differ are descended into,
+    /// so when little changed only a few small manifests have to be fetched.
+    ///
+    /// When a directory exists on only one side, it is a single difference: its children aren't listed.
+    pub async fn diff(&mut self, peer: PeerId, root: Uuid) -> Result<Vec<Difference>, ManifestError<GS::Error>> {
+        let local = *self.roots.get(&root).ok_or(ManifestError::NotServed)?;
+        let local_entries = |id: Option<Uuid>, depth| local.entries(id, Some(depth))
+            .map(Option::unwrap_or_default)
+            .map_err(ManifestError::Local);
+
+        let local_top = local_entries(None, 0)?;
+        let remote_top = self.fetch_manifest(peer, root, None, Some(0)).await?;
+        if let (Some(l), Some(r)) = (local_top.first(), remote_top.first()) {
+            if l.hash() == r.hash() {
+                return Ok(Vec::new())
+            }
+        }
+
+        let mut differences = Vec::new();
+        // when a side was never indexed, it has no top and so no children
+        let mut todo = vec![(local_top.first().map(|e| e.id()), remote_top.first().map(|e| e.id()))];
+        while let Some((local_dir, remote_dir)) = todo.pop() {
+            let local_children = match local_dir {
+                Some(id) => local_entries(Some(id), 1)?.into_iter().skip(1).collect(),
+                None => Vec::new(),
+            };
+            let remote_children = match remote_dir {
+                Some(id) => self.fetch_manifest(peer, root, Some(id), Some(1)).await?.into_iter().skip(1).collect(),
+                None => Vec::new(),
+            };
+
+            let (found, descend) = merkle::compare_children(local_children, remote_children);
+            differences.extend(found);
+            todo.extend(descend.into_iter().map(|(l, r)| (Some(l), Some(r))));
+        }
+
+        Ok(differences)
+    }
+
+    /// The [changes](Change) from our copy of a served root to that of a peer. Unlike [`Node::diff`],
+    /// this fetches the whole manifest of the peer, so moved entries can be found.
+    pub async fn changes(&mut self, peer: PeerId, root: Uuid) -> Result<Vec<Change>, ManifestError<GS::Error>> {
+        let local = *self.roots.get(&root).ok_or(ManifestError::NotServed)?;
+        let local = local.entries(None, None)
+            .map(Option::unwrap_or_default)
+            .map_err(ManifestError::Local)?;
+        let remote = self.fetch_manifest(peer, root, None, None).await?;
+
+        Ok(diff::changes(local, remote))
+    }
+
+    /// Called when a peer was discovered at some address. Unknown peers are ignored. For known
+    /// peers the address is recorded in the [`GlobalStore`], and when we share a root with the
+    /// peer and aren't connected to it yet, it is dialed.
+    pub(crate) fn discovered(&mut self, peer_id: PeerId, address: Multiaddr) -> Result<(), NetworkError<GS::Error>> {
+        if peer_id == self.peer_id() {
+            return Ok(())
+        }
+
+        let mut peer = match self.dfs.connection.get_peer_by_peer_id(&peer_id)? {
+            Some(peer) => peer,
+            None => {
+                log::debug!("ignoring unknown peer {} discovered at {}", peer_id, address);
+                return Ok(())
+            }
+        };
+
+        log::info!("discovered peer {} at {}", peer_id, address);
+        peer.seen_at(address.clone());
+        self.dfs.connection.put_peer(peer.id(), &peer, true)?;
+        self.swarm.behaviour_mut().add_address(&peer_id, address.clone());
+
+        if !self.is_connected(&peer_id) && self.shares_root_with(&peer)? {
+            self.dial_addr(address.clone())?;
+        }
+
+        self.pending_events.push_back(NodeEvent::Discovered(peer_id, address));
+        Ok(())
+    }
+
+    fn shares_root_with(&self, peer: &Peer) -> Result<bool, NetworkError<GS::Error>> {
+        for root in self.dfs.connection.get_all_roots()? {
+            if self.dfs.connection.get_root_member(root.id(), peer.id())?.is_some() {
+                return Ok(true)
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_testdir::TempDir;
+
+    use crate::config::Config;
+    use crate::Dfs;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use data_encoding::HEXLOWER;
+    use uuid::Uuid;
+
+    use crate::network::{Node, NodeEvent};
+    use crate::network::blocks::{DownloadError, PARTIAL_DIR};
+    use crate::network::manifest::ManifestError;
+    use crate::network::pairing::PairingError;
+    use crate::network::shared::{SharedRoot, page_of};
+    use crate::compression::Compression;
+    use crate::peer::invite::Invite;
+    use crate::root::UnsafePath;
+    use crate::root::dir_entry::StorableDirEntry;
+    use crate::root::merkle::Hash;
+    use crate::root::share::ShareMode;
+    use crate::root::blocks::{BLOCK_SIZE, FileHashes, hash};
+    use crate::sync::{SyncError, sync};
+    use crate::test::{connect, drive, populated_tempdir, wait_listening};
+
+    #[tokio::test]
+    async fn known_peers_connect() {
+        let global_a = TempDir::new("global network a", true);
+        let global_b = TempDir::new("global network b", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+        dfs_a.add_peer(dfs_b.identity()).unwrap();
+        dfs_b.add_peer(dfs_a.identity()).unwrap();
+
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        let mut node_b = Node::new(&dfs_b).await.unwrap();
+
+        let address_b = wait_listening(&mut node_b).await;
+        node_a.dial_addr(address_b).unwrap();
+
+        let (mut a_connected, mut b_connected) = (false, false);
+        while !(a_connected && b_connected) {
+            tokio::select! {
+                e = node_a.next_event() => if let NodeEvent::Connected(p) = e {
+                    assert_eq!(p, dfs_b.peer_id());
+                    a_connected = true;
+                },
+                e = node_b.next_event() => if let NodeEvent::Connected(p) = e {
+                    assert_eq!(p, dfs_a.peer_id());
+                    b_connected = true;
+                },
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_peers_are_refused() {
+        let global_a = TempDir::new("global network unknown a", true);
+        let global_b = TempDir::new("global network unknown b", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+        // a knows b, but b doesn't know a
+        dfs_a.add_peer(dfs_b.identity()).unwrap();
+
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        let mut node_b = Node::new(&dfs_b).await.unwrap();
+
+        let address_b = wait_listening(&mut node_b).await;
+        node_a.dial_addr(address_b).unwrap();
+
+        loop {
+            tokio::select! {
+                _ = node_a.next_event() => {},
+                e = node_b.next_event() => match e {
+                    NodeEvent::IncomingConnectionFailed(_) => break,
+                    NodeEvent::Connected(p) => panic!("unknown peer {} was accepted", p),
+                    _ => {}
+                },
+            }
+        }
+
+        assert!(!node_b.is_connected(&dfs_a.peer_id()));
+    }
+
+    #[tokio::test]
+    async fn expired_invites_stop_pairing() {
+        let root_dir = TempDir::new("network expired invite root", true);
+        let global_a = TempDir::new("global network expired invite a", true);
+        let global_b = TempDir::new("global network expired invite b", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+        let root = dfs_b.new_root(&root_dir, "b").unwrap();
+        dfs_b.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(1)).unwrap();
+        dfs_a.add_peer(dfs_b.identity()).unwrap();
+
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        let mut node_b = Node::new(&dfs_b).await.unwrap();
+        let address_b = wait_listening(&mut node_b).await;
+
+        // b accepts unknown peers until the invite expires, without reloading it
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+        node_a.dial_addr(address_b).unwrap();
+
+        loop {
+            tokio::select! {
+                _ = node_a.next_event() => {},
+                e = node_b.next_event() => match e {
+                    NodeEvent::IncomingConnectionFailed(_) => break,
+                    NodeEvent::Connected(p) => panic!("unknown peer {} was accepted", p),
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn discovered_peers_are_dialed() {
+        let root_dir = TempDir::new("network discovered root", true);
+        let global_a = TempDir::new("global network discovered a", true);
+        let global_b = TempDir::new("global network discovered b", true);
+        let global_c = TempDir::new("global network discovered c", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+        let dfs_c = Dfs::new(Config::test_config(&global_c)).unwrap();
+
+        let peer_b = dfs_a.add_peer(dfs_b.identity()).unwrap();
+        dfs_b.add_peer(dfs_a.identity()).unwrap();
+        let root = dfs_a.new_root(&root_dir, "a").unwrap();
+        dfs_a.share_root(&root, &peer_b, ShareMode::ReadWrite).unwrap();
+
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        let mut node_b = Node::new(&dfs_b).await.unwrap();
+        let address_b = wait_listening(&mut node_b).await;
+
+        // unknown peers are ignored
+        node_a.discovered(dfs_c.peer_id(), "/ip4/127.0.0.1/tcp/1".parse().unwrap()).unwrap();
+        assert!(dfs_a.get_peer(peer_b.id()).unwrap().unwrap().addresses().is_empty());
+
+        // known peers get their address recorded and are dialed
+        node_a.discovered(dfs_b.peer_id(), address_b.clone()).unwrap();
+        assert_eq!(dfs_a.get_peer(peer_b.id()).unwrap().unwrap().addresses(), &[address_b]);
+
+        loop {
+            tokio::select! {
+                e = node_a.next_event() => if let NodeEvent::Connected(p) = e {
+                    assert_eq!(p, dfs_b.peer_id());
+                    break
+                },
+                _ = node_b.next_event() => {},
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn pairing() {
+        let root_dir = populated_tempdir("network pairing root");
+        let global_a = TempDir::new("global network pairing a", true);
+        let global_b = TempDir::new("global network pairing b", true);
+        let global_c = TempDir::new("global network pairing c", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+        let dfs_c = Dfs::new(Config::test_config(&global_c)).unwrap();
+
+        let root = dfs_a.new_root(&root_dir, "a").unwrap();
+        let expiry = Duration::from_secs(60);
+        let token = dfs_a.create_invite(&root, ShareMode::ReadWrite, expiry).unwrap();
+        // keeps a accepting unknown peers after b redeemed its invite
+        dfs_a.create_invite(&root, ShareMode::ReadWrite, expiry).unwrap();
+        let invite = dfs_b.accept_invite(&token).unwrap();
+
+        let root = root.connect().unwrap();
+        root.index().await.unwrap();
+
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        let mut node_b = Node::new(&dfs_b).await.unwrap();
+        node_a.serve(&root);
+
+        // b isn't known to a, but may connect to present its invite
+        connect(&mut node_a, &mut node_b).await;
+        drive(node_b.pair(&invite), &mut node_a).await.unwrap();
+        assert_eq!(dfs_a.share_mode(root.id(), &dfs_b.peer_id()).unwrap(), Some(ShareMode::ReadWrite));
+
+        let entries = drive(node_b.fetch_manifest(dfs_a.peer_id(), root.id(), None, None), &mut node_a).await.unwrap();
+        assert_eq!(entries.len(), 4);
+
+        // the invite was used up
+        let res = drive(node_b.pair(&invite), &mut node_a).await;
+        assert!(matches!(res, Err(PairingError::Denied)));
+
+        // unknown peers with an invite a didn't create are sent away
+        let forged = Invite::new(dfs_a.identity(), Vec::new(), root.id(), "a".into(), ShareMode::ReadWrite, expiry);
+        let mut node_c = Node::new(&dfs_c).await.unwrap();
+        let address_a = node_a.listen_addresses().remove(0);
+        node_c.swarm.behaviour_mut().add_address(&dfs_a.peer_id(), address_a);
+        let res = drive(node_c.pair(&forged), &mut node_a).await;
+        assert!(matches!(res, Err(PairingError::Denied)), "{:?}", res);
+        assert_eq!(dfs_a.share_mode(root.id(), &dfs_c.peer_id()).unwrap(), None);
+
+        loop {
+            tokio::select! {
+                e = node_c.next_event() => if let NodeEvent::Disconnected(p) = e {
+                    assert_eq!(p, dfs_a.peer_id());
+                    break
+                },
+                _ = node_a.next_event() => {},
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn manifest_exchange() {
+        let root_dir = populated_tempdir("network manifest root");
+        let global_a = TempDir::new("global network manifest a", true);
+        let global_b = TempDir::new("global network manifest b", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+        let root = dfs_a.new_root(&root_dir, "a").unwrap();
+        let token = dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap();
+        let invite = dfs_b.accept_invite(&token).unwrap();
+        let peer_b = dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+
+        let root = root.connect().unwrap();
+        root.index().await.unwrap();
+        let root_id = root.id();
+
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        let mut node_b = Node::new(&dfs_b).await.unwrap();
+        node_a.serve(&root);
+
+        connect(&mut node_a, &mut node_b).await;
+
+        let entries = drive(node_b.fetch_manifest(dfs_a.peer_id(), root_id, None, None), &mut node_a).await.unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].id(), root_id);
+
+        // after unsharing, a refuses to send the manifest
+        dfs_a.unshare_root(&root, &peer_b).unwrap();
+        let res = drive(node_b.fetch_manifest(dfs_a.peer_id(), root_id, None, None), &mut node_a).await;
+        assert!(matches!(res, Err(ManifestError::Denied)));
+    }
+
+    #[tokio::test]
+    async fn requests_dial_known_peers() {
+        let root_dir = populated_tempdir("network dial root");
+        let global_a = TempDir::new("global network dial a", true);
+        let global_b = TempDir::new("global network dial b", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+        let root = dfs_a.new_root(&root_dir, "a").unwrap().connect().unwrap();
+        root.index().await.unwrap();
+
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        node_a.serve(&root);
+        wait_listening(&mut node_a).await;
+
+        // b only knows where a is from the invite, the request dials it
+        let token = node_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap();
+        let invite = dfs_b.accept_invite(&token).unwrap();
+        dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+        node_a.refresh_peers().unwrap();
+        let mut node_b = Node::new(&dfs_b).await.unwrap();
+        assert!(!node_b.is_connected(&dfs_a.peer_id()));
+
+        let entries = drive(node_b.fetch_manifest(dfs_a.peer_id(), root.id(), None, None), &mut node_a).await.unwrap();
+        assert_eq!(entries.len(), 4);
+    }
+
+    /// Serves a root with one more entry, at a path chosen by the test.
+    struct Malicious<'a> {
+        root: &'a dyn SharedRoot,
+        path: &'static str,
+    }
+
+    impl SharedRoot for Malicious<'_> {
+        fn root_id(&self) -> Uuid {
+            self.root.root_id()
+        }
+
+        fn entries(&self, subtree: Option<Uuid>, depth: Option<u32>) -> Result<Option<Vec<StorableDirEntry>>, String> {
+            let mut entries = self.root.entries(subtree, depth)?.unwrap_or_default();
+            entries.push(StorableDirEntry::new(Uuid::new_v4(), self.path.into(), Some(self.root_id()), true, [1; 32]));
+            Ok(Some(entries))
+        }
+
+        fn entries_page(&self, subtree: Option<Uuid>, depth: Option<u32>, after: Option<Uuid>, limit: usize) -> Result<Option<Vec<StorableDirEntry>>, String> {
+            Ok(self.entries(subtree, depth)?.and_then(|entries| page_of(entries, after, limit)))
+        }
+
+        fn block(&self, hash: &Hash) -> Result<Option<Vec<u8>>, String> {
+            self.root.block(hash)
+        }
+
+        fn block_compression(&self, hash: &Hash) -> Result<Compression, String> {
+            self.root.block_compression(hash)
+        }
+
+        fn disk_path(&self, path: &Path) -> Result<PathBuf, UnsafePath> {
+            self.root.disk_path(path)
+        }
+
+        fn local_db(&self) -> PathBuf {
+            self.root.local_db()
+        }
+    }
+
+    #[tokio::test]
+    async fn unsafe_paths_are_rejected() {
+        let root_a = populated_tempdir("network unsafe root a");
+        let root_b = TempDir::new("network unsafe root b", true);
+        let global_a = TempDir::new("global network unsafe a", true);
+        let global_b = TempDir::new("global network unsafe b", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+        let root = dfs_a.new_root(&root_a, "a").unwrap();
+        let token = dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap();
+        let invite = dfs_b.accept_invite(&token).unwrap();
+        dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+
+        let root_a = root.connect().unwrap();
+        root_a.index().await.unwrap();
+        let root_b = dfs_b.join_root(&invite, &root_b, "b").unwrap().connect().unwrap();
+        root_b.index().await.unwrap();
+
+        for path in ["/../escape", "/.dfs/x"] {
+            let malicious = Malicious { root: &root_a, path };
+            let mut node_a = Node::new(&dfs_a).await.unwrap();
+            let mut node_b = Node::new(&dfs_b).await.unwrap();
+            node_a.serve(&malicious);
+            node_b.serve(&root_b);
+            connect(&mut node_a, &mut node_b).await;
+
+            let res = drive(node_b.fetch_manifest(dfs_a.peer_id(), root_b.id(), None, None), &mut node_a).await;
+            assert!(matches!(res, Err(ManifestError::UnsafePath(_))), "{}: {:?}", path, res);
+
+            let res = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await;
+            assert!(matches!(res, Err(SyncError::Manifest(ManifestError::UnsafePath(_)))), "{}: {:?}", path, res);
+        }
+
+        assert!(!root_b.path().join("../escape").exists());
+        assert!(!root_b.path().join(".dfs/x").exists());
+        assert!(root_b.get_by_path("/.dfs/x").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn diff() {
+        let root_a = populated_tempdir("network diff root a");
+        let root_b = populated_tempdir("network diff root b");
+        let global_a = TempDir::new("global network diff a", true);
+        let global_b = TempDir::new("global network diff b", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+        let root = dfs_a.new_root(&root_a, "a").unwrap();
+        let token = dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap();
+        let invite = dfs_b.accept_invite(&token).unwrap();
+        dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+        let root_id = root.id();
+
+        let root_a = root.connect().unwrap();
+        root_a.index().await.unwrap();
+        let root_b = dfs_b.join_root(&invite, &root_b, "b").unwrap().connect().unwrap();
+        fs::write(root_b.path().join("a/ipsum.txt"), "changed").unwrap();
+        root_b.index().await.unwrap();
+
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        let mut node_b = Node::new(&dfs_b).await.unwrap();
+        node_a.serve(&root_a);
+        node_b.serve(&root_b);
+
+        connect(&mut node_a, &mut node_b).await;
+
+        let differences = drive(node_b.diff(dfs_a.peer_id(), root_id), &mut node_a).await.unwrap();
+
+        // the directory which contains the file differs as well
+        let mut paths: Vec<_> = differences.iter().map(|d| d.path.to_str().unwrap()).collect();
+        paths.sort_unstable();
+        assert_eq!(paths, vec!["/a", "/a/ipsum.txt"]);
+    }
+
+    #[tokio::test]
+    async fn download() {
+        let dir_a = TempDir::new("network download root a", true);
+        let dir_b = TempDir::new("network download root b", true);
+        let dir_c = TempDir::new("network download root c", true);
+        let global_a = TempDir::new("global network download a", true);
+        let global_b = TempDir::new("global network download b", true);
+        let global_c = TempDir::new("global network download c", true);
+        let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+        let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+        let dfs_c = Dfs::new(Config::test_config(&global_c)).unwrap();
+
+        // a and c have the same file, b has nothing
+        let contents: Vec<u8> = (0..BLOCK_SIZE * 3 + BLOCK_SIZE / 2).map(|i| (i % 251) as u8).collect();
+        fs::write(dir_a.join("big"), &contents).unwrap();
+        fs::write(dir_c.join("big"), &contents).unwrap();
+
+        let root = dfs_a.new_root(&dir_a, "a").unwrap();
+        let root_id = root.id();
+        let expiry = Duration::from_secs(60);
+        let invite_b = dfs_b.accept_invite(&dfs_a.create_invite(&root, ShareMode::ReadWrite, expiry).unwrap()).unwrap();
+        dfs_a.redeem_invite(invite_b.id(), dfs_b.identity()).unwrap();
+        let invite_c = dfs_c.accept_invite(&dfs_a.create_invite(&root, ShareMode::ReadWrite, expiry).unwrap()).unwrap();
+        dfs_a.redeem_invite(invite_c.id(), dfs_c.identity()).unwrap();
+        let root_c = dfs_c.join_root(&invite_c, &dir_c, "c").unwrap();
+
+        // b and c share the root with each other as well
+        let invite = dfs_b.accept_invite(&dfs_c.create_invite(&root_c, ShareMode::ReadWrite, expiry).unwrap()).unwrap();
+        dfs_c.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+
+        let root_a = root.connect().unwrap();
+        root_a.index().await.unwrap();
+        let root_c = root_c.connect().unwrap();
+        root_c.index().await.unwrap();
+        let root_b = dfs_b.join_root(&invite_b, &dir_b, "b").unwrap().connect().unwrap();
+
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        let mut node_b = Node::new(&dfs_b).await.unwrap();
+        let mut node_c = Node::new(&dfs_c).await.unwrap();
+        node_a.serve(&root_a);
+        node_b.serve(&root_b);
+        node_c.serve(&root_c);
+        connect(&mut node_a, &mut node_b).await;
+        connect(&mut node_c, &mut node_b).await;
+
+        let entries = drive(node_b.fetch_manifest(dfs_a.peer_id(), root_id, None, None), &mut node_a).await.unwrap();
+        let big = entries.iter().find(|e| e.path().to_str() == Some("/big")).unwrap();
+        assert_eq!(big.blocks().len(), 4);
+        let peers = [dfs_a.peer_id(), dfs_c.peer_id()];
+
+        // a size the blocks can't hold is refused before anything is staged
+        let mut forged = big.clone();
+        forged.set_contents(FileHashes { hash: *big.hash(), blocks: big.blocks().to_vec(), size: u64::MAX });
+        let res = node_b.download(root_id, &forged, &peers).await;
+        assert!(matches!(res, Err(DownloadError::TooLarge(u64::MAX))), "{:?}", res);
+
+        // pretend an earlier download was interrupted after the first block
+        let staging = root_b.local_db_path().join(PARTIAL_DIR);
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(staging.join(HEXLOWER.encode(big.hash())), &contents[..BLOCK_SIZE as usize]).unwrap();
+
+        let report = {
+            let download = drive(node_b.download(root_id, big, &peers), &mut node_a);
+            drive(download, &mut node_c).await.unwrap()
+        };
+
+        assert_eq!(report.reused, 1);
+        assert_eq!(report.fetched.values().sum::<usize>(), 3);
+        assert_eq!(report.fetched.len(), 2, "blocks should be fetched from both peers");
+
+        let downloaded = fs::read(dir_b.join("big")).unwrap();
+        assert_eq!(hash(&downloaded), hash(&contents));
+        assert!(fs::read_dir(staging).unwrap().next().is_none());
+    }
+}
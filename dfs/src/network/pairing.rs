@@ -0,0 +1,50 @@
+use libp2p::request_response::OutboundFailure;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::network::codec::{BincodeCodec, Protocol};
+use crate::peer::PeerIdentity;
+
+/// The protocol with which an invited node presents its invite to the inviter.
+pub const PAIRING_PROTOCOL: Protocol = Protocol("/dfs/pairing/1.0.0");
+
+pub type PairingCodec = BincodeCodec<PairingRequest, PairingResponse>;
+
+#[derive(Debug, Error)]
+pub enum PairingError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error("the inviter didn't accept the invite, it is unknown, expired or was already redeemed")]
+    Denied,
+
+    #[error("the inviter failed to redeem the invite: {0}")]
+    Remote(String),
+
+    #[error("request failed: {0}")]
+    Request(OutboundFailure),
+}
+
+/// Ask the inviter to [redeem](crate::Dfs::redeem_invite) an invite. This is the only request
+/// a node answers for peers it doesn't know yet, and only while it has pending invites.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PairingRequest {
+    /// the id of the invite, which only the holder of the invite token knows
+    pub invite: Uuid,
+
+    /// the identity of the invited node, which must be the one the request comes from
+    pub identity: PeerIdentity,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum PairingResponse {
+    /// The invite was redeemed, the root is shared with the invited node.
+    Paired,
+
+    /// The invite is unknown, expired or was already redeemed, or the identity isn't the one
+    /// of the requesting peer.
+    Denied,
+
+    Error(String),
+}
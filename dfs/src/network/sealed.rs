@@ -0,0 +1,224 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use data_encoding::HEXLOWER;
+use libp2p::PeerId;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::compression::Compression;
+use crate::encryption::SealedEntry;
+use crate::global_store::GlobalStore;
+use crate::network::Node;
+use crate::network::blocks::{BlockRequest, BlockResponse};
+use crate::network::manifest::ManifestError;
+use crate::network::shared::{SharedRoot, page_of};
+use crate::root::{UnsafePath, check_path};
+use crate::root::dir_entry::StorableDirEntry;
+use crate::root::merkle::Hash;
+
+/// The file in the folder of a [`SealedRoot`] in which its entries are kept.
+pub const ENTRIES_FILE: &str = "entries";
+
+/// The folder in the folder of a [`SealedRoot`] in which its blocks are kept.
+pub const SEALED_BLOCKS_DIR: &str = "blocks";
+
+#[derive(Debug, Error)]
+pub enum RelayError<GSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] GSE),
+
+    #[error("failed to fetch the manifest: {0}")]
+    Manifest(ManifestError<GSE>),
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+/// What happened while [relaying](Node::relay) a root.
+#[derive(Debug, Default)]
+pub struct RelayReport {
+    /// the number of entries the root has now
+    pub entries: usize,
+
+    /// blocks which were fetched from the peer
+    pub fetched: usize,
+
+    /// blocks the peer couldn't provide, like those of files it doesn't keep on disk
+    pub missing: usize,
+
+    /// blocks which were removed because no entry uses them anymore
+    pub removed: usize,
+}
+
+/// A root kept by a node which only has it [encrypted](crate::root::share::ShareMode::Encrypted):
+/// the [sealed entries](SealedEntry) and the sealed blocks of the files, as a peer with the
+/// [key](crate::encryption::RootKey) sent them. The node can't read any of it, but it can
+/// [serve](Node::serve) the root to the other peers of the root, so they can sync through it
+/// when they can't reach each other.
+///
+/// Of the entries, the node only knows their ids and the [addresses](crate::encryption::RootKey::block_address)
+/// of the blocks of files, under which it keeps the blocks. The peers open the entries and blocks,
+/// and verify them with the hashes inside.
+pub struct SealedRoot {
+    id: Uuid,
+    dir: PathBuf,
+}
+
+impl SealedRoot {
+    /// Keep the root with this id in `dir`. What was relayed to `dir` before is kept.
+    pub fn open(dir: impl AsRef<Path>, id: Uuid) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join(SEALED_BLOCKS_DIR))?;
+
+        Ok(Self {
+            id,
+            dir,
+        })
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// All entries of the root, as they were last [relayed](Node::relay), ordered like the
+    /// [manifest](crate::root::ConnectedRoot::manifest) of the peer.
+    pub fn load_entries(&self) -> io::Result<Vec<SealedEntry>> {
+        match fs::read(self.dir.join(ENTRIES_FILE)) {
+            Ok(bytes) => bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save_entries(&self, entries: &[SealedEntry]) -> io::Result<()> {
+        let bytes = bincode::serialize(entries).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let staging = self.dir.join(format!("{}.tmp", ENTRIES_FILE));
+        fs::write(&staging, bytes)?;
+        fs::rename(staging, self.dir.join(ENTRIES_FILE))
+    }
+
+    fn block_path(&self, address: &Hash) -> PathBuf {
+        self.dir.join(SEALED_BLOCKS_DIR).join(HEXLOWER.encode(address))
+    }
+
+    /// Remove the blocks which none of `entries` uses. Returns how many were removed.
+    fn prune_blocks(&self, entries: &[SealedEntry]) -> io::Result<usize> {
+        let used: HashSet<_> = entries.iter()
+            .flat_map(|e| e.blocks())
+            .map(|hash| HEXLOWER.encode(hash))
+            .collect();
+
+        let mut removed = 0;
+        for file in fs::read_dir(self.dir.join(SEALED_BLOCKS_DIR))? {
+            let file = file?;
+            if !used.contains(file.file_name().to_string_lossy().as_ref()) {
+                fs::remove_file(file.path())?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+impl SharedRoot for SealedRoot {
+    fn root_id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The entries are sealed, so as far as this node knows the root was never indexed.
+    fn entries(&self, _subtree: Option<Uuid>, _depth: Option<u32>) -> Result<Option<Vec<StorableDirEntry>>, String> {
+        Ok(None)
+    }
+
+    fn entries_page(&self, _subtree: Option<Uuid>, _depth: Option<u32>, _after: Option<Uuid>, _limit: usize) -> Result<Option<Vec<StorableDirEntry>>, String> {
+        Ok(None)
+    }
+
+    fn sealed_entries_page(&self, after: Option<Uuid>, limit: usize) -> Result<Option<Vec<SealedEntry>>, String> {
+        let entries = self.load_entries().map_err(|e| e.to_string())?;
+        Ok(page_of(entries, after, limit))
+    }
+
+    /// The block with this address.
+    fn block(&self, address: &Hash) -> Result<Option<Vec<u8>>, String> {
+        match fs::read(self.block_path(address)) {
+            Ok(block) => Ok(Some(block)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn block_compression(&self, _: &Hash) -> Result<Compression, String> {
+        Ok(Compression::None)
+    }
+
+    fn disk_path(&self, path: &Path) -> Result<PathBuf, UnsafePath> {
+        check_path(path, Path::new(""))?;
+        Ok(self.dir.join(path.strip_prefix("/").unwrap_or(path)))
+    }
+
+    fn local_db(&self) -> PathBuf {
+        self.dir.clone()
+    }
+
+    fn sealed(&self) -> bool {
+        true
+    }
+}
+
+impl<'dfs, GS: GlobalStore> Node<'dfs, GS> {
+    /// Fetch the entries and blocks of a root from a peer, sealed, and keep them in `root`.
+    /// Blocks which were fetched before aren't fetched again, and blocks which are no longer
+    /// used are removed. Serve the root to pass it on to the other peers of the root.
+    pub async fn relay(&mut self, peer: PeerId, root: &SealedRoot) -> Result<RelayReport, RelayError<GS::Error>> {
+        let entries = self.fetch_sealed_manifest(peer, root.id()).await.map_err(RelayError::Manifest)?;
+
+        let mut report = RelayReport::default();
+        // where each missing block is used, which is how the peer finds it
+        let mut wanted: HashMap<Hash, (Uuid, u32)> = HashMap::new();
+        for entry in &entries {
+            for (index, address) in entry.blocks().iter().enumerate() {
+                if !root.block_path(address).exists() {
+                    wanted.entry(*address).or_insert((entry.id(), index as u32));
+                }
+            }
+        }
+
+        for (address, location) in wanted {
+            let request_id = self.swarm.behaviour_mut().blocks.send_request(&peer, BlockRequest {
+                root: root.id(),
+                hash: address,
+                location: Some(location),
+            });
+
+            let response = loop {
+                if let Some(response) = self.block_responses.remove(&request_id) {
+                    break response
+                }
+                self.poll_once().await;
+            };
+
+            match response {
+                Ok(BlockResponse::Sealed(sealed)) => {
+                    let path = root.block_path(&address);
+                    fs::write(&path, sealed).map_err(|e| RelayError::Io(path, e))?;
+                    report.fetched += 1;
+                }
+                response => {
+                    log::debug!("{} didn't provide sealed block {}: {:?}", peer, HEXLOWER.encode(&address), response);
+                    report.missing += 1;
+                }
+            }
+        }
+
+        root.save_entries(&entries).map_err(|e| RelayError::Io(root.dir.join(ENTRIES_FILE), e))?;
+        report.entries = entries.len();
+        report.removed = root.prune_blocks(&entries).map_err(|e| RelayError::Io(root.dir.join(SEALED_BLOCKS_DIR), e))?;
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use crate::compression::Compression;
+use crate::encryption::SealedEntry;
+use crate::global_store::GlobalStore;
+use crate::network::manifest::PageEntry;
+use crate::root::{ConnectedRoot, UnsafePath};
+use crate::root::dir_entry::StorableDirEntry;
+use crate::root::local_store::LocalStore;
+use crate::root::merkle::Hash;
+
+/// A root which a [`Node`](crate::network::Node) serves to its peers. This is implemented
+/// by [`ConnectedRoot`], and only exists so a node can serve roots with different
+/// [`LocalStore`]s at the same time.
+pub trait SharedRoot {
+    /// The uuid of the root, see [`StorableRoot::id`](crate::root::StorableRoot::id).
+    fn root_id(&self) -> Uuid;
+
+    /// See [`ConnectedRoot::manifest`].
+    fn entries(&self, subtree: Option<Uuid>, depth: Option<u32>) -> Result<Option<Vec<StorableDirEntry>>, String>;
+
+    /// See [`ConnectedRoot::manifest_page`].
+    fn entries_page(&self, subtree: Option<Uuid>, depth: Option<u32>, after: Option<Uuid>, limit: usize) -> Result<Option<Vec<StorableDirEntry>>, String>;
+
+    /// See [`ConnectedRoot::read_block`].
+    fn block(&self, hash: &Hash) -> Result<Option<Vec<u8>>, String>;
+
+    /// How blocks with this hash are compressed when they're sent, see [`ConnectedRoot::compression`].
+    fn block_compression(&self, hash: &Hash) -> Result<Compression, String>;
+
+    /// See [`ConnectedRoot::path_on_disk`].
+    fn disk_path(&self, path: &Path) -> Result<PathBuf, UnsafePath>;
+
+    /// See [`ConnectedRoot::local_db_path`].
+    fn local_db(&self) -> PathBuf;
+
+    /// Whether the entries and blocks of this root are sealed, see [`SealedRoot`](crate::network::sealed::SealedRoot).
+    /// Sealed roots list their entries with [`sealed_entries_page`](SharedRoot::sealed_entries_page),
+    /// and know their blocks by their [addresses](crate::encryption::RootKey::block_address).
+    fn sealed(&self) -> bool {
+        false
+    }
+
+    /// A page of the entries of a sealed root, like [`entries_page`](SharedRoot::entries_page)
+    /// for the whole root.
+    fn sealed_entries_page(&self, _after: Option<Uuid>, _limit: usize) -> Result<Option<Vec<SealedEntry>>, String> {
+        Ok(None)
+    }
+}
+
+impl<'dfs, GS: GlobalStore, LS: LocalStore> SharedRoot for ConnectedRoot<'dfs, GS, LS> {
+    fn root_id(&self) -> Uuid {
+        self.id()
+    }
+
+    fn entries(&self, subtree: Option<Uuid>, depth: Option<u32>) -> Result<Option<Vec<StorableDirEntry>>, String> {
+        self.manifest(subtree, depth)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn entries_page(&self, subtree: Option<Uuid>, depth: Option<u32>, after: Option<Uuid>, limit: usize) -> Result<Option<Vec<StorableDirEntry>>, String> {
+        self.manifest_page(subtree, depth, after, limit)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn block(&self, hash: &Hash) -> Result<Option<Vec<u8>>, String> {
+        self.read_block(hash)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn block_compression(&self, hash: &Hash) -> Result<Compression, String> {
+        match self.block_entries(hash).map_err(|e| format!("{:?}", e))?.first() {
+            Some((entry, _)) => self.compression(entry.path()).map_err(|e| format!("{:?}", e)),
+            None => Ok(Compression::None),
+        }
+    }
+
+    fn disk_path(&self, path: &Path) -> Result<PathBuf, UnsafePath> {
+        self.path_on_disk(path)
+    }
+
+    fn local_db(&self) -> PathBuf {
+        self.local_db_path()
+    }
+}
+
+/// The page of `entries` which starts after the entry with id `after`, for roots which have all
+/// their entries at hand anyway. Like [`ConnectedRoot::manifest_page`], returns None when `after`
+/// isn't one of the entries.
+pub(crate) fn page_of<E: PageEntry>(entries: Vec<E>, after: Option<Uuid>, limit: usize) -> Option<Vec<E>> {
+    let start = match after {
+        Some(after) => entries.iter().position(|e| e.id() == after)? + 1,
+        None => 0,
+    };
+
+    Some(entries.into_iter().skip(start).take(limit).collect())
+}
+
+/// The entries under the entry with id `subtree`, up to `depth` levels deep, from all `entries`
+/// of a root in the order of [`ConnectedRoot::manifest`], for roots which only have all their
+/// entries at hand. Returns None when there's no such subtree.
+pub(crate) fn subtree_of(entries: &[StorableDirEntry], subtree: Option<Uuid>, depth: Option<u32>) -> Option<Vec<StorableDirEntry>> {
+    let top = match subtree {
+        Some(id) => entries.iter().find(|e| e.id() == id)?,
+        None => match entries.first() {
+            Some(top) => top,
+            None => return Some(Vec::new()),
+        },
+    };
+
+    // the entries are in the order of the manifest, so the children are kept in order
+    let mut children: HashMap<Uuid, Vec<&StorableDirEntry>> = HashMap::new();
+    for entry in entries {
+        if let Some(parent) = entry.parent_id() {
+            children.entry(parent).or_default().push(entry);
+        }
+    }
+
+    let mut res = Vec::new();
+    let mut todo = vec![(top, 0)];
+    while let Some((entry, level)) = todo.pop() {
+        res.push(entry.clone());
+        if depth.is_none_or(|depth| level < depth) {
+            let below = children.get(&entry.id()).into_iter().flatten().rev();
+            todo.extend(below.map(|child| (*child, level + 1)));
+        }
+    }
+
+    Some(res)
+}
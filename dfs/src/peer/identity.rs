@@ -0,0 +1,127 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use libp2p::identity::{ed25519, Keypair};
+use libp2p::identity::error::DecodingError;
+use thiserror::Error;
+
+/// Name of the file (inside the global store directory) which holds
+/// the private key of the local node.
+pub const IDENTITY_FILE: &str = "identity.key";
+
+#[derive(Debug, Error)]
+pub enum IdentityError {
+    #[error("failed to read or write the identity file at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+
+    #[error("the identity file at {0:?} doesn't contain a valid ed25519 keypair: {1}")]
+    Decode(PathBuf, DecodingError),
+}
+
+/// Load the keypair of the local node from `path`. When no such file exists, a new
+/// Ed25519 keypair is generated and stored there.
+///
+/// The private key never leaves this file. On unix, the file is only readable and writable
+/// by its owner (mode `0600`).
+///
+/// ```
+/// # use temp_testdir::TempDir;
+/// use dfs::peer::identity::load_or_generate;
+///
+/// let tempdir = TempDir::new("test", true);
+/// let path = tempdir.join("identity.key");
+///
+/// let first = load_or_generate(&path).unwrap();
+/// let second = load_or_generate(&path).unwrap();
+///
+/// assert_eq!(first.public(), second.public());
+/// ```
+pub fn load_or_generate(path: &Path) -> Result<Keypair, IdentityError> {
+    if path.exists() {
+        load(path)
+    } else {
+        let keypair = ed25519::Keypair::generate();
+        store(path, &keypair)?;
+        Ok(Keypair::Ed25519(keypair))
+    }
+}
+
+fn load(path: &Path) -> Result<Keypair, IdentityError> {
+    let io_err = |e| IdentityError::Io(path.to_path_buf(), e);
+
+    restrict_permissions(path).map_err(io_err)?;
+
+    let mut bytes = Vec::new();
+    fs::File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(io_err)?;
+
+    ed25519::Keypair::decode(&mut bytes)
+        .map(Keypair::Ed25519)
+        .map_err(|e| IdentityError::Decode(path.to_path_buf(), e))
+}
+
+fn store(path: &Path, keypair: &ed25519::Keypair) -> Result<(), IdentityError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)
+        .and_then(|mut f| f.write_all(&keypair.encode()))
+        .map_err(|e| IdentityError::Io(path.to_path_buf(), e))
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path)?.permissions();
+    if permissions.mode() & 0o077 != 0 {
+        log::warn!("identity file at {:?} was readable by others, restricting permissions", path);
+        permissions.set_mode(0o600);
+        fs::set_permissions(path, permissions)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_testdir::TempDir;
+
+    use crate::peer::identity::load_or_generate;
+
+    #[cfg(unix)]
+    #[test]
+    fn identity_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tempdir = TempDir::new("identity permissions", true);
+        let path = tempdir.join("identity.key");
+
+        load_or_generate(&path).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn corrupt_identity_file() {
+        let tempdir = TempDir::new("identity corrupt", true);
+        let path = tempdir.join("identity.key");
+        std::fs::write(&path, b"not a key").unwrap();
+
+        assert!(load_or_generate(&path).is_err());
+    }
+}
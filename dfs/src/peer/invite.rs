@@ -0,0 +1,224 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use data_encoding::BASE32_NOPAD;
+use libp2p::identity::Keypair;
+use libp2p::identity::error::SigningError;
+use libp2p::Multiaddr;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::peer::PeerIdentity;
+use crate::root::share::ShareMode;
+
+#[derive(Debug, Error)]
+pub enum InviteError {
+    #[error("invite token isn't valid base32: {0}")]
+    Encoding(#[from] data_encoding::DecodeError),
+
+    #[error("invite token is malformed: {0}")]
+    Bincode(#[from] bincode::Error),
+
+    #[error("failed to sign invite: {0}")]
+    Signing(#[from] SigningError),
+
+    #[error("the signature of the invite doesn't match the inviter")]
+    InvalidSignature,
+
+    #[error("the invite has expired")]
+    Expired,
+}
+
+/// An invite to a root. An invite is created by a node sharing a root, and handed
+/// (out of band) to the node it wants to share the root with as a token.
+///
+/// The token is signed with the key of the inviter. When it is accepted, the
+/// inviter becomes a peer and the root is shared with it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Invite {
+    id: Uuid,
+    inviter: PeerIdentity,
+    addresses: Vec<Multiaddr>,
+    root: Uuid,
+    root_name: String,
+    /// how the inviter shares the root with the invitee
+    mode: ShareMode,
+    /// seconds since the unix epoch
+    expires: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedInvite {
+    invite: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Invite {
+    pub(crate) fn new(inviter: PeerIdentity, addresses: Vec<Multiaddr>, root: Uuid, root_name: String, mode: ShareMode, expiry: Duration) -> Self {
+        // an expiry too far in the future to represent never expires
+        let expires = match SystemTime::now().checked_add(expiry) {
+            Some(expires) => expires.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            None => u64::MAX,
+        };
+
+        Self {
+            id: Uuid::new_v4(),
+            inviter,
+            addresses,
+            root,
+            root_name,
+            mode,
+            expires,
+        }
+    }
+
+    /// Unique id of this invite, used by the inviter to recognize its own invites.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn inviter(&self) -> &PeerIdentity {
+        &self.inviter
+    }
+
+    /// Addresses the inviter listened on when it created the invite.
+    pub fn addresses(&self) -> &[Multiaddr] {
+        &self.addresses
+    }
+
+    /// Id of the root this invite is for. This id is the same on every peer of the root.
+    pub fn root(&self) -> Uuid {
+        self.root
+    }
+
+    /// Name of the root at the inviter.
+    pub fn root_name(&self) -> &str {
+        &self.root_name
+    }
+
+    /// How the inviter shares the root. The invitee should use the [inverse](ShareMode::inverse).
+    pub fn mode(&self) -> ShareMode {
+        self.mode
+    }
+
+    /// When the invite expires, in seconds since the unix epoch.
+    pub(crate) fn expires(&self) -> u64 {
+        self.expires
+    }
+
+    pub fn is_expired(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(u64::MAX);
+
+        now > self.expires
+    }
+
+    /// Sign this invite and encode it as a token. The keypair must be the one of the inviter.
+    pub(crate) fn to_token(&self, keypair: &Keypair) -> Result<String, InviteError> {
+        let invite = bincode::serialize(self)?;
+        let signature = keypair.sign(&invite)?;
+
+        let signed = bincode::serialize(&SignedInvite {
+            invite,
+            signature,
+        })?;
+
+        Ok(BASE32_NOPAD.encode(&signed))
+    }
+
+    /// Decode a token created with [`Dfs::create_invite`](crate::Dfs::create_invite). This
+    /// verifies the signature and checks whether the invite has expired.
+    pub fn from_token(token: &str) -> Result<Self, InviteError> {
+        let bytes = BASE32_NOPAD.decode(token.trim().as_bytes())?;
+        let signed: SignedInvite = bincode::deserialize(&bytes)?;
+        let invite: Invite = bincode::deserialize(&signed.invite)?;
+
+        if !invite.inviter.public_key().verify(&signed.invite, &signed.signature) {
+            return Err(InviteError::InvalidSignature)
+        }
+
+        if invite.is_expired() {
+            return Err(InviteError::Expired)
+        }
+
+        Ok(invite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use data_encoding::BASE32_NOPAD;
+    use libp2p::identity::Keypair;
+    use uuid::Uuid;
+
+    use crate::peer::PeerIdentity;
+    use crate::peer::invite::{Invite, InviteError, SignedInvite};
+    use crate::root::share::ShareMode;
+
+    fn invite(keypair: &Keypair, expiry: Duration) -> Invite {
+        let identity = PeerIdentity::new("inviter".to_string(), keypair.public());
+        Invite::new(identity, Vec::new(), Uuid::new_v4(), "root".to_string(), ShareMode::ReadWrite, expiry)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let keypair = Keypair::generate_ed25519();
+        let invite = invite(&keypair, Duration::from_secs(60));
+
+        let token = invite.to_token(&keypair).unwrap();
+        let decoded = Invite::from_token(&token).unwrap();
+
+        assert_eq!(decoded.id(), invite.id());
+        assert_eq!(decoded.root(), invite.root());
+    }
+
+    #[test]
+    fn wrong_signer() {
+        let keypair = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519();
+        let invite = invite(&keypair, Duration::from_secs(60));
+
+        let token = invite.to_token(&other).unwrap();
+        assert!(matches!(Invite::from_token(&token), Err(InviteError::InvalidSignature)));
+    }
+
+    #[test]
+    fn tampered() {
+        let keypair = Keypair::generate_ed25519();
+        let invite = invite(&keypair, Duration::from_secs(60));
+        let token = invite.to_token(&keypair).unwrap();
+
+        let mut signed: SignedInvite = bincode::deserialize(&BASE32_NOPAD.decode(token.as_bytes()).unwrap()).unwrap();
+        let last = signed.invite.len() - 1;
+        signed.invite[last] ^= 1;
+        let token = BASE32_NOPAD.encode(&bincode::serialize(&signed).unwrap());
+
+        assert!(Invite::from_token(&token).is_err());
+    }
+
+    #[test]
+    fn expired() {
+        let keypair = Keypair::generate_ed25519();
+        let mut invite = invite(&keypair, Duration::from_secs(0));
+        invite.expires -= 10;
+
+        let token = invite.to_token(&keypair).unwrap();
+        assert!(matches!(Invite::from_token(&token), Err(InviteError::Expired)));
+    }
+
+    #[test]
+    fn never_expires() {
+        let keypair = Keypair::generate_ed25519();
+        let invite = invite(&keypair, Duration::MAX);
+
+        assert!(!invite.is_expired());
+        let token = invite.to_token(&keypair).unwrap();
+        assert!(Invite::from_token(&token).is_ok());
+    }
+}
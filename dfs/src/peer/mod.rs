@@ -1,18 +1,36 @@
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
+use libp2p::identity::PublicKey;
+use libp2p::{Multiaddr, PeerId};
 
-#[derive(Serialize, Deserialize)]
+pub mod identity;
+pub mod invite;
+
+/// The maximum number of addresses remembered per peer.
+pub const MAX_ADDRESSES: usize = 8;
+
+/// A peer is a dfs node identified by its public key. Every DFS has exactly one local peer
+/// (itself, see [`Dfs::local_peer`](crate::Dfs::local_peer)), all other peers are remote.
+/// The [`PeerId`] of a peer is derived from this key, so connections to a peer
+/// can be authenticated. Only the public half of a key is ever stored in a peer.
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Peer {
     uuid: Uuid,
     name: String,
+    #[serde(with = "public_key")]
+    public_key: PublicKey,
+    /// Addresses this peer was last known to be reachable at.
+    addresses: Vec<Multiaddr>,
 }
 
 impl Peer {
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, public_key: PublicKey) -> Self {
         let uuid = Uuid::new_v4();
         Self {
             uuid,
             name,
+            public_key,
+            addresses: Vec::new(),
         }
     }
 
@@ -23,5 +41,141 @@ impl Peer {
     pub fn id(&self) -> Uuid {
         self.uuid
     }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn addresses(&self) -> &[Multiaddr] {
+        &self.addresses
+    }
+
+    /// Remember an address this peer is reachable at. Returns false when the
+    /// address was already known.
+    pub fn add_address(&mut self, address: Multiaddr) -> bool {
+        if self.addresses.contains(&address) {
+            false
+        } else {
+            self.addresses.push(address);
+            true
+        }
+    }
+
+    /// Record that this peer was just seen at an address. The most recently seen
+    /// addresses come first, and only the last [`MAX_ADDRESSES`] are kept.
+    ///
+    /// ```
+    /// # use dfs::peer::Peer;
+    /// # use libp2p::identity::Keypair;
+    /// let mut peer = Peer::new("jonathan".to_string(), Keypair::generate_ed25519().public());
+    ///
+    /// peer.seen_at("/ip4/10.0.0.1/tcp/4242".parse().unwrap());
+    /// peer.seen_at("/ip4/10.0.0.2/tcp/4242".parse().unwrap());
+    /// peer.seen_at("/ip4/10.0.0.1/tcp/4242".parse().unwrap());
+    ///
+    /// assert_eq!(peer.addresses().len(), 2);
+    /// assert_eq!(peer.addresses()[0], "/ip4/10.0.0.1/tcp/4242".parse().unwrap());
+    /// ```
+    pub fn seen_at(&mut self, address: Multiaddr) {
+        self.addresses.retain(|a| a != &address);
+        self.addresses.insert(0, address);
+        self.addresses.truncate(MAX_ADDRESSES);
+    }
+
+    /// The libp2p [`PeerId`] of this peer, derived from its public key.
+    ///
+    /// ```
+    /// # use dfs::peer::Peer;
+    /// use libp2p::identity::Keypair;
+    ///
+    /// let keypair = Keypair::generate_ed25519();
+    /// let peer = Peer::new("jonathan".to_string(), keypair.public());
+    ///
+    /// assert_eq!(peer.peer_id(), keypair.public().into_peer_id());
+    /// ```
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key.clone().into_peer_id()
+    }
+
+    /// The public identity of this peer, which can be handed to other nodes.
+    pub fn identity(&self) -> PeerIdentity {
+        PeerIdentity {
+            name: self.name.clone(),
+            public_key: self.public_key.clone(),
+        }
+    }
 }
 
+/// The public part of a peer: everything another node needs to know to add it as a peer.
+/// Remote peers are never created from scratch, they are always imported from
+/// the identity they exported themselves (see [`Dfs::identity`](crate::Dfs::identity)).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PeerIdentity {
+    name: String,
+    #[serde(with = "public_key")]
+    public_key: PublicKey,
+}
+
+impl PeerIdentity {
+    pub fn new(name: String, public_key: PublicKey) -> Self {
+        Self {
+            name,
+            public_key,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key.clone().into_peer_id()
+    }
+
+    /// Encode this identity so it can be exchanged out of band.
+    ///
+    /// ```
+    /// # use dfs::peer::PeerIdentity;
+    /// use libp2p::identity::Keypair;
+    ///
+    /// let identity = PeerIdentity::new("jonathan".to_string(), Keypair::generate_ed25519().public());
+    /// let decoded = PeerIdentity::from_bytes(&identity.to_bytes()).unwrap();
+    ///
+    /// assert_eq!(decoded.name(), "jonathan");
+    /// assert_eq!(decoded.peer_id(), identity.peer_id());
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // serializing a struct of a string and bytes can't fail
+        bincode::serialize(self).expect("failed to serialize peer identity")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, bincode::Error> {
+        bincode::deserialize(bytes)
+    }
+}
+
+impl From<PeerIdentity> for Peer {
+    fn from(identity: PeerIdentity) -> Self {
+        Peer::new(identity.name, identity.public_key)
+    }
+}
+
+/// (De)serializes a [`PublicKey`] as its protobuf encoding.
+mod public_key {
+    use libp2p::identity::PublicKey;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(key: &PublicKey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&key.clone().into_protobuf_encoding())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PublicKey, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        PublicKey::from_protobuf_encoding(&bytes).map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::root::merkle::Hash;
+
+/// Files are split into blocks of this size (only the last block of a file may be smaller).
+/// Blocks are the unit in which file contents are transferred between peers.
+pub const BLOCK_SIZE: u64 = 256 * 1024;
+
+/// Hash some data the way blocks and files are hashed.
+pub fn hash(data: &[u8]) -> Hash {
+    *blake3::hash(data).as_bytes()
+}
+
+/// The hashes of a file, see [`hash_file`].
+#[derive(Debug, Clone, Default)]
+pub struct FileHashes {
+    /// hash of the whole contents
+    pub hash: Hash,
+
+    /// hash of every block of the file, in order
+    pub blocks: Vec<Hash>,
+
+    pub size: u64,
+}
+
+/// Hash the contents of a file, and every [block](BLOCK_SIZE) of it.
+pub fn hash_file(path: impl AsRef<Path>) -> io::Result<FileHashes> {
+    let mut hasher = FileHasher::default();
+    for block in read_blocks(path)? {
+        hasher.update(&block?);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Computes the [`FileHashes`] of a file from its blocks, in order.
+#[derive(Default)]
+pub struct FileHasher {
+    hasher: blake3::Hasher,
+    hashes: FileHashes,
+}
+
+impl FileHasher {
+    /// Add the next block of the file. Returns the hash of the block.
+    pub fn update(&mut self, block: &[u8]) -> Hash {
+        let block_hash = hash(block);
+        self.hasher.update(block);
+        self.hashes.blocks.push(block_hash);
+        self.hashes.size += block.len() as u64;
+        block_hash
+    }
+
+    pub fn finish(mut self) -> FileHashes {
+        self.hashes.hash = *self.hasher.finalize().as_bytes();
+        self.hashes
+    }
+}
+
+/// The [blocks](BLOCK_SIZE) of a file, in order. See [`read_blocks`].
+pub struct Blocks {
+    file: File,
+}
+
+impl Iterator for Blocks {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut block = Vec::with_capacity(BLOCK_SIZE as usize);
+        match (&mut self.file).take(BLOCK_SIZE).read_to_end(&mut block) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(block)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Read a file block by block.
+pub fn read_blocks(path: impl AsRef<Path>) -> io::Result<Blocks> {
+    Ok(Blocks { file: File::open(path)? })
+}
+
+/// The length of block `index` of a file with this size.
+pub fn block_len(size: u64, index: usize) -> u64 {
+    let offset = index as u64 * BLOCK_SIZE;
+    size.saturating_sub(offset).min(BLOCK_SIZE)
+}
+
+/// Where the contents of a block can be found in a root. Stored in the
+/// [`LocalStore`](crate::root::local_store::LocalStore) by block hash.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BlockLocation {
+    /// the file entry containing the block
+    pub entry: Uuid,
+
+    /// index of the block within the file
+    pub index: u32,
+}
+
+/// Read block `index` of a file, and check that it still has the expected hash. Returns
+/// None when the file changed since it was indexed.
+pub fn read_block(path: impl AsRef<Path>, index: u32, expected: &Hash) -> io::Result<Option<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(index as u64 * BLOCK_SIZE))?;
+
+    let mut block = Vec::with_capacity(BLOCK_SIZE as usize);
+    file.take(BLOCK_SIZE).read_to_end(&mut block)?;
+
+    Ok(if &hash(&block) == expected { Some(block) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use temp_testdir::TempDir;
+
+    use crate::root::blocks::{BLOCK_SIZE, block_len, hash_file, read_block};
+
+    #[test]
+    fn blocks() {
+        let dir = TempDir::new("test blocks", true);
+        let path = dir.join("file");
+        let contents: Vec<u8> = (0..BLOCK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        fs::write(&path, &contents).unwrap();
+
+        let hashes = hash_file(&path).unwrap();
+        assert_eq!(hashes.blocks.len(), 3);
+        assert_eq!(hashes.size, contents.len() as u64);
+        assert_eq!(hashes.hash, *blake3::hash(&contents).as_bytes());
+        assert_eq!(block_len(hashes.size, 2), 10);
+        assert_eq!(block_len(hashes.size, 3), 0);
+
+        let last = read_block(&path, 2, &hashes.blocks[2]).unwrap().unwrap();
+        assert_eq!(last, &contents[BLOCK_SIZE as usize * 2..]);
+        assert!(read_block(&path, 1, &hashes.blocks[2]).unwrap().is_none());
+    }
+}
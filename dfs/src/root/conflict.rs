@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use data_encoding::HEXLOWER;
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+
+use crate::root::merkle::Hash;
+
+/// A file which two peers changed concurrently. The version which won is kept at the path
+/// of the file, the other one is kept next to it in a conflict copy (see [`copy_path`]),
+/// until the conflict is [resolved](crate::root::ConnectedRoot::resolve_conflict).
+///
+/// Which version wins, and the name of the copy, don't depend on which peer detects the
+/// conflict, so all peers end up with the same file and the same copy.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Conflict {
+    path: PathBuf,
+    copy: PathBuf,
+    /// the bytes of the id of the peer whose version lost
+    peer: Vec<u8>,
+    /// seconds since the unix epoch
+    detected: u64,
+}
+
+/// How to resolve a [`Conflict`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Resolution {
+    /// Keep the version at the path of the file and delete the copy.
+    KeepCurrent,
+    /// Replace the file with the version in the copy.
+    KeepCopy,
+}
+
+impl Conflict {
+    pub(crate) fn new(path: PathBuf, copy: PathBuf, peer: &PeerId, detected: SystemTime) -> Self {
+        let detected = detected.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            path,
+            copy,
+            peer: peer.to_bytes(),
+            detected,
+        }
+    }
+
+    /// The (root relative) path of the file which had the conflict.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The (root relative) path of the conflict copy with the version which lost.
+    pub fn copy(&self) -> &Path {
+        &self.copy
+    }
+
+    /// The peer whose version lost.
+    pub fn peer(&self) -> PeerId {
+        PeerId::from_bytes(&self.peer).expect("invalid peer id in conflict")
+    }
+
+    /// When the conflict was detected on this node.
+    pub fn detected(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.detected)
+    }
+}
+
+/// Where the losing version of a file in a conflict is kept: next to the file, with (the start
+/// of) the hash of the losing version and (the end of the id of) the peer which made the change
+/// in the name.
+///
+/// ```
+/// # use std::path::Path;
+/// # use libp2p::PeerId;
+/// # use dfs::root::conflict::copy_path;
+/// let peer = PeerId::random();
+/// let copy = copy_path(Path::new("/docs/report.txt"), &[0xab; 32], &peer);
+///
+/// let name = copy.file_name().unwrap().to_str().unwrap();
+/// assert!(copy.starts_with("/docs"));
+/// assert!(name.starts_with("report.sync-conflict-abababab-"));
+/// assert!(name.ends_with(".txt"));
+/// ```
+pub fn copy_path(path: &Path, hash: &Hash, peer: &PeerId) -> PathBuf {
+    let hash = HEXLOWER.encode(&hash[..4]);
+
+    let peer = peer.to_base58();
+    // all ed25519 peer ids start the same, the end is what tells them apart
+    let peer = &peer[peer.len().saturating_sub(7)..];
+
+    with_suffix(path, &format!("sync-conflict-{}-{}", hash, peer))
+}
+
+/// The `n`th alternative for a conflict copy at `copy`, for when a file conflicts again with
+/// the same version while the earlier copy is still there.
+pub(crate) fn numbered_copy_path(copy: &Path, n: usize) -> PathBuf {
+    with_suffix(copy, &n.to_string())
+}
+
+/// `path`, with `suffix` added to the name of the file before its extension.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}.{}", stem, suffix);
+    if let Some(extension) = path.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+
+    path.with_file_name(name)
+}
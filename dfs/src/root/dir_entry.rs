@@ -1,19 +1,39 @@
 use crate::root::{ConnectedRoot, GetDirEntryError};
+use std::ffi::OsStr;
 use std::path::{PathBuf, Path};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use crate::global_store::GlobalStore;
 use std::ops::{Deref, DerefMut};
 use serde::{Serialize, Deserialize};
 use crate::root::local_store::LocalStore;
+use crate::root::blocks::FileHashes;
+use crate::root::merkle::{Hash, TOMBSTONE};
+use crate::root::version::VersionVector;
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
 pub enum DirEntryType {
     Dir,
     File
 }
 
+/// Whether the contents of an entry are on disk in this copy of the root.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Presence {
+    /// The entry is on disk.
+    Local,
+
+    /// An empty file stands in for the file on disk. Its contents are only downloaded when it
+    /// is [hydrated](crate::root::ConnectedRoot::hydrate).
+    Placeholder,
+
+    /// The entry isn't [selected](crate::root::selection::Selection), so it isn't on disk.
+    /// Only its metadata is kept.
+    Excluded,
+}
+
 /// Storable version of a [`DirEntry`]. For documentation refer to [`DirEntry`]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct StorableDirEntry {
     /// the name of this entry. This name is a relative path to the dfs root
     path: PathBuf,
@@ -26,9 +46,49 @@ pub struct StorableDirEntry {
 
     /// optional id of the parent of this entry
     parent: Option<Uuid>,
+
+    /// the hash of the contents of a file, or the merkle hash of the children of a directory.
+    /// See [`merkle`](crate::root::merkle).
+    hash: Hash,
+
+    /// the hashes of the [blocks](crate::root::blocks) of a file. Empty for directories.
+    blocks: Vec<Hash>,
+
+    /// the size of a file in bytes. 0 for directories.
+    size: u64,
+
+    /// which peers changed this entry, see [`VersionVector`].
+    version: VersionVector,
+
+    /// when the entry was deleted, in seconds since the unix epoch. Deleted entries are kept as
+    /// tombstones, so the deletion can be synced to other peers.
+    deleted: Option<u64>,
+
+    /// whether the contents of this entry are on disk here. Not synced: it only describes this copy.
+    presence: Presence,
+
+    /// the inode of the entry on disk when it was last indexed, so it's recognized when it is
+    /// moved. Not synced either.
+    inode: Option<u64>,
 }
 
 impl StorableDirEntry {
+    pub(crate) fn new(uuid: Uuid, path: PathBuf, parent: Option<Uuid>, is_dir: bool, hash: Hash) -> Self {
+        Self {
+            path,
+            entry_type: if is_dir { DirEntryType::Dir } else { DirEntryType::File },
+            uuid,
+            parent,
+            hash,
+            blocks: Vec::new(),
+            size: 0,
+            version: VersionVector::default(),
+            deleted: None,
+            presence: Presence::Local,
+            inode: None,
+        }
+    }
+
     /// Returns whether or not this entry is a directory
     ///
     /// ```
@@ -52,7 +112,7 @@ impl StorableDirEntry {
     /// assert!(entry.is_dir());
     /// ```
     pub fn is_dir(&self) -> bool {
-        matches!(self.entry_type, DirEntryType::Dir{..})
+        matches!(self.entry_type, DirEntryType::Dir)
     }
 
     /// Returns whether or not this entry is a file
@@ -109,6 +169,119 @@ impl StorableDirEntry {
         self.path.as_path()
     }
 
+    /// The last component of the path of this entry. Empty for the top level directory of the root.
+    pub fn name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_default()
+    }
+
+    pub(crate) fn set_path(&mut self, path: PathBuf) {
+        self.path = path;
+    }
+
+    /// The hash of the contents of this entry. For directories, this is the
+    /// [merkle hash](crate::root::merkle::dir_hash) of its children.
+    pub fn hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    pub(crate) fn set_hash(&mut self, hash: Hash) {
+        self.hash = hash;
+    }
+
+    /// The hashes of the [blocks](crate::root::blocks) of this file, in order.
+    pub fn blocks(&self) -> &[Hash] {
+        &self.blocks
+    }
+
+    /// The size of this file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The version of this entry, which tells which changes to it are known here.
+    pub fn version(&self) -> &VersionVector {
+        &self.version
+    }
+
+    pub(crate) fn version_mut(&mut self) -> &mut VersionVector {
+        &mut self.version
+    }
+
+    /// Give this entry the same contents as `other`. The changes which led to those contents
+    /// are merged into the version of this entry.
+    pub(crate) fn copy_contents(&mut self, other: &StorableDirEntry) {
+        self.hash = other.hash;
+        self.blocks = other.blocks.clone();
+        self.size = other.size;
+        self.deleted = other.deleted;
+        self.version.merge(&other.version);
+    }
+
+    /// Whether this entry is a tombstone of a deleted file or directory.
+    pub fn is_deleted(&self) -> bool {
+        self.deleted.is_some()
+    }
+
+    /// When this entry was deleted, if it is a tombstone.
+    pub fn deleted_at(&self) -> Option<SystemTime> {
+        self.deleted.map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Turn this entry into a tombstone. The version isn't changed.
+    pub(crate) fn tombstone(&mut self, at: SystemTime) {
+        self.deleted = Some(at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+        self.hash = TOMBSTONE;
+        self.blocks = Vec::new();
+        self.size = 0;
+        self.presence = Presence::Local;
+        self.inode = None;
+    }
+
+    /// Bring a tombstone back to life. Its hash has to be set again.
+    pub(crate) fn undelete(&mut self) {
+        self.deleted = None;
+    }
+
+    /// Whether the contents of this entry are on disk here.
+    pub fn presence(&self) -> Presence {
+        self.presence
+    }
+
+    pub(crate) fn set_presence(&mut self, presence: Presence) {
+        self.presence = presence;
+    }
+
+    /// Whether the contents of this entry are on disk here, so they can be sent to peers.
+    pub fn is_local(&self) -> bool {
+        self.presence == Presence::Local
+    }
+
+    /// Whether an empty file stands in for this file on disk, see [`Presence::Placeholder`].
+    pub fn is_placeholder(&self) -> bool {
+        self.presence == Presence::Placeholder
+    }
+
+    /// Whether only the metadata of this entry is known here, because it isn't
+    /// [selected](crate::root::selection::Selection). Excluded entries aren't on disk.
+    pub fn is_excluded(&self) -> bool {
+        self.presence == Presence::Excluded
+    }
+
+    /// The inode of this entry on disk when it was last indexed, where inodes exist.
+    pub fn inode(&self) -> Option<u64> {
+        self.inode
+    }
+
+    pub(crate) fn set_inode(&mut self, inode: Option<u64>) {
+        self.inode = inode;
+    }
+
+    pub(crate) fn set_contents(&mut self, hashes: FileHashes) {
+        self.hash = hashes.hash;
+        self.blocks = hashes.blocks;
+        self.size = hashes.size;
+    }
+
     /// Return whether or not this direntry is the top level directory of the root.
     ///
     /// ```
@@ -131,16 +304,26 @@ impl StorableDirEntry {
     /// // Not the standard way to make DirEntries. Usually you use `index` on a root
     /// // to have it collect the entries for you.
     /// let entry = DirEntry::new(&connected_root, "/test".into(), None, true);
-    /// assert_eq!(entry.is_root());
+    /// assert!(entry.is_root());
     /// ```
     pub fn is_root(&self) -> bool {
         self.parent.is_none()
     }
 
-    #[doc(hidden)]
-    pub(crate) fn id(&self) -> Uuid {
+    /// The id of this entry. Ids are unique within a root.
+    pub fn id(&self) -> Uuid {
         self.uuid
     }
+
+    /// The id of the parent of this entry, or None for the top level directory of the root.
+    /// To get the parent entry itself, use [`DirEntry::parent`].
+    pub fn parent_id(&self) -> Option<Uuid> {
+        self.parent
+    }
+
+    pub(crate) fn set_parent(&mut self, parent: Option<Uuid>) {
+        self.parent = parent;
+    }
 }
 
 pub struct DirEntry<'root, 'dfs, GS, LS> {
@@ -172,17 +355,15 @@ impl<'root, 'dfs, GS: GlobalStore, LS: LocalStore> DirEntry<'root, 'dfs, GS, LS>
     }
 
     pub fn new(root: &'root ConnectedRoot<'dfs, GS, LS>, path: PathBuf, parent: Option<Uuid>, is_dir: bool) -> Self {
-        let uuid = Uuid::new_v4();
+        Self::with_id(root, Uuid::new_v4(), path, parent, is_dir)
+    }
 
-        Self::from_storable(
-            root,
-            StorableDirEntry {
-                path,
-                entry_type: if is_dir { DirEntryType::Dir } else { DirEntryType::File },
-                uuid,
-                parent
-            }
-        )
+    pub(crate) fn with_id(root: &'root ConnectedRoot<'dfs, GS, LS>, uuid: Uuid, path: PathBuf, parent: Option<Uuid>, is_dir: bool) -> Self {
+        Self::from_storable(root, StorableDirEntry::new(uuid, path, parent, is_dir, Hash::default()))
+    }
+
+    pub(crate) fn root(&self) -> &'root ConnectedRoot<'dfs, GS, LS> {
+        self.root
     }
 
     pub fn parent(&self) -> Result<Option<DirEntry<'root, 'dfs, GS, LS>>, GetDirEntryError<LS::Error>> {
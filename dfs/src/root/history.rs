@@ -0,0 +1,254 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::GetRootError;
+use crate::block_store::BlockStoreError;
+use crate::compression::Compression;
+use crate::global_store::GlobalStore;
+use crate::root::{ConnectedRoot, UnsafePath};
+use crate::root::blocks::{FileHasher, FileHashes, read_blocks};
+use crate::root::dir_entry::{DirEntry, StorableDirEntry};
+use crate::root::local_store::LocalStore;
+use crate::root::merkle::Hash;
+
+#[derive(Debug, Error)]
+pub enum HistoryError<GSE, LSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] LSE),
+
+    #[error("block store error: {0}")]
+    BlockStore(BlockStoreError<GSE>),
+
+    #[error("the contents of the version are no longer in the block store")]
+    Missing,
+
+    #[error("{0:?} isn't a file")]
+    NotAFile(PathBuf),
+
+    #[error("the version isn't a version of {0:?}")]
+    OtherFile(PathBuf),
+
+    #[error("{0}")]
+    UnsafePath(UnsafePath),
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+/// A previous version of a file, kept when a sync replaced the contents of the file. The blocks
+/// of the contents are kept in the [block store](crate::block_store::BLOCKS_DIR), which is shared
+/// by all roots. How many versions are kept, and for how long, is
+/// [configured](crate::config::Config::versions_kept).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileVersion {
+    id: Uuid,
+    /// the entry of the file as it was
+    entry: StorableDirEntry,
+    /// milliseconds since the unix epoch
+    saved: u64,
+}
+
+impl FileVersion {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The entry of the file when this version was saved, with the hashes and size of its contents.
+    pub fn entry(&self) -> &StorableDirEntry {
+        &self.entry
+    }
+
+    pub fn hash(&self) -> &Hash {
+        self.entry.hash()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.entry.size()
+    }
+
+    /// When this version was replaced.
+    pub fn saved(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.saved)
+    }
+}
+
+impl<'dfs, GS: GlobalStore, LS: LocalStore> ConnectedRoot<'dfs, GS, LS> {
+    /// Keep the contents the file of `entry` has on disk as a version of it, before they are
+    /// replaced. Nothing is kept for files which aren't on disk. Returns whether a version was kept.
+    pub(crate) fn save_version(&self, entry: &StorableDirEntry) -> Result<bool, HistoryError<GS::Error, LS::Error>> {
+        if entry.is_dir() || !entry.is_local() || entry.is_deleted() || self.dfs.cfg().versions_kept == 0 {
+            return Ok(false)
+        }
+
+        // the file may have changed since the root was last indexed
+        let compression = match self.compression(entry.path()) {
+            Ok(compression) => compression,
+            Err(GetRootError::CompileStatement(e)) => return Err(HistoryError::BlockStore(e.into())),
+        };
+        let hashes = match self.store_blocks(&self.path_on_disk(entry.path()).map_err(HistoryError::UnsafePath)?, compression)? {
+            Some(hashes) => hashes,
+            None => return Ok(false),
+        };
+        let mut entry = entry.clone();
+        entry.set_contents(hashes);
+
+        let saved = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.connection.put_file_version(&FileVersion {
+            id: Uuid::new_v4(),
+            entry: entry.clone(),
+            saved,
+        })?;
+
+        self.prune_versions(entry.id())?;
+        Ok(true)
+    }
+
+    /// Put the blocks of the file at `path` in the block store. Returns None when there is no file.
+    fn store_blocks(&self, path: &Path, compression: Compression) -> Result<Option<FileHashes>, HistoryError<GS::Error, LS::Error>> {
+        let blocks = match read_blocks(path) {
+            Ok(blocks) => blocks,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(HistoryError::Io(path.to_path_buf(), e)),
+        };
+
+        let mut hasher = FileHasher::default();
+        let mut stored = Vec::new();
+        for block in blocks {
+            let res = block.map_err(|e| HistoryError::Io(path.to_path_buf(), e))
+                .and_then(|block| {
+                    let hash = hasher.update(&block);
+                    self.dfs.store_block(&hash, &block, compression).map_err(HistoryError::BlockStore)?;
+                    stored.push(hash);
+                    Ok(())
+                });
+
+            if let Err(e) = res {
+                // nothing uses the blocks stored so far
+                for hash in &stored {
+                    if let Err(e) = self.dfs.release_block(hash) {
+                        log::warn!("failed to release a block: {:?}", e);
+                    }
+                }
+                return Err(e)
+            }
+        }
+
+        Ok(Some(hasher.finish()))
+    }
+
+    /// Write the contents of a kept version to `to`. Returns false when some of its blocks
+    /// are no longer in the block store.
+    pub(crate) fn write_version(&self, version: &FileVersion, to: &Path) -> Result<bool, HistoryError<GS::Error, LS::Error>> {
+        let io_err = |e| HistoryError::Io(to.to_path_buf(), e);
+        let mut file = File::create(to).map_err(io_err)?;
+        for hash in version.entry.blocks() {
+            match self.dfs.stored_block(hash).map_err(HistoryError::BlockStore)? {
+                Some(block) => file.write_all(&block).map_err(io_err)?,
+                None => {
+                    drop(file);
+                    fs::remove_file(to).map_err(io_err)?;
+                    return Ok(false)
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// All kept versions of the file with id `entry`, oldest first.
+    pub(crate) fn file_versions(&self, entry: Uuid) -> Result<Vec<FileVersion>, LS::Error> {
+        let mut versions = self.connection.get_file_versions(entry)?;
+        versions.sort_by_key(|v| v.saved);
+        Ok(versions)
+    }
+
+    /// Remove the versions of a file which are too old, and the oldest ones when there are
+    /// more than are to be kept.
+    fn prune_versions(&self, entry: Uuid) -> Result<(), HistoryError<GS::Error, LS::Error>> {
+        let cfg = self.dfs.cfg();
+        // a retention reaching before the epoch keeps versions forever
+        let expired_before = SystemTime::now().checked_sub(cfg.version_retention);
+
+        let versions = self.file_versions(entry)?;
+        let excess = versions.len().saturating_sub(cfg.versions_kept);
+        for (i, version) in versions.iter().enumerate() {
+            if i < excess || expired_before.is_some_and(|before| version.saved() < before) {
+                self.remove_version(version)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_version(&self, version: &FileVersion) -> Result<(), HistoryError<GS::Error, LS::Error>> {
+        if self.connection.remove_file_version(version.entry.id(), version.id)?.is_some() {
+            for hash in version.entry.blocks() {
+                self.dfs.release_block(hash).map_err(HistoryError::BlockStore)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forget all versions of the file with id `entry`, when the entry itself is removed.
+    pub(crate) fn remove_versions(&self, entry: Uuid) -> Result<(), LS::Error> {
+        for version in self.connection.get_file_versions(entry)? {
+            if let Err(e) = self.remove_version(&version) {
+                log::warn!("failed to remove version of {:?}: {:?}", version.entry.path(), e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'root, 'dfs, GS: GlobalStore, LS: LocalStore> DirEntry<'root, 'dfs, GS, LS> {
+    /// The previous versions of this file which are kept, oldest first.
+    pub fn versions(&self) -> Result<Vec<FileVersion>, HistoryError<GS::Error, LS::Error>> {
+        if self.is_dir() {
+            return Err(HistoryError::NotAFile(self.path().to_path_buf()))
+        }
+
+        Ok(self.root().file_versions(self.id())?)
+    }
+
+    /// Bring back a previous version of this file. The current contents are kept as a version
+    /// in turn. Restoring is a change by this peer, so the peers take the restored contents over
+    /// when they sync.
+    pub fn restore(&self, version: &FileVersion) -> Result<(), HistoryError<GS::Error, LS::Error>> {
+        let root = self.root();
+        if self.is_dir() {
+            return Err(HistoryError::NotAFile(self.path().to_path_buf()))
+        }
+        if version.entry.id() != self.id() {
+            return Err(HistoryError::OtherFile(self.path().to_path_buf()))
+        }
+
+        // staged first, so the version survives when it's pruned while saving the current contents
+        let staged = root.local_db_path().join(format!("{}.restore", version.id));
+        if !root.write_version(version, &staged)? {
+            return Err(HistoryError::Missing)
+        }
+
+        let current = root.connection.get_direntry(self.id())?;
+        if let Some(current) = &current {
+            root.save_version(current)?;
+        }
+
+        let on_disk = root.path_on_disk(self.path()).map_err(HistoryError::UnsafePath)?;
+        fs::rename(&staged, &on_disk).map_err(|e| HistoryError::Io(on_disk, e))?;
+
+        let contents = root.local_change(self.path(), version.entry.clone())?;
+        root.put_file(self.path(), &contents)?;
+
+        Ok(())
+    }
+}
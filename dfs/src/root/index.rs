@@ -1,5 +1,10 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::iter;
 use std::sync::Arc;
-use std::path::PathBuf;
+use std::time::SystemTime;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
 use tokio::{io, fs};
 use crate::root::{GetRootEntryError, ConnectedRoot};
 use tokio::sync::Mutex;
@@ -7,13 +12,16 @@ use tokio::sync::mpsc::{channel, unbounded_channel, Sender, UnboundedSender, Rec
 use tokio::sync::oneshot::{channel as oneshot_channel, Sender as OneshotSender};
 use std::sync::atomic::{AtomicUsize, Ordering, AtomicBool};
 use tokio::select;
-use tokio::task::spawn;
+use tokio::task::{spawn, spawn_blocking};
 use thiserror::Error;
-use crate::root::dir_entry::DirEntry;
+use crate::root::dir_entry::{DirEntry, Presence, StorableDirEntry};
 use crate::global_store::GlobalStore;
 use crate::root::local_store::LocalStore;
+use crate::root::blocks::{FileHashes, hash_file};
+use crate::root::merkle::{Hash, dir_hash};
 use std::ops::Deref;
 use uuid::Uuid;
+use libp2p::PeerId;
 
 #[derive(Debug, Error)]
 #[error("couldn't index at {path}: {error}")]
@@ -64,18 +72,21 @@ pub struct Inner {
     queued: AtomicUsize,
     spawned: AtomicUsize,
     root_id: Uuid,
+    /// the folder with the local store of the root, which is never indexed
+    local_db: PathBuf,
     pub task_done_tx: Sender<()>,
 }
 
 impl Inner {
-    async fn index_direntry(&self, entry: fs::DirEntry, parent_id: Uuid) -> Uuid {
+    async fn index_direntry(&self, path: PathBuf, is_dir: bool, inode: Option<u64>, hashes: FileHashes, parent_id: Uuid) -> Uuid {
         let (resp_tx, resp_rx) = oneshot_channel();
 
-        let path = entry.path();
-
         if let Err(err) = self.db_tx.send(DbMessage {
             resp: resp_tx,
-            entry,
+            path: path.clone(),
+            is_dir,
+            inode,
+            hashes,
             parent_id,
         }).await {
             log::error!("couldn't send db msg {:?}", err)
@@ -114,11 +125,40 @@ impl Inner {
         let mut dir = non_fatal!(fs::read_dir(&task.path).await);
         while let Some(entry) = non_fatal!(dir.next_entry().await) {
             let path = entry.path();
-            let identifier = self.index_direntry(entry, task.parent_id).await;
+            if path == self.local_db {
+                continue
+            }
+
+            let is_dir = path.is_dir();
+            let inode = entry.metadata().await.ok().and_then(|m| inode(&m));
+            let hashes = if is_dir {
+                // directories get their hash from their children once indexing is done.
+                // Until then, they're treated as empty.
+                FileHashes {
+                    hash: dir_hash(&[]),
+                    ..FileHashes::default()
+                }
+            } else {
+                let file = path.clone();
+                match spawn_blocking(move || hash_file(file)).await {
+                    Ok(Ok(hashes)) => hashes,
+                    Ok(Err(error)) => {
+                        self.errors.lock().await.push(NonFatalIndexError { path, error });
+                        continue
+                    }
+                    Err(e) => {
+                        let error = io::Error::other(e);
+                        self.errors.lock().await.push(NonFatalIndexError { path, error });
+                        continue
+                    }
+                }
+            };
+
+            let identifier = self.index_direntry(path.clone(), is_dir, inode, hashes, task.parent_id).await;
 
             log::debug!("indexed direntry at {:?}", path);
 
-            if path.is_dir() {
+            if is_dir {
                 if let Err(err) = self.todo_queue_tx.send(Task {
                     path,
                     parent_id: identifier
@@ -142,10 +182,25 @@ impl Inner {
 #[derive(Debug)]
 struct DbMessage {
     resp: OneshotSender<Uuid>,
-    entry: fs::DirEntry,
+    path: PathBuf,
+    is_dir: bool,
+    inode: Option<u64>,
+    hashes: FileHashes,
     parent_id: Uuid,
 }
 
+/// The inode of a file or directory, on platforms which have them.
+#[cfg(unix)]
+pub(crate) fn inode(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.ino())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn inode(_meta: &Metadata) -> Option<u64> {
+    None
+}
+
 pub(crate) struct Indexer<'dfs, 'root, GS, LS: LocalStore> {
     inner: Arc<Inner>,
 
@@ -162,7 +217,26 @@ pub(crate) struct Indexer<'dfs, 'root, GS, LS: LocalStore> {
 
     // There will never actually be contention over this mutex
     // because it will never be accessed concurrently.
-    root: &'root ConnectedRoot<'dfs, GS, LS>
+    root: &'root ConnectedRoot<'dfs, GS, LS>,
+
+    /// Entries which were already in the store, by parent and then by path. Filled
+    /// the first time a child of a parent is indexed.
+    existing: Mutex<HashMap<Uuid, HashMap<PathBuf, StorableDirEntry>>>,
+
+    /// Ids of all entries which still exist on disk. Entries not in here after indexing are removed.
+    seen: Mutex<HashSet<Uuid>>,
+
+    /// Directories of which a child was added, changed or removed, so their hash must be updated.
+    dirty: Mutex<HashSet<Uuid>>,
+
+    /// Ids of the entries which were on disk before indexing, by inode and, for files which
+    /// aren't empty, by hash. Entries which appear at a new path are looked up here, to find
+    /// the ones which were moved.
+    inodes: HashMap<u64, Uuid>,
+    hashes: HashMap<Hash, Vec<Uuid>>,
+
+    /// Changes found while indexing are made by this peer. See [`VersionVector`](crate::root::version::VersionVector).
+    local_peer: PeerId,
 }
 
 impl<'dfs, 'root, GS: GlobalStore, LS: LocalStore> Indexer<'dfs, 'root, GS, LS> {
@@ -175,6 +249,20 @@ impl<'dfs, 'root, GS: GlobalStore, LS: LocalStore> Indexer<'dfs, 'root, GS, LS>
         let (task_done_tx, task_done_rx) = channel(1024);
 
         let root_id = root.root_dir()?.id();
+
+        let mut inodes = HashMap::new();
+        let mut hashes: HashMap<_, Vec<_>> = HashMap::new();
+        for entry in root.connection.get_all_direntries()? {
+            if entry.is_deleted() || entry.is_excluded() || entry.parent_id().is_none() {
+                continue
+            }
+            if let Some(inode) = entry.inode() {
+                inodes.insert(inode, entry.id());
+            }
+            if entry.is_file() && entry.is_local() && entry.size() > 0 {
+                hashes.entry(*entry.hash()).or_default().push(entry.id());
+            }
+        }
         if let Err(err) = todo_queue_tx.send(Task {
             path: root.path().clone(),
             parent_id: root_id
@@ -194,13 +282,20 @@ impl<'dfs, 'root, GS: GlobalStore, LS: LocalStore> Indexer<'dfs, 'root, GS, LS>
                 // one is queued already at the start (the root)
                 queued: AtomicUsize::new(1),
                 spawned: AtomicUsize::new(0),
-                root_id
+                root_id,
+                local_db: root.local_db_path(),
             }),
             fatal_errors_rx: Some(fatal_errors_rx),
             task_done_rx: Some(task_done_rx),
             db_rx: Some(db_rx),
             todo_queue_rx: Mutex::new(todo_queue_rx),
             root,
+            existing: Mutex::new(HashMap::new()),
+            seen: Mutex::new(iter::once(root_id).collect()),
+            dirty: Mutex::new(HashSet::new()),
+            inodes,
+            hashes,
+            local_peer: root.dfs().peer_id(),
         })
     }
 
@@ -226,24 +321,176 @@ impl<'dfs, 'root, GS: GlobalStore, LS: LocalStore> Indexer<'dfs, 'root, GS, LS>
     }
 
     async fn handle_db_message(&self, msg: DbMessage) -> Result<(), IndexError<LS::Error>> {
+        // paths of entries are relative to the root, starting with a `/`
+        let relative = pathdiff::diff_paths(&msg.path, self.root.path())
+            .unwrap_or_default();
+        let path = Path::new("/").join(relative);
+
+        let mut existing = self.existing.lock().await;
+        let siblings = match existing.entry(msg.parent_id) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(
+                self.root.connection.get_children(msg.parent_id)?
+                    .into_iter()
+                    .map(|i| (i.path().to_path_buf(), i))
+                    .collect()
+            ),
+        };
+
+        let mut found = siblings.remove(&path);
+
+        // an entry which is new at this path may have been moved here, from where it's gone now
+        if found.as_ref().is_none_or(StorableDirEntry::is_deleted) {
+            if let Some(moved) = self.moved_entry(&msg).await? {
+                found = self.root.move_path(moved.path(), &path, None)?;
+
+                // a tombstone took its place, which the old parent has to load again
+                if let Some(old_parent) = moved.parent_id() {
+                    existing.remove(&old_parent);
+                }
+                let mut dirty = self.dirty.lock().await;
+                dirty.extend(moved.parent_id());
+                dirty.insert(msg.parent_id);
+            }
+        }
+
+        let id = match found {
+            Some(old) if old.is_dir() == msg.is_dir && old.is_deleted() => {
+                // the entry was deleted before, and now it's back
+                let mut entry = old.clone();
+                entry.undelete();
+                entry.set_inode(msg.inode);
+                if !msg.is_dir {
+                    entry.set_contents(msg.hashes);
+                }
+                entry.version_mut().bump(&self.local_peer);
+                self.root.connection.put_direntry(entry.id(), &entry, true)?;
+                self.root.replace_blocks(Some(&old), Some(&entry))?;
+
+                let mut dirty = self.dirty.lock().await;
+                dirty.insert(msg.parent_id);
+                if msg.is_dir {
+                    // its hash is still that of a tombstone
+                    dirty.insert(entry.id());
+                }
 
-        let entry = DirEntry::new(
-            self.root,
-            Default::default(),
-            Some(msg.parent_id),
-            msg.entry.path().is_dir()
-        );
+                old.id()
+            }
+            Some(old) if old.is_dir() == msg.is_dir => {
+                // an empty file in place of a placeholder is the placeholder itself
+                let stub = old.is_placeholder() && msg.hashes.size == 0;
+                // directories are rehashed from their children later
+                let changed = !msg.is_dir && !stub && old.hash() != &msg.hashes.hash;
+                // an entry which wasn't on disk, but is now, is local from now on
+                if changed || (!old.is_local() && !stub) || old.inode() != msg.inode {
+                    let mut entry = old.clone();
+                    if !stub {
+                        entry.set_presence(Presence::Local);
+                    }
+                    entry.set_inode(msg.inode);
+                    if changed {
+                        entry.set_contents(msg.hashes);
+                        entry.version_mut().bump(&self.local_peer);
+                        self.dirty.lock().await.insert(msg.parent_id);
+                    }
+                    self.root.connection.put_direntry(entry.id(), &entry, true)?;
+                    self.root.replace_blocks(Some(&old), Some(&entry))?;
+                }
+
+                old.id()
+            }
+            old => {
+                // a new entry, or one which changed from a file into a directory or vice versa.
+                // In the last case the old entry is replaced, without leaving a tombstone.
+                if let Some(old) = old {
+                    self.root.remove_subtree(old)?;
+                }
 
-        self.root.connection.put_direntry(entry.id(), entry.deref(), false)?
-            .to_err(|| IndexError::Exists)?;
+                let mut entry = DirEntry::new(self.root, path, Some(msg.parent_id), msg.is_dir);
+                entry.set_contents(msg.hashes);
+                entry.set_inode(msg.inode);
+                entry.version_mut().bump(&self.local_peer);
 
-        if let Err(err) = msg.resp.send(entry.id()) {
+                self.root.connection.put_direntry(entry.id(), entry.deref(), false)?
+                    .to_err(|| IndexError::Exists)?;
+                self.root.replace_blocks(None, Some(&entry))?;
+                self.dirty.lock().await.insert(msg.parent_id);
+
+                entry.id()
+            }
+        };
+
+        self.seen.lock().await.insert(id);
+
+        if let Err(err) = msg.resp.send(id) {
             log::error!("couldn't send response (id={})", err)
         };
 
         Ok(())
     }
 
+    /// Find the entry which was moved to where `msg` was found: one which isn't at its old path
+    /// anymore, with the same inode or otherwise the same contents.
+    async fn moved_entry(&self, msg: &DbMessage) -> Result<Option<StorableDirEntry>, LS::Error> {
+        let by_inode = msg.inode.and_then(|inode| self.inodes.get(&inode));
+        let by_hash = self.hashes.get(&msg.hashes.hash).filter(|_| !msg.is_dir && msg.hashes.size > 0);
+        let candidates = by_inode.into_iter().chain(by_hash.into_iter().flatten());
+
+        let seen = self.seen.lock().await;
+        for id in candidates {
+            if seen.contains(id) {
+                continue
+            }
+            let entry = match self.root.connection.get_direntry(*id)? {
+                Some(entry) if !entry.is_deleted() && entry.is_dir() == msg.is_dir => entry,
+                _ => continue,
+            };
+
+            let on_disk = match self.root.path_on_disk(entry.path()) {
+                Ok(on_disk) => on_disk,
+                Err(_) => continue,
+            };
+            let same_inode = msg.inode.is_some() && msg.inode == entry.inode();
+            let gone = match fs::symlink_metadata(on_disk).await {
+                Err(e) => e.kind() == io::ErrorKind::NotFound,
+                // something else took its place
+                Ok(meta) => same_inode && inode(&meta) != msg.inode,
+            };
+            if gone {
+                return Ok(Some(entry))
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Remove entries which weren't seen during indexing, and update the hashes of all
+    /// directories whose contents changed.
+    async fn finish(&self) -> Result<(), IndexError<LS::Error>> {
+        let seen = self.seen.lock().await;
+        let mut dirty = self.dirty.lock().await;
+
+        // entries which disappeared are kept as tombstones, so the deletion reaches other peers.
+        // Excluded entries were never on disk in the first place.
+        let now = SystemTime::now();
+        for entry in self.root.connection.get_all_direntries()? {
+            if !seen.contains(&entry.id()) && !entry.is_deleted() && !entry.is_excluded() {
+                let mut tombstone = entry.clone();
+                tombstone.tombstone(now);
+                tombstone.version_mut().bump(&self.local_peer);
+                self.root.connection.put_direntry(tombstone.id(), &tombstone, true)?;
+                self.root.replace_blocks(Some(&entry), None)?;
+                dirty.extend(entry.parent_id());
+            }
+        }
+
+        for dir in dirty.iter() {
+            self.root.rehash_from(*dir)?;
+        }
+
+        Ok(())
+    }
+
     pub(crate) async fn index(mut self) -> Result<(), IndexError<LS::Error>> {
         // unwrap safe because we can only call index once
         let mut fatal_error = self.fatal_errors_rx.take().unwrap();
@@ -290,6 +537,8 @@ impl<'dfs, 'root, GS: GlobalStore, LS: LocalStore> Indexer<'dfs, 'root, GS, LS>
             }
         };
 
+        self.finish().await?;
+
         log::info!("done");
         Ok(())
     }
@@ -1,16 +1,55 @@
-use std::path::Path;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
 
-use heed::{Database, Env, EnvOpenOptions};
-use heed::types::SerdeBincode;
+use libp2p::PeerId;
+
+use heed::{Database, Env, EnvOpenOptions, RwTxn};
+use heed::types::{SerdeBincode, Str, UnalignedSlice, Unit};
 use uuid::Uuid;
 
 use crate::global_store::PutStatus;
-use crate::root::local_store::LocalStore;
+use crate::root::local_store::{LocalStore, base_key, base_prefix, block_key, block_location, block_prefix, version_key};
+use crate::root::blocks::BlockLocation;
+use crate::root::conflict::Conflict;
 use crate::root::dir_entry::StorableDirEntry;
+use crate::root::history::FileVersion;
+use crate::root::snapshot::Snapshot;
+use crate::root::merkle::Hash;
 
 pub struct Heed {
     env: Env,
     direntries: Database<SerdeBincode<Uuid>, SerdeBincode<StorableDirEntry>>,
+    /// parent uuid bytes ++ child uuid bytes -> ()
+    children: Database<UnalignedSlice<u8>, Unit>,
+    /// see [`block_key`]
+    blocks: Database<UnalignedSlice<u8>, Unit>,
+    /// see [`base_key`]
+    base: Database<UnalignedSlice<u8>, SerdeBincode<Hash>>,
+    /// path of the conflict copy -> conflict
+    conflicts: Database<Str, SerdeBincode<Conflict>>,
+    /// see [`version_key`]
+    versions: Database<UnalignedSlice<u8>, SerdeBincode<FileVersion>>,
+    snapshots: Database<SerdeBincode<Uuid>, SerdeBincode<Snapshot>>,
+}
+
+fn child_key(parent: Uuid, child: Uuid) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..16].copy_from_slice(parent.as_bytes());
+    key[16..].copy_from_slice(child.as_bytes());
+    key
+}
+
+impl Heed {
+    fn link_parent(&self, txn: &mut RwTxn, old: Option<&StorableDirEntry>, new: &StorableDirEntry) -> Result<(), heed::Error> {
+        if let Some(old_parent) = old.and_then(|o| o.parent_id()) {
+            self.children.delete(txn, &child_key(old_parent, new.id()))?;
+        }
+        if let Some(parent) = new.parent_id() {
+            self.children.put(txn, &child_key(parent, new.id()), &())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl LocalStore for Heed {
@@ -18,13 +57,19 @@ impl LocalStore for Heed {
 
     fn new(path: &Path) -> Result<Self, Self::Error> {
         let env = EnvOpenOptions::new()
-            .max_dbs(3)
+            .max_dbs(8)
             .map_size(2 * 1024 * 1024 * 1024)
             .open(path)?;
 
 
         Ok(Self {
             direntries: env.create_database(Some("direntries"))?,
+            children: env.create_database(Some("children"))?,
+            blocks: env.create_database(Some("block_locations"))?,
+            base: env.create_database(Some("base"))?,
+            conflicts: env.create_database(Some("conflicts"))?,
+            versions: env.create_database(Some("versions"))?,
+            snapshots: env.create_database(Some("snapshots"))?,
             env,
         })
     }
@@ -32,11 +77,13 @@ impl LocalStore for Heed {
     fn put_direntry(&self, id: Uuid, dir: &StorableDirEntry, overwrite: bool) -> Result<PutStatus, Self::Error> {
         let mut txn = self.env.write_txn()?;
 
-        // if !overwrite && (self.direntries.get(&txn, &id)?.is_some()) {
-        //     return Ok(PutStatus::Exists)
-        // }
+        let old = self.direntries.get(&txn, &id)?;
+        if !overwrite && old.is_some() {
+            return Ok(PutStatus::Exists)
+        }
 
         self.direntries.put(&mut txn, &id, dir)?;
+        self.link_parent(&mut txn, old.as_ref(), dir)?;
 
         txn.commit()?;
 
@@ -48,4 +95,212 @@ impl LocalStore for Heed {
         let res = self.direntries.get(&txn, &id)?;
         Ok(res)
     }
+
+    fn remove_direntry(&self, id: Uuid) -> Result<Option<StorableDirEntry>, Self::Error> {
+        let mut txn = self.env.write_txn()?;
+
+        let old = self.direntries.get(&txn, &id)?;
+        if let Some(old) = &old {
+            self.direntries.delete(&mut txn, &id)?;
+            if let Some(parent) = old.parent_id() {
+                self.children.delete(&mut txn, &child_key(parent, id))?;
+            }
+        }
+
+        txn.commit()?;
+
+        Ok(old)
+    }
+
+    fn get_children(&self, parent: Uuid) -> Result<Vec<StorableDirEntry>, Self::Error> {
+        let txn = self.env.read_txn()?;
+
+        let mut res = Vec::new();
+        for i in self.children.prefix_iter(&txn, parent.as_bytes())? {
+            let (key, _) = i?;
+            // keys are always 32 bytes, see `child_key`
+            let child = Uuid::from_slice(&key[16..]).expect("invalid child key");
+
+            if let Some(entry) = self.direntries.get(&txn, &child)? {
+                res.push(entry);
+            }
+        }
+
+        Ok(res)
+    }
+
+    fn get_next_child(&self, parent: Uuid, after: Option<Uuid>) -> Result<Option<StorableDirEntry>, Self::Error> {
+        let txn = self.env.read_txn()?;
+
+        let after = after.map(|after| child_key(parent, after));
+        let start = match &after {
+            Some(after) => Bound::Excluded(&after[..]),
+            None => Bound::Included(&parent.as_bytes()[..]),
+        };
+        for i in self.children.range(&txn, &(start, Bound::Unbounded))? {
+            let (key, _) = i?;
+            if !key.starts_with(parent.as_bytes()) {
+                break
+            }
+            let child = Uuid::from_slice(&key[16..]).expect("invalid child key");
+
+            if let Some(entry) = self.direntries.get(&txn, &child)? {
+                return Ok(Some(entry))
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn get_all_direntries(&self) -> Result<Vec<StorableDirEntry>, Self::Error> {
+        let txn = self.env.read_txn()?;
+
+        let entries = self.direntries.iter(&txn)?
+            .map(|i| i.map(|i| i.1))
+            .collect::<Result<_, _>>()?;
+        Ok(entries)
+    }
+
+    fn put_block(&self, hash: &Hash, location: BlockLocation) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        self.blocks.put(&mut txn, &block_key(hash, location), &())?;
+        txn.commit()
+    }
+
+    fn get_block_locations(&self, hash: &Hash) -> Result<Vec<BlockLocation>, Self::Error> {
+        let txn = self.env.read_txn()?;
+
+        let locations = self.blocks.prefix_iter(&txn, hash)?
+            .map(|i| i.map(|(key, _)| block_location(key)))
+            .collect::<Result<_, _>>()?;
+        Ok(locations)
+    }
+
+    fn remove_block(&self, hash: &Hash, entry: Uuid) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+
+        let mut locations = self.blocks.prefix_iter_mut(&mut txn, &block_prefix(hash, entry))?;
+        while locations.next().transpose()?.is_some() {
+            locations.del_current()?;
+        }
+        drop(locations);
+
+        txn.commit()
+    }
+
+    fn set_base(&self, peer: &PeerId, base: &[(PathBuf, Hash)]) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+
+        let mut old = self.base.prefix_iter_mut(&mut txn, &base_prefix(peer))?;
+        while old.next().transpose()?.is_some() {
+            old.del_current()?;
+        }
+        drop(old);
+
+        for (path, hash) in base {
+            self.base.put(&mut txn, &base_key(peer, path), hash)?;
+        }
+
+        txn.commit()
+    }
+
+    fn get_base(&self, peer: &PeerId, path: &Path) -> Result<Option<Hash>, Self::Error> {
+        let txn = self.env.read_txn()?;
+        self.base.get(&txn, &base_key(peer, path))
+    }
+
+    fn put_conflict(&self, conflict: &Conflict) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        self.conflicts.put(&mut txn, &conflict.copy().to_string_lossy(), conflict)?;
+        txn.commit()
+    }
+
+    fn get_conflicts(&self) -> Result<Vec<Conflict>, Self::Error> {
+        let txn = self.env.read_txn()?;
+
+        let conflicts = self.conflicts.iter(&txn)?
+            .map(|i| i.map(|i| i.1))
+            .collect::<Result<_, _>>()?;
+        Ok(conflicts)
+    }
+
+    fn remove_conflict(&self, copy: &Path) -> Result<Option<Conflict>, Self::Error> {
+        let mut txn = self.env.write_txn()?;
+
+        let key = copy.to_string_lossy();
+        let old = self.conflicts.get(&txn, &key)?;
+        if old.is_some() {
+            self.conflicts.delete(&mut txn, &key)?;
+        }
+
+        txn.commit()?;
+        Ok(old)
+    }
+
+    fn put_file_version(&self, version: &FileVersion) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        self.versions.put(&mut txn, &version_key(version.entry().id(), version.id()), version)?;
+        txn.commit()
+    }
+
+    fn get_file_versions(&self, entry: Uuid) -> Result<Vec<FileVersion>, Self::Error> {
+        let txn = self.env.read_txn()?;
+
+        let versions = self.versions.prefix_iter(&txn, entry.as_bytes())?
+            .map(|i| i.map(|i| i.1))
+            .collect::<Result<_, _>>()?;
+        Ok(versions)
+    }
+
+    fn remove_file_version(&self, entry: Uuid, version: Uuid) -> Result<Option<FileVersion>, Self::Error> {
+        let mut txn = self.env.write_txn()?;
+
+        let key = version_key(entry, version);
+        let old = self.versions.get(&txn, &key)?;
+        if old.is_some() {
+            self.versions.delete(&mut txn, &key)?;
+        }
+
+        txn.commit()?;
+        Ok(old)
+    }
+
+    fn put_snapshot(&self, snapshot: &Snapshot) -> Result<(), Self::Error> {
+        let mut txn = self.env.write_txn()?;
+        self.snapshots.put(&mut txn, &snapshot.id(), snapshot)?;
+        txn.commit()
+    }
+
+    fn get_snapshot(&self, id: Uuid) -> Result<Option<Snapshot>, Self::Error> {
+        let txn = self.env.read_txn()?;
+        self.snapshots.get(&txn, &id)
+    }
+
+    fn get_snapshots(&self) -> Result<Vec<Snapshot>, Self::Error> {
+        let txn = self.env.read_txn()?;
+
+        let snapshots = self.snapshots.iter(&txn)?
+            .map(|i| i.map(|i| i.1))
+            .collect::<Result<_, _>>()?;
+        Ok(snapshots)
+    }
+
+    fn remove_snapshot(&self, id: Uuid) -> Result<Option<Snapshot>, Self::Error> {
+        let mut txn = self.env.write_txn()?;
+
+        let old = self.snapshots.get(&txn, &id)?;
+        if old.is_some() {
+            self.snapshots.delete(&mut txn, &id)?;
+        }
+
+        txn.commit()?;
+        Ok(old)
+    }
+}
+
+impl Drop for Heed {
+    fn drop(&mut self) {
+        // see the global heed store: close the environment so it can be reopened later.
+        let _ = self.env.clone().prepare_for_closing();
+    }
 }
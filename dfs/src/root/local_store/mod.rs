@@ -1,23 +1,137 @@
 
 use uuid::Uuid;
 
-use std::path::Path;
+use std::convert::TryInto;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use libp2p::PeerId;
 use crate::global_store::PutStatus;
+use crate::root::blocks::BlockLocation;
+use crate::root::conflict::Conflict;
 use crate::root::dir_entry::StorableDirEntry;
+use crate::root::history::FileVersion;
+use crate::root::snapshot::Snapshot;
+use crate::root::merkle::Hash;
 
 pub mod heed_store;
 pub mod sled_store;
 pub mod sqlite;
 
 pub trait LocalStore: Sized + 'static {
-    type Error;
+    type Error: Debug;
 
     /// Creat a new database connection.
     ///
     /// If path is None, returns an in-memory database
     fn new(path: &Path) -> Result<Self, Self::Error>;
 
+    /// Store an entry under `id`. When there is an entry with this id already, it is only
+    /// replaced when `overwrite` is true, otherwise it is kept and [`PutStatus::Exists`] is
+    /// returned.
     fn put_direntry(&self, id: Uuid, dir: &StorableDirEntry, overwrite: bool) -> Result<PutStatus, Self::Error>;
     fn get_direntry(&self, id: Uuid) -> Result<Option<StorableDirEntry>, Self::Error>;
+
+    /// Remove an entry. Returns the removed entry, if it existed. Children of the
+    /// entry aren't removed.
+    fn remove_direntry(&self, id: Uuid) -> Result<Option<StorableDirEntry>, Self::Error>;
+
+    /// Get all entries which have the entry with this id as their parent.
+    /// The entries are ordered by their uuid.
+    fn get_children(&self, parent: Uuid) -> Result<Vec<StorableDirEntry>, Self::Error>;
+    /// Get the first child of the entry with id `parent` which comes after the child with id
+    /// `after`, in the order of [`get_children`](LocalStore::get_children). The first child
+    /// when `after` is None.
+    fn get_next_child(&self, parent: Uuid, after: Option<Uuid>) -> Result<Option<StorableDirEntry>, Self::Error>;
+
+    /// Get all entries in the store, in no particular order.
+    fn get_all_direntries(&self) -> Result<Vec<StorableDirEntry>, Self::Error>;
+
+    /// Record where the block with this hash can be found. Every location of a block is kept,
+    /// so it can still be found when one of the files containing it changes.
+    fn put_block(&self, hash: &Hash, location: BlockLocation) -> Result<(), Self::Error>;
+    /// Get all locations of the block with this hash, ordered by entry id and index.
+    fn get_block_locations(&self, hash: &Hash) -> Result<Vec<BlockLocation>, Self::Error>;
+    /// Forget the locations of the block with this hash in the file with id `entry`.
+    fn remove_block(&self, hash: &Hash, entry: Uuid) -> Result<(), Self::Error>;
+
+    /// Replace the sync base with a peer: the hashes of all entries, by path, which were equal
+    /// here and at the peer after the last sync with it.
+    fn set_base(&self, peer: &PeerId, base: &[(PathBuf, Hash)]) -> Result<(), Self::Error>;
+    fn get_base(&self, peer: &PeerId, path: &Path) -> Result<Option<Hash>, Self::Error>;
+
+    /// Record a conflict. Conflicts are identified by the path of their copy.
+    fn put_conflict(&self, conflict: &Conflict) -> Result<(), Self::Error>;
+    /// Get all unresolved conflicts, ordered by the path of their copy.
+    fn get_conflicts(&self) -> Result<Vec<Conflict>, Self::Error>;
+    /// Returns the removed conflict, if there was one with this copy.
+    fn remove_conflict(&self, copy: &Path) -> Result<Option<Conflict>, Self::Error>;
+
+    /// Keep a previous version of a file. Versions are identified by the id of the file
+    /// and their own id, see [`version_key`].
+    fn put_file_version(&self, version: &FileVersion) -> Result<(), Self::Error>;
+    /// Get all kept versions of the file with id `entry`, in no particular order.
+    fn get_file_versions(&self, entry: Uuid) -> Result<Vec<FileVersion>, Self::Error>;
+    /// Returns the removed version, if the file had a version with this id.
+    fn remove_file_version(&self, entry: Uuid, version: Uuid) -> Result<Option<FileVersion>, Self::Error>;
+
+    /// Snapshots are never changed once they're stored, see [`Snapshot`].
+    fn put_snapshot(&self, snapshot: &Snapshot) -> Result<(), Self::Error>;
+    fn get_snapshot(&self, id: Uuid) -> Result<Option<Snapshot>, Self::Error>;
+    /// Get all snapshots, in no particular order.
+    fn get_snapshots(&self) -> Result<Vec<Snapshot>, Self::Error>;
+    /// Returns the removed snapshot, if there was one with this id.
+    fn remove_snapshot(&self, id: Uuid) -> Result<Option<Snapshot>, Self::Error>;
+}
+
+
+/// The key under which the sync base of a path is stored: the peer id, prefixed with its
+/// length, followed by the path.
+pub(crate) fn base_key(peer: &PeerId, path: &Path) -> Vec<u8> {
+    let mut key = base_prefix(peer);
+    key.extend_from_slice(path.to_string_lossy().as_bytes());
+    key
+}
+
+/// The key under which a previous version of a file is stored: the id of the file followed by
+/// the id of the version, so all versions of a file can be found by prefix.
+pub(crate) fn version_key(entry: Uuid, version: Uuid) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..16].copy_from_slice(entry.as_bytes());
+    key[16..].copy_from_slice(version.as_bytes());
+    key
+}
+
+/// The key under which a location of a block is stored: the hash of the block, followed by the
+/// id of the file and the index of the block in it, so all locations of a block, and those in
+/// a single file, can be found by prefix.
+pub(crate) fn block_key(hash: &Hash, location: BlockLocation) -> [u8; 52] {
+    let mut key = [0; 52];
+    key[..32].copy_from_slice(hash);
+    key[32..48].copy_from_slice(location.entry.as_bytes());
+    key[48..].copy_from_slice(&location.index.to_be_bytes());
+    key
 }
 
+/// The location of a block, from its [`block_key`].
+pub(crate) fn block_location(key: &[u8]) -> BlockLocation {
+    BlockLocation {
+        entry: Uuid::from_slice(&key[32..48]).expect("invalid block key"),
+        index: u32::from_be_bytes(key[48..52].try_into().expect("invalid block key")),
+    }
+}
+
+/// The common prefix of the keys of the locations of a block in the file with id `entry`.
+pub(crate) fn block_prefix(hash: &Hash, entry: Uuid) -> [u8; 48] {
+    let mut key = [0; 48];
+    key[..32].copy_from_slice(hash);
+    key[32..].copy_from_slice(entry.as_bytes());
+    key
+}
+
+/// The common prefix of the keys of the sync base with a peer.
+pub(crate) fn base_prefix(peer: &PeerId) -> Vec<u8> {
+    let peer = peer.to_bytes();
+    let mut key = vec![peer.len() as u8];
+    key.extend_from_slice(&peer);
+    key
+}
@@ -1,16 +1,42 @@
-use std::path::Path;
+use std::convert::TryInto;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use libp2p::PeerId;
 
 use uuid::Uuid;
 
 use crate::global_store::PutStatus;
-use crate::root::local_store::LocalStore;
+use crate::root::local_store::{LocalStore, base_key, base_prefix, block_key, block_location, block_prefix, version_key};
+use crate::root::blocks::BlockLocation;
+use crate::root::conflict::Conflict;
 use crate::root::dir_entry::StorableDirEntry;
-use sled::{Db, Tree};
+use crate::root::history::FileVersion;
+use crate::root::snapshot::Snapshot;
+use crate::root::merkle::Hash;
+use sled::{Batch, Tree};
 use thiserror::Error;
 
 pub struct Sled {
-    db: Db,
     direntries: Tree,
+    /// parent uuid bytes ++ child uuid bytes -> ()
+    children: Tree,
+    /// see [`block_key`]
+    blocks: Tree,
+    /// see [`base_key`]
+    base: Tree,
+    /// path of the conflict copy -> conflict
+    conflicts: Tree,
+    /// see [`version_key`]
+    versions: Tree,
+    snapshots: Tree,
+}
+
+fn child_key(parent: Uuid, child: Uuid) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..16].copy_from_slice(parent.as_bytes());
+    key[16..].copy_from_slice(child.as_bytes());
+    key
 }
 
 #[derive(Debug, Error)]
@@ -33,7 +59,12 @@ impl LocalStore for Sled {
 
         Ok(Self {
             direntries: db.open_tree(b"direntries")?,
-            db,
+            children: db.open_tree(b"children")?,
+            blocks: db.open_tree(b"block_locations")?,
+            base: db.open_tree(b"base")?,
+            conflicts: db.open_tree(b"conflicts")?,
+            versions: db.open_tree(b"versions")?,
+            snapshots: db.open_tree(b"snapshots")?,
         })
     }
 
@@ -41,18 +72,25 @@ impl LocalStore for Sled {
         let s_id = bincode::serialize(&id)?;
         let s_dir = bincode::serialize(&dir)?;
 
+        let old = if overwrite {
+            self.direntries.insert(s_id.as_slice(), s_dir.as_slice())?
+        } else {
+            // only insert when there was no entry with this id yet
+            let res = self.direntries.compare_and_swap(s_id.as_slice(), None as Option<&[u8]>, Some(s_dir.as_slice()))?;
+            if res.is_err() {
+                return Ok(PutStatus::Exists)
+            }
+            None
+        };
+
+        let old: Option<StorableDirEntry> = old.map(|i| bincode::deserialize(&i)).transpose()?;
+        if let Some(old_parent) = old.and_then(|o| o.parent_id()) {
+            self.children.remove(child_key(old_parent, id))?;
+        }
+        if let Some(parent) = dir.parent_id() {
+            self.children.insert(child_key(parent, id), &[])?;
+        }
 
-        self.direntries.insert(s_id.as_slice(), s_dir.as_slice())?;
-        // self.direntries.transaction(move |tx| {
-        //
-        //     if !overwrite && (tx.get(&s_id)?.is_some()) {
-        //         return Ok(PutStatus::Exists)
-        //     }
-        //
-        //     tx.insert(s_id.as_slice(), s_dir.as_slice())?;
-        //
-        //     Ok(PutStatus::Ok)
-        // }).map_err(Into::into)
         Ok(PutStatus::Ok)
     }
 
@@ -64,4 +102,160 @@ impl LocalStore for Sled {
             .transpose()
             .map_err(Into::into)
     }
+
+    fn remove_direntry(&self, id: Uuid) -> Result<Option<StorableDirEntry>, Self::Error> {
+        let s_id = bincode::serialize(&id)?;
+
+        let old: Option<StorableDirEntry> = self.direntries.remove(s_id)?
+            .map(|i| bincode::deserialize(&i))
+            .transpose()?;
+
+        if let Some(parent) = old.as_ref().and_then(|o| o.parent_id()) {
+            self.children.remove(child_key(parent, id))?;
+        }
+
+        Ok(old)
+    }
+
+    fn get_children(&self, parent: Uuid) -> Result<Vec<StorableDirEntry>, Self::Error> {
+        let mut res = Vec::new();
+        for i in self.children.scan_prefix(parent.as_bytes()) {
+            let (key, _) = i?;
+            // keys are always 32 bytes, see `child_key`
+            let child = Uuid::from_slice(&key[16..]).expect("invalid child key");
+
+            if let Some(entry) = self.get_direntry(child)? {
+                res.push(entry);
+            }
+        }
+
+        Ok(res)
+    }
+
+    fn get_next_child(&self, parent: Uuid, after: Option<Uuid>) -> Result<Option<StorableDirEntry>, Self::Error> {
+        let after = after.map(|after| child_key(parent, after));
+        let start = match &after {
+            Some(after) => Bound::Excluded(&after[..]),
+            None => Bound::Included(&parent.as_bytes()[..]),
+        };
+        for i in self.children.range::<&[u8], _>((start, Bound::Unbounded)) {
+            let (key, _) = i?;
+            if !key.starts_with(parent.as_bytes()) {
+                break
+            }
+            let child = Uuid::from_slice(&key[16..]).expect("invalid child key");
+
+            if let Some(entry) = self.get_direntry(child)? {
+                return Ok(Some(entry))
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn get_all_direntries(&self) -> Result<Vec<StorableDirEntry>, Self::Error> {
+        self.direntries.iter()
+            .map(|i| Ok(bincode::deserialize(&i?.1)?))
+            .collect()
+    }
+
+    fn put_block(&self, hash: &Hash, location: BlockLocation) -> Result<(), Self::Error> {
+        self.blocks.insert(block_key(hash, location), &[])?;
+        Ok(())
+    }
+
+    fn get_block_locations(&self, hash: &Hash) -> Result<Vec<BlockLocation>, Self::Error> {
+        self.blocks.scan_prefix(hash)
+            .map(|i| Ok(block_location(&i?.0)))
+            .collect()
+    }
+
+    fn remove_block(&self, hash: &Hash, entry: Uuid) -> Result<(), Self::Error> {
+        let mut batch = Batch::default();
+        for i in self.blocks.scan_prefix(block_prefix(hash, entry)) {
+            batch.remove(i?.0);
+        }
+
+        self.blocks.apply_batch(batch)?;
+        Ok(())
+    }
+
+    fn set_base(&self, peer: &PeerId, base: &[(PathBuf, Hash)]) -> Result<(), Self::Error> {
+        let mut batch = Batch::default();
+        for i in self.base.scan_prefix(base_prefix(peer)) {
+            batch.remove(i?.0);
+        }
+        for (path, hash) in base {
+            batch.insert(base_key(peer, path), &hash[..]);
+        }
+
+        self.base.apply_batch(batch)?;
+        Ok(())
+    }
+
+    fn get_base(&self, peer: &PeerId, path: &Path) -> Result<Option<Hash>, Self::Error> {
+        Ok(self.base.get(base_key(peer, path))?
+            .and_then(|i| i.as_ref().try_into().ok()))
+    }
+
+    fn put_conflict(&self, conflict: &Conflict) -> Result<(), Self::Error> {
+        self.conflicts.insert(conflict.copy().to_string_lossy().as_bytes(), bincode::serialize(conflict)?)?;
+        Ok(())
+    }
+
+    fn get_conflicts(&self) -> Result<Vec<Conflict>, Self::Error> {
+        self.conflicts.iter()
+            .map(|i| Ok(bincode::deserialize(&i?.1)?))
+            .collect()
+    }
+
+    fn remove_conflict(&self, copy: &Path) -> Result<Option<Conflict>, Self::Error> {
+        self.conflicts.remove(copy.to_string_lossy().as_bytes())?
+            .map(|i| bincode::deserialize(&i))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    fn put_file_version(&self, version: &FileVersion) -> Result<(), Self::Error> {
+        self.versions.insert(version_key(version.entry().id(), version.id()), bincode::serialize(version)?)?;
+        Ok(())
+    }
+
+    fn get_file_versions(&self, entry: Uuid) -> Result<Vec<FileVersion>, Self::Error> {
+        self.versions.scan_prefix(entry.as_bytes())
+            .map(|i| Ok(bincode::deserialize(&i?.1)?))
+            .collect()
+    }
+
+    fn remove_file_version(&self, entry: Uuid, version: Uuid) -> Result<Option<FileVersion>, Self::Error> {
+        self.versions.remove(version_key(entry, version))?
+            .map(|i| bincode::deserialize(&i))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    fn put_snapshot(&self, snapshot: &Snapshot) -> Result<(), Self::Error> {
+        self.snapshots.insert(snapshot.id().as_bytes(), bincode::serialize(snapshot)?)?;
+        Ok(())
+    }
+
+    fn get_snapshot(&self, id: Uuid) -> Result<Option<Snapshot>, Self::Error> {
+        self.snapshots.get(id.as_bytes())?
+            .map(|i| bincode::deserialize(&i))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    fn get_snapshots(&self) -> Result<Vec<Snapshot>, Self::Error> {
+        self.snapshots.iter()
+            .map(|i| Ok(bincode::deserialize(&i?.1)?))
+            .collect()
+    }
+
+    fn remove_snapshot(&self, id: Uuid) -> Result<Option<Snapshot>, Self::Error> {
+        self.snapshots.remove(id.as_bytes())?
+            .map(|i| bincode::deserialize(&i))
+            .transpose()
+            .map_err(Into::into)
+    }
 }
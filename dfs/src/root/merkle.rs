@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use crate::root::dir_entry::StorableDirEntry;
+
+/// A blake3 hash. Files are identified by the hash of their contents, directories by the
+/// hash of their children (see [`dir_hash`]), which makes the index of a root a Merkle tree.
+pub type Hash = [u8; 32];
+
+/// The hash of every tombstone. Tombstones are part of the tree, so deletions show up when
+/// comparing roots, but all tombstones at a path are equal, whatever their version.
+pub const TOMBSTONE: Hash = [0; 32];
+
+/// The hash of a directory with these children. It combines the name, type and hash
+/// of every child, so two directories have the same hash exactly when they have the
+/// same contents. The order of the children doesn't matter.
+pub fn dir_hash(children: &[StorableDirEntry]) -> Hash {
+    let mut children: Vec<_> = children.iter().collect();
+    children.sort_by(|a, b| a.name().cmp(b.name()));
+
+    let mut hasher = blake3::Hasher::new();
+    for child in children {
+        let name = child.name().to_string_lossy();
+        hasher.update(&(name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update(&[child.is_dir() as u8]);
+        hasher.update(child.hash());
+    }
+
+    *hasher.finalize().as_bytes()
+}
+
+/// An entry which differs between two copies of a root. Entries are matched up by path,
+/// so at least one of `local` and `remote` is set.
+#[derive(Debug, Clone)]
+pub struct Difference {
+    pub path: PathBuf,
+    pub local: Option<StorableDirEntry>,
+    pub remote: Option<StorableDirEntry>,
+}
+
+/// Compare the children of a directory which exists on both sides. Returns the children which
+/// differ, and of those the pairs of (local, remote) ids of directories which exist on both
+/// sides, and so have to be descended into to find all differences.
+///
+/// Children with equal hashes are skipped entirely, which is what makes comparing two
+/// mostly equal roots fast.
+pub fn compare_children(local: Vec<StorableDirEntry>, remote: Vec<StorableDirEntry>) -> (Vec<Difference>, Vec<(Uuid, Uuid)>) {
+    let mut local: HashMap<OsString, StorableDirEntry> = local.into_iter()
+        .map(|e| (e.name().to_os_string(), e))
+        .collect();
+
+    let mut differences = Vec::new();
+    let mut descend = Vec::new();
+
+    for remote in remote {
+        match local.remove(remote.name()) {
+            Some(local) if local.hash() == remote.hash() && local.is_dir() == remote.is_dir() => {}
+            Some(local) => {
+                if local.is_dir() && remote.is_dir() {
+                    descend.push((local.id(), remote.id()));
+                }
+                differences.push(Difference {
+                    path: remote.path().to_path_buf(),
+                    local: Some(local),
+                    remote: Some(remote),
+                });
+            }
+            None => differences.push(Difference {
+                path: remote.path().to_path_buf(),
+                local: None,
+                remote: Some(remote),
+            }),
+        }
+    }
+
+    differences.extend(local.into_values().map(|local| Difference {
+        path: local.path().to_path_buf(),
+        local: Some(local),
+        remote: None,
+    }));
+
+    (differences, descend)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::root::dir_entry::StorableDirEntry;
+    use crate::root::merkle::{compare_children, dir_hash};
+
+    fn file(path: &str, contents: u8) -> StorableDirEntry {
+        StorableDirEntry::new(Uuid::new_v4(), path.into(), Some(Uuid::nil()), false, [contents; 32])
+    }
+
+    #[test]
+    fn dir_hash_ignores_order() {
+        let a = file("/a", 1);
+        let b = file("/b", 2);
+
+        assert_eq!(dir_hash(&[a.clone(), b.clone()]), dir_hash(&[b.clone(), a.clone()]));
+        assert_ne!(dir_hash(&[a.clone(), b]), dir_hash(&[a.clone(), file("/c", 2)]));
+        assert_ne!(dir_hash(&[a]), dir_hash(&[file("/a", 3)]));
+    }
+
+    #[test]
+    fn compare() {
+        let local = vec![file("/same", 1), file("/changed", 1), file("/local", 1)];
+        let remote = vec![file("/same", 1), file("/changed", 2), file("/remote", 1)];
+
+        let (mut differences, descend) = compare_children(local, remote);
+        differences.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let paths: Vec<_> = differences.iter().map(|d| d.path.to_str().unwrap()).collect();
+        assert_eq!(paths, vec!["/changed", "/local", "/remote"]);
+        assert!(differences[1].remote.is_none());
+        assert!(differences[2].local.is_none());
+        assert!(descend.is_empty());
+    }
+}
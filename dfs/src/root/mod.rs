@@ -1,24 +1,40 @@
-use std::fs::create_dir_all;
+use std::collections::HashSet;
+use std::fs::{self, create_dir_all};
 use std::io;
 use std::ops::{Deref, DerefMut};
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
 
 use thiserror::Error;
 
-use dir_entry::DirEntry;
+use dir_entry::{DirEntry, Presence, StorableDirEntry};
 
-use crate::Dfs;
-use crate::root::index::{IndexError, Indexer};
+use crate::{Dfs, GetRootError};
+use crate::compression::Compression;
+use crate::root::index::{IndexError, Indexer, inode};
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
 use crate::global_store::GlobalStore;
 use crate::root::local_store::heed_store::Heed;
 use crate::root::local_store::LocalStore;
 use crate::root::local_store::sled_store::Sled;
+use crate::root::blocks::BlockLocation;
+use crate::root::conflict::{Conflict, Resolution};
+use crate::root::merkle::{Hash, TOMBSTONE, dir_hash};
 
+pub mod blocks;
+pub mod conflict;
 pub mod index;
 pub mod dir_entry;
+pub mod history;
 pub mod local_store;
+pub mod merkle;
+pub mod placeholder;
+pub mod selection;
+pub mod share;
+pub mod snapshot;
+pub mod version;
+pub mod vfs;
 
 
 #[derive(Debug, Error)]
@@ -43,6 +59,45 @@ pub enum GetDirEntryError<LSE> {
     DbInteractionError(#[from] LSE),
 }
 
+/// A root relative path which leads outside the root, or into its local database. Paths like
+/// these come from peers, and are never written to.
+#[derive(Debug, Error)]
+#[error("{0:?} isn't a path inside the root")]
+pub struct UnsafePath(pub PathBuf);
+
+#[derive(Debug, Error)]
+pub enum ReadBlockError<LSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] LSE),
+
+    #[error("failed to read block from {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum ConflictError<LSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] LSE),
+
+    #[error("there is no conflict with copy {0:?}")]
+    NotFound(PathBuf),
+
+    #[error("{0}")]
+    UnsafePath(UnsafePath),
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum GcError<GSE, LSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] LSE),
+
+    #[error("global db error: {0}")]
+    GlobalDb(GSE),
+}
+
 #[derive(Debug, Error)]
 pub enum GetRootEntryError<LSE> {
     #[error("db error: {0}")]
@@ -64,7 +119,6 @@ pub struct StorableRoot {
     uuid: Uuid,
     path: PathBuf,
     name: String,
-    root_direntry_id: Option<Uuid>,
 }
 
 impl StorableRoot {
@@ -162,17 +216,19 @@ impl<'dfs, GS: GlobalStore> Root<'dfs, GS> {
 
     /// Create a root from a [`StorableRoot`] To Create a root, use
     /// the [`create_root`] function on a [`Dfs`]
-    pub(crate) fn new(dfs: &'dfs Dfs<GS>, name: String, path: PathBuf) -> Self {
-        let uuid = Uuid::new_v4();
-
+    pub(crate) fn new(dfs: &'dfs Dfs<GS>, uuid: Uuid, name: String, path: PathBuf) -> Self {
         Self::from_storable(dfs, StorableRoot {
             uuid,
             name,
             path,
-            root_direntry_id: None
         })
     }
 
+    /// The [`Dfs`] this root belongs to.
+    pub(crate) fn dfs(&self) -> &'dfs Dfs<GS> {
+        self.dfs
+    }
+
     /// By default, Roots are disconnected from their [`LocalStore`]. By connecting
     /// a Root, this [`LocalStore`] is opened, and files in the root can be modified.
     ///
@@ -268,13 +324,13 @@ impl<'dfs, GS: GlobalStore, LS: LocalStore> ConnectedRoot<'dfs, GS, LS> {
     /// let cfg = Config::test_config(&tempdir);
     /// let dfs = Dfs::new(cfg).unwrap();
     /// let root = dfs.new_root(&tempdir, "test").unwrap();
-    /// let mut connected_root = root.connect().unwrap();
+    /// let connected_root = root.connect().unwrap();
     ///
     /// // Do the indexing
     /// assert!(connected_root.index().await.is_ok());
     /// # }
     /// ```
-    pub async fn index(&'dfs mut self) -> Result<(), IndexError<LS::Error>> {
+    pub async fn index(&self) -> Result<(), IndexError<LS::Error>> {
         let indexer = Indexer::new(self)?;
         indexer.index().await?;
 
@@ -287,36 +343,659 @@ impl<'dfs, GS: GlobalStore, LS: LocalStore> ConnectedRoot<'dfs, GS, LS> {
     /// On a brand new root (just created with [`new_root`]), the root direntry may not
     /// exist yet. This method will first create it in the [`LocalStore`] and then return it.
     ///
+    /// The root direntry has the same uuid as the root itself, so it is the same on every peer.
+    ///
     /// TODO: make children method on DirEntry
-    pub fn root_dir(&self) -> Result<DirEntry<GS, LS>, GetRootEntryError<LS::Error>> {
+    pub fn root_dir(&self) -> Result<DirEntry<'_, 'dfs, GS, LS>, GetRootEntryError<LS::Error>> {
         if !self.path.exists() {
             return Err(GetRootEntryError::Exists(self.path.clone()))
         }
 
-        if let Some(id) = self.root_direntry_id {
-            if let Some(entry) = self.connection.get_direntry(id)? {
-                Ok(DirEntry::from_storable(self, entry))
-            } else {
-                self.create_root()
-            }
+        if let Some(entry) = self.connection.get_direntry(self.id())? {
+            Ok(DirEntry::from_storable(self, entry))
         } else {
             self.create_root()
         }
     }
 
     #[doc(hidden)]
-    fn create_root(&self) -> Result<DirEntry<GS, LS>, GetRootEntryError<LS::Error>> {
+    fn create_root(&self) -> Result<DirEntry<'_, 'dfs, GS, LS>, GetRootEntryError<LS::Error>> {
         if !self.path.is_dir() {
             return Err(GetRootEntryError::NotDir(self.path.clone()))
         }
 
-        let root = DirEntry::new(self, "/".into(), None, true);
+        let mut root = DirEntry::with_id(self, self.id(), "/".into(), None, true);
+        root.set_hash(dir_hash(&[]));
 
         let _ = self.connection.put_direntry(root.id(), root.deref(), true)?;
 
         Ok(root)
     }
 
+    /// List all entries in the subtree starting at the entry with id `subtree`, or the whole
+    /// root when `subtree` is None. The first entry is the top of the subtree, after which
+    /// the entries follow depth first: every directory is followed by everything under it, and
+    /// siblings are ordered by uuid. Since this order only depends on the contents of the
+    /// [`LocalStore`], it can be listed in pages, see [`manifest_page`](ConnectedRoot::manifest_page).
+    ///
+    /// With a `depth`, only entries at most that many levels below the top are listed. A depth of
+    /// 0 lists only the top, 1 the top and its children.
+    ///
+    /// Returns None when there is no entry with id `subtree`.
+    pub fn manifest(&self, subtree: Option<Uuid>, depth: Option<u32>) -> Result<Option<Vec<StorableDirEntry>>, GetDirEntryError<LS::Error>> {
+        self.manifest_page(subtree, depth, None, usize::MAX)
+    }
+
+    /// At most `limit` entries of the [`manifest`](ConnectedRoot::manifest), starting after the
+    /// entry with id `after`, or at the top when `after` is None. Continuing after the last entry
+    /// of a page only takes a few lookups, however far into the manifest the page is.
+    ///
+    /// Returns None when there is no entry with id `subtree`, or when the entry with id `after`
+    /// isn't listed in the manifest (anymore), because it was moved or removed.
+    pub fn manifest_page(&self, subtree: Option<Uuid>, depth: Option<u32>, after: Option<Uuid>, limit: usize) -> Result<Option<Vec<StorableDirEntry>>, GetDirEntryError<LS::Error>> {
+        let top = match subtree {
+            Some(id) => self.connection.get_direntry(id)?,
+            None => self.connection.get_direntry(self.id())?,
+        };
+
+        let top = match top {
+            Some(top) => top,
+            None if subtree.is_none() => return Ok(Some(Vec::new())),
+            None => return Ok(None),
+        };
+
+        // the last listed entry, and how far below the top it is
+        let mut last = match after {
+            Some(after) => match self.manifest_level(top.id(), after, depth)? {
+                Some(found) => Some(found),
+                None => return Ok(None),
+            },
+            None => None,
+        };
+
+        let mut entries = Vec::new();
+        while entries.len() < limit {
+            let next = match last {
+                Some((entry, level)) => self.next_in_manifest(top.id(), entry, level, depth)?,
+                None => Some((top.clone(), 0)),
+            };
+            match next {
+                Some((entry, level)) => {
+                    entries.push(entry.clone());
+                    last = Some((entry, level));
+                }
+                None => break,
+            }
+        }
+
+        Ok(Some(entries))
+    }
+
+    /// The entry with id `id` and how many levels it is below the entry with id `top`, when it
+    /// is listed in the manifest of `top`.
+    fn manifest_level(&self, top: Uuid, id: Uuid, depth: Option<u32>) -> Result<Option<(StorableDirEntry, u32)>, LS::Error> {
+        let entry = match self.connection.get_direntry(id)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let mut level = 0;
+        let mut ancestor = entry.clone();
+        while ancestor.id() != top {
+            ancestor = match ancestor.parent_id() {
+                Some(parent) => match self.connection.get_direntry(parent)? {
+                    Some(parent) => parent,
+                    None => return Ok(None),
+                },
+                None => return Ok(None),
+            };
+            level += 1;
+        }
+
+        if depth.is_some_and(|d| level > d) {
+            return Ok(None)
+        }
+        Ok(Some((entry, level)))
+    }
+
+    /// The entry after `entry`, which is `level` levels below the entry with id `top`, in the
+    /// manifest of `top`: its first child, or else the next sibling of it or of its closest
+    /// ancestor which has one.
+    fn next_in_manifest(&self, top: Uuid, entry: StorableDirEntry, level: u32, depth: Option<u32>) -> Result<Option<(StorableDirEntry, u32)>, LS::Error> {
+        if entry.is_dir() && depth.is_none_or(|d| level < d) {
+            if let Some(child) = self.connection.get_next_child(entry.id(), None)? {
+                return Ok(Some((child, level + 1)))
+            }
+        }
+
+        let (mut entry, mut level) = (entry, level);
+        while entry.id() != top {
+            let parent = match entry.parent_id() {
+                Some(parent) => parent,
+                None => break,
+            };
+            if let Some(sibling) = self.connection.get_next_child(parent, Some(entry.id()))? {
+                return Ok(Some((sibling, level)))
+            }
+
+            entry = match self.connection.get_direntry(parent)? {
+                Some(parent) => parent,
+                None => break,
+            };
+            level -= 1;
+        }
+
+        Ok(None)
+    }
+
+    /// The merkle hash of the whole root. Two copies of a root with the same contents
+    /// have the same hash. Only up to date after indexing the root.
+    pub fn root_hash(&self) -> Result<Hash, GetRootEntryError<LS::Error>> {
+        Ok(*self.root_dir()?.hash())
+    }
+
+    /// Recompute the merkle hash of the directory with id `dir` from its children, and
+    /// walk up to recompute its ancestors as long as their hash changes.
+    pub(crate) fn rehash_from(&self, dir: Uuid) -> Result<(), LS::Error> {
+        let mut next = Some(dir);
+        while let Some(id) = next {
+            let mut entry = match self.connection.get_direntry(id)? {
+                // the hash of a tombstone doesn't depend on its children
+                Some(entry) if !entry.is_deleted() => entry,
+                _ => break,
+            };
+
+            let hash = dir_hash(&self.connection.get_children(id)?);
+            if &hash == entry.hash() {
+                break
+            }
+
+            entry.set_hash(hash);
+            self.connection.put_direntry(id, &entry, true)?;
+            next = entry.parent_id();
+        }
+
+        Ok(())
+    }
+
+    /// Update the block index after the contents of a file changed. The locations of the blocks
+    /// of `old` are forgotten, other files containing the same blocks still provide them, and
+    /// those of `new` are recorded.
+    pub(crate) fn replace_blocks(&self, old: Option<&StorableDirEntry>, new: Option<&StorableDirEntry>) -> Result<(), LS::Error> {
+        if let Some(old) = old {
+            for hash in old.blocks().iter().collect::<HashSet<_>>() {
+                self.connection.remove_block(hash, old.id())?;
+            }
+        }
+
+        if let Some(new) = new {
+            for (index, hash) in new.blocks().iter().enumerate() {
+                self.connection.put_block(hash, BlockLocation {
+                    entry: new.id(),
+                    index: index as u32,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the block with this hash from any file in the root containing it. Returns None when
+    /// no file contains the block, or the files changed since the root was last indexed.
+    pub fn read_block(&self, hash: &Hash) -> Result<Option<Vec<u8>>, ReadBlockError<LS::Error>> {
+        for (entry, location) in self.block_entries(hash)? {
+            let path = match self.path_on_disk(entry.path()) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            match blocks::read_block(&path, location.index, hash) {
+                Ok(Some(block)) => return Ok(Some(block)),
+                Ok(None) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(ReadBlockError::Io(path, e)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The files which contain the block with this hash, and where in the files it is.
+    pub(crate) fn block_entries(&self, hash: &Hash) -> Result<Vec<(StorableDirEntry, BlockLocation)>, LS::Error> {
+        let mut found = Vec::new();
+        for location in self.connection.get_block_locations(hash)? {
+            if let Some(entry) = self.connection.get_direntry(location.entry)? {
+                found.push((entry, location));
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// How the blocks of the file at this (root relative) path are compressed: as
+    /// [set](Dfs::set_compression) for the root, unless the file is compressed already.
+    pub fn compression(&self, path: &Path) -> Result<Compression, GetRootError<GS::Error>> {
+        Ok(self.dfs.compression(self.id())?.for_path(path))
+    }
+
+    /// Where the entry with this (root relative) path is stored on disk. Fails for paths which
+    /// aren't [inside the root](check_path).
+    pub fn path_on_disk(&self, path: &Path) -> Result<PathBuf, UnsafePath> {
+        check_path(path, &self.dfs.cfg().local_db)?;
+        Ok(self.path().join(path.strip_prefix("/").unwrap_or(path)))
+    }
+
+    /// The folder with the [`LocalStore`] of this root. Other local state of the root,
+    /// like partial downloads, is kept here too.
+    pub fn local_db_path(&self) -> PathBuf {
+        self.path().join(&self.dfs.cfg().local_db)
+    }
+
+    /// Get the entry with this (root relative) path. Deleted entries aren't found.
+    pub fn get_by_path(&self, path: impl AsRef<Path>) -> Result<Option<DirEntry<'_, 'dfs, GS, LS>>, GetDirEntryError<LS::Error>> {
+        Ok(
+            self.find(path.as_ref(), false)?
+            .map(|entry| DirEntry::from_storable(self, entry))
+        )
+    }
+
+    /// Find the entry at `path`, optionally including tombstones.
+    fn find(&self, path: &Path, deleted: bool) -> Result<Option<StorableDirEntry>, LS::Error> {
+        let mut current = match self.connection.get_direntry(self.id())? {
+            Some(top) => top,
+            None => return Ok(None),
+        };
+
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir | Component::CurDir => continue,
+                Component::Normal(name) => name,
+                Component::ParentDir | Component::Prefix(_) => return Ok(None),
+            };
+
+            current = match self.connection.get_children(current.id())?.into_iter().find(|c| c.name() == name) {
+                Some(child) if deleted || !child.is_deleted() => child,
+                _ => return Ok(None),
+            };
+        }
+
+        Ok(Some(current))
+    }
+
+    /// Make sure there are directory entries for the directory at `path` and all its ancestors,
+    /// replacing files which are in the way and bringing deleted directories back. Returns the
+    /// entry of the directory.
+    pub(crate) fn ensure_dir(&self, path: &Path) -> Result<StorableDirEntry, LS::Error> {
+        let mut current = match self.connection.get_direntry(self.id())? {
+            Some(top) => top,
+            None => {
+                let top = StorableDirEntry::new(self.id(), "/".into(), None, true, dir_hash(&[]));
+                self.connection.put_direntry(top.id(), &top, true)?;
+                top
+            }
+        };
+
+        let mut changed = None;
+        for component in path.components() {
+            let name = match component {
+                Component::Normal(name) => name,
+                _ => continue,
+            };
+
+            let existing = self.connection.get_children(current.id())?.into_iter().find(|c| c.name() == name);
+            current = match existing {
+                Some(child) if child.is_dir() && !child.is_deleted() => child,
+                Some(mut child) if child.is_dir() => {
+                    child.undelete();
+                    child.set_hash(dir_hash(&self.connection.get_children(child.id())?));
+                    child.version_mut().bump(&self.dfs.peer_id());
+                    self.connection.put_direntry(child.id(), &child, true)?;
+                    changed.get_or_insert(current.id());
+                    child
+                }
+                existing => {
+                    if let Some(file) = existing {
+                        self.remove_subtree(file)?;
+                    }
+
+                    let mut dir = StorableDirEntry::new(Uuid::new_v4(), current.path().join(name), Some(current.id()), true, dir_hash(&[]));
+                    dir.version_mut().bump(&self.dfs.peer_id());
+                    self.connection.put_direntry(dir.id(), &dir, false)?;
+                    changed.get_or_insert(current.id());
+                    dir
+                }
+            };
+        }
+
+        if let Some(changed) = changed {
+            self.rehash_from(changed)?;
+        }
+
+        Ok(current)
+    }
+
+    /// Store the file at `path`, with the contents (hashes, blocks and size) of `contents`.
+    /// This updates the entry of the file if it exists (or is a tombstone), and creates it
+    /// (and its parents) otherwise.
+    pub(crate) fn put_file(&self, path: &Path, contents: &StorableDirEntry) -> Result<(), LS::Error> {
+        self.put_file_entry(path, contents, Presence::Local)
+    }
+
+    /// Store the file at `path` as a [placeholder](Presence::Placeholder) with the metadata of
+    /// `contents`. Empty files are never placeholders: their contents are known.
+    pub(crate) fn put_placeholder(&self, path: &Path, contents: &StorableDirEntry) -> Result<(), LS::Error> {
+        let presence = if contents.size() == 0 { Presence::Local } else { Presence::Placeholder };
+        self.put_file_entry(path, contents, presence)
+    }
+
+    /// Store the metadata of an entry which isn't [selected](crate::root::selection::Selection),
+    /// so it isn't on disk here. Its hash is kept, so the merkle hashes still match those of the peers.
+    pub(crate) fn put_excluded(&self, entry: &StorableDirEntry) -> Result<(), LS::Error> {
+        if entry.is_file() {
+            return self.put_file_entry(entry.path(), entry, Presence::Excluded)
+        }
+
+        let mut dir = self.ensure_dir(entry.path())?;
+        if !dir.is_excluded() {
+            dir.set_presence(Presence::Excluded);
+            self.connection.put_direntry(dir.id(), &dir, true)?;
+        }
+        Ok(())
+    }
+
+    /// Mark an entry and everything under it as [excluded](StorableDirEntry::is_excluded), after
+    /// its contents were removed from disk. Nothing changes about the entries except that.
+    pub(crate) fn exclude(&self, entry: StorableDirEntry) -> Result<(), LS::Error> {
+        let mut todo = vec![entry];
+        while let Some(mut entry) = todo.pop() {
+            if entry.is_deleted() || entry.is_excluded() {
+                continue
+            }
+            if entry.is_dir() {
+                todo.extend(self.connection.get_children(entry.id())?);
+            }
+
+            entry.set_presence(Presence::Excluded);
+            self.connection.put_direntry(entry.id(), &entry, true)?;
+            self.replace_blocks(Some(&entry), None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Give `contents` the version of a change by this peer to the entry at `path`: newer than
+    /// that of the entry which is there, or was there before it was deleted.
+    pub(crate) fn local_change(&self, path: &Path, mut contents: StorableDirEntry) -> Result<StorableDirEntry, LS::Error> {
+        if let Some(existing) = self.find(path, true)? {
+            contents.version_mut().merge(existing.version());
+        }
+        contents.version_mut().bump(&self.dfs.peer_id());
+        Ok(contents)
+    }
+
+    fn put_file_entry(&self, path: &Path, contents: &StorableDirEntry, presence: Presence) -> Result<(), LS::Error> {
+        let parent_path = path.parent().unwrap_or_else(|| Path::new("/"));
+        let parent = self.ensure_dir(parent_path)?;
+        let name = path.file_name().unwrap_or_default();
+
+        let old = self.connection.get_children(parent.id())?.into_iter().find(|c| c.name() == name);
+        let mut new = match old {
+            Some(old) if !old.is_dir() => old,
+            old => {
+                if let Some(dir) = old {
+                    self.remove_subtree(dir)?;
+                }
+                StorableDirEntry::new(Uuid::new_v4(), parent.path().join(name), Some(parent.id()), false, Hash::default())
+            }
+        };
+
+        let old = new.clone();
+        new.copy_contents(contents);
+        new.set_presence(presence);
+        // the file on disk was (re)written, so it's recognized by its new inode when it's moved
+        let on_disk = self.path_on_disk(new.path()).ok().and_then(|p| fs::symlink_metadata(p).ok());
+        new.set_inode(on_disk.as_ref().and_then(inode).filter(|_| presence != Presence::Excluded));
+        self.connection.put_direntry(new.id(), &new, true)?;
+        // only the blocks of files which are on disk can be read here
+        self.replace_blocks(Some(&old), Some(&new).filter(|n| n.is_local()))?;
+        self.rehash_from(parent.id())
+    }
+
+    /// Delete the entry at `path` and everything under it, leaving tombstones. When the deletion
+    /// comes from a peer, `remote` is its tombstone, of which the version is taken over. Otherwise
+    /// the deletion is a change by this peer. Returns whether there was an entry to delete.
+    pub(crate) fn delete_path(&self, path: &Path, remote: Option<&StorableDirEntry>) -> Result<bool, LS::Error> {
+        let now = SystemTime::now();
+        let local_peer = self.dfs.peer_id();
+
+        let mut entry = match self.find(path, true)? {
+            Some(entry) if entry.parent_id().is_some() => entry,
+            _ => return Ok(false),
+        };
+
+        if entry.is_deleted() {
+            if let Some(remote) = remote {
+                entry.version_mut().merge(remote.version());
+                self.connection.put_direntry(entry.id(), &entry, true)?;
+            }
+            return Ok(false)
+        }
+
+        let mut todo = self.connection.get_children(entry.id())?;
+        while let Some(mut child) = todo.pop() {
+            if child.is_deleted() {
+                continue
+            }
+            todo.extend(self.connection.get_children(child.id())?);
+
+            let old = child.clone();
+            child.tombstone(now);
+            child.version_mut().bump(&local_peer);
+            self.connection.put_direntry(child.id(), &child, true)?;
+            self.replace_blocks(Some(&old), None)?;
+        }
+
+        let old = entry.clone();
+        entry.tombstone(now);
+        match remote {
+            Some(remote) => entry.version_mut().merge(remote.version()),
+            None => entry.version_mut().bump(&local_peer),
+        }
+        self.connection.put_direntry(entry.id(), &entry, true)?;
+        self.replace_blocks(Some(&old), None)?;
+
+        if let Some(parent) = entry.parent_id() {
+            self.rehash_from(parent)?;
+        }
+        Ok(true)
+    }
+
+    /// Move the entry at `from` and everything under it to `to`, keeping their ids, and leave
+    /// tombstones at the old paths, so peers see a deletion there and [recognize](crate::sync::plan)
+    /// the move as a rename. A tombstone at `to` is replaced. When the move comes from a peer,
+    /// `remote` is its tombstone at `from` and its entry at `to`, of which the versions are taken
+    /// over. Otherwise the move is a change by this peer. Returns the moved entry, if there was one.
+    pub(crate) fn move_path(&self, from: &Path, to: &Path, remote: Option<(&StorableDirEntry, &StorableDirEntry)>) -> Result<Option<StorableDirEntry>, LS::Error> {
+        let now = SystemTime::now();
+        let local_peer = self.dfs.peer_id();
+
+        let mut entry = match self.find(from, false)? {
+            Some(entry) if entry.parent_id().is_some() => entry,
+            _ => return Ok(None),
+        };
+        let name = match to.file_name() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let parent = self.ensure_dir(to.parent().unwrap_or_else(|| Path::new("/")))?;
+        let to = parent.path().join(name);
+        let from = entry.path().to_path_buf();
+        if from == to {
+            return Ok(Some(entry))
+        }
+
+        let tombstone_of = |entry: &StorableDirEntry, parent: Option<Uuid>| {
+            let mut tombstone = StorableDirEntry::new(Uuid::new_v4(), entry.path().to_path_buf(), parent, entry.is_dir(), TOMBSTONE);
+            tombstone.version_mut().merge(entry.version());
+            tombstone.tombstone(now);
+            tombstone
+        };
+
+        let mut tombstone = tombstone_of(&entry, entry.parent_id());
+        if let Some(replaced) = self.find(&to, true)? {
+            entry.version_mut().merge(replaced.version());
+            self.remove_subtree(replaced)?;
+        }
+        match remote {
+            Some((remote_from, remote_to)) => {
+                tombstone.version_mut().merge(remote_from.version());
+                entry.version_mut().merge(remote_to.version());
+            }
+            None => {
+                tombstone.version_mut().bump(&local_peer);
+                entry.version_mut().bump(&local_peer);
+            }
+        }
+        self.connection.put_direntry(tombstone.id(), &tombstone, false)?;
+
+        let old_parent = entry.parent_id();
+        entry.set_path(to.clone());
+        entry.set_parent(Some(parent.id()));
+        self.connection.put_direntry(entry.id(), &entry, true)?;
+
+        // everything under the entry moves along, and leaves a tombstone under that of the entry
+        let mut todo: Vec<_> = self.connection.get_children(entry.id())?.into_iter()
+            .map(|child| (child, tombstone.id()))
+            .collect();
+        while let Some((mut child, tombstone_parent)) = todo.pop() {
+            let path = match child.path().strip_prefix(&from) {
+                Ok(rest) => to.join(rest),
+                Err(_) => continue,
+            };
+
+            let mut parent = tombstone_parent;
+            if !child.is_deleted() {
+                let mut tombstone = tombstone_of(&child, Some(tombstone_parent));
+                tombstone.version_mut().bump(&local_peer);
+                self.connection.put_direntry(tombstone.id(), &tombstone, false)?;
+                parent = tombstone.id();
+            }
+            if child.is_dir() {
+                todo.extend(self.connection.get_children(child.id())?.into_iter().map(|c| (c, parent)));
+            }
+
+            child.set_path(path);
+            self.connection.put_direntry(child.id(), &child, true)?;
+        }
+
+        if let Some(old_parent) = old_parent {
+            self.rehash_from(old_parent)?;
+        }
+        self.rehash_from(parent.id())?;
+        Ok(Some(entry))
+    }
+
+    /// Remove an entry and all entries under it, without leaving tombstones and without
+    /// updating the hash of its parent.
+    pub(crate) fn remove_subtree(&self, entry: StorableDirEntry) -> Result<(), LS::Error> {
+        let mut todo = vec![entry];
+        while let Some(entry) = todo.pop() {
+            if entry.is_dir() {
+                todo.extend(self.connection.get_children(entry.id())?);
+            }
+
+            self.connection.remove_direntry(entry.id())?;
+            self.replace_blocks(Some(&entry), None)?;
+            self.remove_versions(entry.id())?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove tombstones which are no longer needed: those which every peer the root is shared
+    /// with has as well (they agreed on it during the last sync, see [`sync`](crate::sync)),
+    /// and those older than the [retention period](crate::config::Config::tombstone_retention).
+    /// Returns how many tombstones were removed.
+    pub fn gc_tombstones(&self) -> Result<usize, GcError<GS::Error, LS::Error>> {
+        let mut peers = Vec::new();
+        for (peer, _) in self.dfs.connection.get_root_members(self.id()).map_err(GcError::GlobalDb)? {
+            if let Some(peer) = self.dfs.connection.get_peer(peer).map_err(GcError::GlobalDb)? {
+                peers.push(peer.peer_id());
+            }
+        }
+
+        // a retention reaching before the epoch keeps unacknowledged tombstones forever
+        let expired_before = SystemTime::now().checked_sub(self.dfs.cfg().tombstone_retention);
+        let mut removed = 0;
+        let mut parents = Vec::new();
+        for entry in self.connection.get_all_direntries()? {
+            let deleted_at = match entry.deleted_at() {
+                Some(at) => at,
+                None => continue,
+            };
+
+            let mut acknowledged = true;
+            for peer in &peers {
+                if self.connection.get_base(peer, entry.path())? != Some(TOMBSTONE) {
+                    acknowledged = false;
+                    break
+                }
+            }
+
+            // an earlier removal of a parent may already have removed this entry
+            let expired = expired_before.is_some_and(|before| deleted_at < before);
+            if (acknowledged || expired) && self.connection.get_direntry(entry.id())?.is_some() {
+                parents.extend(entry.parent_id());
+                self.remove_subtree(entry)?;
+                removed += 1;
+            }
+        }
+
+        for parent in parents {
+            self.rehash_from(parent)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// All unresolved [conflicts](Conflict) in this root.
+    pub fn conflicts(&self) -> Result<Vec<Conflict>, ConflictError<LS::Error>> {
+        Ok(self.connection.get_conflicts()?)
+    }
+
+    /// Resolve the conflict with this (root relative) conflict copy by choosing which
+    /// version to keep. The copy is removed either way. When the copy is kept, it becomes
+    /// a new change of this peer, so other peers take it over when they sync.
+    pub fn resolve_conflict(&self, copy: impl AsRef<Path>, resolution: Resolution) -> Result<(), ConflictError<LS::Error>> {
+        let copy = copy.as_ref();
+        let conflict = self.connection.get_conflicts()?.into_iter()
+            .find(|c| c.copy() == copy)
+            .ok_or_else(|| ConflictError::NotFound(copy.to_path_buf()))?;
+
+        let copy_on_disk = self.path_on_disk(conflict.copy()).map_err(ConflictError::UnsafePath)?;
+        match resolution {
+            Resolution::KeepCurrent => match fs::remove_file(&copy_on_disk) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(ConflictError::Io(copy_on_disk, e)),
+                _ => {}
+            },
+            Resolution::KeepCopy => {
+                let path_on_disk = self.path_on_disk(conflict.path()).map_err(ConflictError::UnsafePath)?;
+                fs::rename(&copy_on_disk, &path_on_disk).map_err(|e| ConflictError::Io(copy_on_disk, e))?;
+
+                if let Some(mut contents) = self.find(conflict.copy(), false)? {
+                    if let Some(current) = self.find(conflict.path(), true)? {
+                        contents.version_mut().merge(current.version());
+                    }
+                    contents.version_mut().bump(&self.dfs.peer_id());
+                    self.put_file(conflict.path(), &contents)?;
+                }
+            }
+        }
+
+        self.delete_path(conflict.copy(), None)?;
+        self.connection.remove_conflict(conflict.copy())?;
+        Ok(())
+    }
+
     /// Get a [`DirEntry`] by it's uuid.
     pub fn get_by_id(&self, id: Uuid) -> Result<Option<DirEntry<'_, 'dfs, GS, LS>>, GetDirEntryError<LS::Error>> {
         Ok(
@@ -327,24 +1006,98 @@ impl<'dfs, GS: GlobalStore, LS: LocalStore> ConnectedRoot<'dfs, GS, LS> {
 }
 
 
+/// Check that a root relative path stays inside the root and out of its local database at
+/// `local_db`, as set in the [`Config`](crate::config::Config). Entries from peers are checked
+/// with this before anything is done with them.
+///
+/// ```
+/// # use std::path::Path;
+/// # use dfs::root::check_path;
+/// let local_db = Path::new(".dfs");
+/// assert!(check_path(Path::new("/"), local_db).is_ok());
+/// assert!(check_path(Path::new("/notes/todo.txt"), local_db).is_ok());
+///
+/// assert!(check_path(Path::new("/../escape"), local_db).is_err());
+/// assert!(check_path(Path::new("notes"), local_db).is_err());
+/// assert!(check_path(Path::new("/.dfs/x"), local_db).is_err());
+/// ```
+pub fn check_path(path: &Path, local_db: &Path) -> Result<(), UnsafePath> {
+    let mut components = path.components();
+    let inside = components.next() == Some(Component::RootDir)
+        && components.all(|c| matches!(c, Component::Normal(_)));
+
+    // a local database outside the root can't be reached through it
+    let in_local_db = local_db.is_relative() && {
+        let local_db: PathBuf = local_db.components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        local_db.components().next().is_some()
+            && path.strip_prefix("/").is_ok_and(|p| p.starts_with(&local_db))
+    };
+
+    if inside && !in_local_db {
+        Ok(())
+    } else {
+        Err(UnsafePath(path.to_path_buf()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::fs::create_dir_all;
+    use std::fs::{self, create_dir_all};
     use std::ops::Deref;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
 
     use temp_testdir::TempDir;
+    use uuid::Uuid;
 
     use crate::config::Config;
     use crate::Dfs;
+    use crate::global_store::{GlobalStore, PutStatus};
+    use crate::root::ConnectedRoot;
+    use crate::root::blocks::hash;
+    use crate::root::dir_entry::StorableDirEntry;
+    use crate::root::local_store::LocalStore;
+    use crate::root::local_store::heed_store::Heed;
+    use crate::root::share::ShareMode;
+    use crate::test::populated_tempdir;
+
+    /// Putting an entry without `overwrite` keeps the entry which is there.
+    fn put_keeps_existing<GS: GlobalStore, LS: LocalStore>(root: &ConnectedRoot<'_, GS, LS>) {
+        let id = Uuid::new_v4();
+        let first = StorableDirEntry::new(id, "/first".into(), Some(root.id()), false, [1; 32]);
+        let second = StorableDirEntry::new(id, "/second".into(), Some(root.id()), false, [2; 32]);
+        let path = || root.connection.get_direntry(id).unwrap().unwrap().path().to_path_buf();
+
+        assert_eq!(root.connection.put_direntry(id, &first, false).unwrap(), PutStatus::Ok);
+        assert_eq!(root.connection.put_direntry(id, &second, false).unwrap(), PutStatus::Exists);
+        assert_eq!(path(), Path::new("/first"));
+        assert_eq!(root.connection.put_direntry(id, &second, true).unwrap(), PutStatus::Ok);
+        assert_eq!(path(), Path::new("/second"));
+    }
+
+    #[test]
+    fn put_without_overwrite() {
+        let sled_dir = TempDir::new("test put sled", true);
+        let heed_dir = TempDir::new("test put heed", true);
+        let global = TempDir::new("global put", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+
+        put_keeps_existing(&dfs.new_root(&sled_dir, "sled").unwrap().connect().unwrap());
+        put_keeps_existing(&dfs.new_root(&heed_dir, "heed").unwrap().connect_with::<Heed>().unwrap());
+
+        // the heed environment is closed when the store is dropped, so it can be opened again
+        let root = dfs.get_root_by_name("heed").unwrap().unwrap();
+        put_keeps_existing(&root.connect_with::<Heed>().unwrap());
+    }
 
     #[test]
     fn connect() {
         let root_a_dir = TempDir::new("test a", true);
         let global = TempDir::new("global", true);
 
-        let mut cfg = Config::default();
-        cfg.global_db = global.as_ref().to_path_buf();
+        let cfg = Config::test_config(&global);
 
         let dfs = Dfs::new(cfg.clone()).unwrap();
 
@@ -364,8 +1117,7 @@ mod tests {
 
         let global = TempDir::new("global", true);
 
-        let mut cfg = Config::default();
-        cfg.global_db = global.as_ref().to_path_buf();
+        let cfg = Config::test_config(&global);
 
         let dfs = Dfs::new(cfg.clone()).unwrap();
 
@@ -377,6 +1129,218 @@ mod tests {
         assert_eq!(root_dir.path(), PathBuf::from("/"))
     }
 
+    #[tokio::test]
+    async fn manifest() {
+        let root_dir = populated_tempdir("test manifest");
+        let global = TempDir::new("global manifest", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+
+        let connected = dfs.new_root(&root_dir, "a").unwrap().connect().unwrap();
+        connected.index().await.unwrap();
+
+        let entries = connected.manifest(None, None).unwrap().unwrap();
+        let mut paths: Vec<_> = entries.iter().map(|e| e.path().to_path_buf()).collect();
+        assert_eq!(paths[0], PathBuf::from("/"));
+        assert_eq!(entries[0].id(), connected.id());
+
+        // the .dfs folder isn't indexed
+        paths.sort();
+        assert_eq!(paths, vec![
+            PathBuf::from("/"),
+            PathBuf::from("/a"),
+            PathBuf::from("/a/ipsum.txt"),
+            PathBuf::from("/test.txt"),
+        ]);
+
+        let a = entries.iter().find(|e| e.path() == Path::new("/a")).unwrap();
+        let subtree = connected.manifest(Some(a.id()), None).unwrap().unwrap();
+        assert_eq!(subtree.len(), 2);
+        assert_eq!(subtree[1].parent_id(), Some(a.id()));
+
+        assert!(connected.manifest(Some(Uuid::new_v4()), None).unwrap().is_none());
+
+        // directories are followed by everything under them
+        let a_index = entries.iter().position(|e| e.id() == a.id()).unwrap();
+        assert_eq!(entries[a_index + 1].parent_id(), Some(a.id()));
+
+        // page by page, continuing after the last entry of the previous page
+        let mut paged = Vec::new();
+        while let Some(page) = connected.manifest_page(None, None, paged.last().map(StorableDirEntry::id), 1).unwrap() {
+            if page.is_empty() {
+                break
+            }
+            paged.extend(page);
+        }
+        assert_eq!(paged.iter().map(|e| e.id()).collect::<Vec<_>>(), entries.iter().map(|e| e.id()).collect::<Vec<_>>());
+
+        let depth_0 = connected.manifest_page(None, Some(0), None, 10).unwrap().unwrap();
+        assert_eq!(depth_0.len(), 1);
+        assert!(connected.manifest_page(None, Some(0), Some(a.id()), 10).unwrap().is_none());
+        assert!(connected.manifest_page(None, None, Some(Uuid::new_v4()), 10).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reindex() {
+        let root_dir = populated_tempdir("test reindex");
+        let global = TempDir::new("global reindex", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+
+        let connected = dfs.new_root(&root_dir, "a").unwrap().connect().unwrap();
+        connected.index().await.unwrap();
+        let ids = |c: &ConnectedRoot<_, _>| {
+            let mut ids: Vec<_> = c.manifest(None, None).unwrap().unwrap().iter().map(|e| e.id()).collect();
+            ids.sort();
+            ids
+        };
+        let before = ids(&connected);
+        let hash = connected.root_hash().unwrap();
+
+        // nothing changed, so nothing changes in the index
+        connected.index().await.unwrap();
+        assert_eq!(ids(&connected), before);
+        assert_eq!(connected.root_hash().unwrap(), hash);
+
+        // changing a file changes the hash of all its ancestors, but keeps its id
+        let version = |c: &ConnectedRoot<_, _>| c.get_by_path("/a/ipsum.txt").unwrap().unwrap().version().get(&dfs.peer_id());
+        assert_eq!(version(&connected), 1);
+        fs::write(root_dir.join("a/ipsum.txt"), "changed").unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(ids(&connected), before);
+        assert_ne!(connected.root_hash().unwrap(), hash);
+        assert_eq!(version(&connected), 2);
+
+        // removed files leave a tombstone in the index
+        fs::write(root_dir.join("a/ipsum.txt"), fs::read("tests/fake_dir/a/ipsum.txt").unwrap()).unwrap();
+        fs::remove_file(root_dir.join("test.txt")).unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(ids(&connected), before);
+        assert!(connected.get_by_path("/test.txt").unwrap().is_none());
+        let tombstone = connected.manifest(None, None).unwrap().unwrap().into_iter()
+            .find(|e| e.path() == Path::new("/test.txt"))
+            .unwrap();
+        assert!(tombstone.is_deleted());
+
+        // when the file comes back, so does the entry, and the hash is back to before the changes
+        fs::write(root_dir.join("test.txt"), fs::read("tests/fake_dir/test.txt").unwrap()).unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(ids(&connected), before);
+        assert_eq!(connected.root_hash().unwrap(), hash);
+        assert!(connected.get_by_path("/test.txt").unwrap().unwrap().version() > tombstone.version());
+    }
+
+    #[tokio::test]
+    async fn moves() {
+        let root_dir = populated_tempdir("test moves");
+        let global = TempDir::new("global moves", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+
+        let connected = dfs.new_root(&root_dir, "a").unwrap().connect().unwrap();
+        connected.index().await.unwrap();
+        let id = |path: &str| connected.get_by_path(path).unwrap().map(|e| e.id());
+        let (a, ipsum, test) = (id("/a"), id("/a/ipsum.txt"), id("/test.txt"));
+
+        // moved entries keep their id, everything under a moved directory included,
+        // and leave a tombstone behind
+        fs::rename(root_dir.join("a"), root_dir.join("b")).unwrap();
+        fs::rename(root_dir.join("test.txt"), root_dir.join("b/test.txt")).unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(id("/b"), a);
+        assert_eq!(id("/b/ipsum.txt"), ipsum);
+        assert_eq!(id("/b/test.txt"), test);
+        assert!(connected.find(Path::new("/a"), true).unwrap().unwrap().is_deleted());
+        assert!(connected.find(Path::new("/test.txt"), true).unwrap().unwrap().is_deleted());
+
+        // a file which was copied and removed is recognized by its contents
+        fs::copy(root_dir.join("b/test.txt"), root_dir.join("copy.txt")).unwrap();
+        fs::remove_file(root_dir.join("b/test.txt")).unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(id("/copy.txt"), test);
+
+        // but not while the original is still there
+        fs::copy(root_dir.join("copy.txt"), root_dir.join("other.txt")).unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(id("/copy.txt"), test);
+        assert_ne!(id("/other.txt"), test);
+    }
+
+    #[tokio::test]
+    async fn tombstones_expire() {
+        let root_dir = populated_tempdir("test tombstones");
+        let global = TempDir::new("global tombstones", true);
+        let other_global = TempDir::new("global tombstones other", true);
+        let mut cfg = Config::test_config(&global);
+        cfg.tombstone_retention = Duration::from_secs(60);
+        let dfs = Dfs::new(cfg).unwrap();
+
+        // the peer never acknowledges the tombstone, it has to expire
+        let other = Dfs::new(Config::test_config(&other_global)).unwrap();
+        let peer = dfs.add_peer(other.identity()).unwrap();
+        let root = dfs.new_root(&root_dir, "a").unwrap();
+        dfs.share_root(&root, &peer, ShareMode::ReadWrite).unwrap();
+
+        let connected = root.connect().unwrap();
+        connected.index().await.unwrap();
+        fs::remove_file(root_dir.join("test.txt")).unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(connected.gc_tombstones().unwrap(), 0);
+
+        let mut expired = connected.find(Path::new("/test.txt"), true).unwrap().unwrap();
+        expired.tombstone(SystemTime::now() - Duration::from_secs(120));
+        connected.connection.put_direntry(expired.id(), &expired, true).unwrap();
+        assert_eq!(connected.gc_tombstones().unwrap(), 1);
+        assert!(connected.find(Path::new("/test.txt"), true).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn tombstones_kept_forever() {
+        let root_dir = populated_tempdir("test tombstones forever");
+        let global = TempDir::new("global tombstones forever", true);
+        let other_global = TempDir::new("global tombstones forever other", true);
+        let mut cfg = Config::test_config(&global);
+        cfg.tombstone_retention = Duration::MAX;
+        let dfs = Dfs::new(cfg).unwrap();
+
+        let other = Dfs::new(Config::test_config(&other_global)).unwrap();
+        let peer = dfs.add_peer(other.identity()).unwrap();
+        let root = dfs.new_root(&root_dir, "a").unwrap();
+        dfs.share_root(&root, &peer, ShareMode::ReadWrite).unwrap();
+
+        let connected = root.connect().unwrap();
+        connected.index().await.unwrap();
+        fs::remove_file(root_dir.join("test.txt")).unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(connected.gc_tombstones().unwrap(), 0);
+        assert!(connected.find(Path::new("/test.txt"), true).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn shared_blocks() {
+        let root_dir = TempDir::new("test shared blocks", true);
+        let global = TempDir::new("global shared blocks", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+
+        fs::write(root_dir.join("one"), "the same").unwrap();
+        fs::write(root_dir.join("two"), "the same").unwrap();
+        let connected = dfs.new_root(&root_dir, "a").unwrap().connect().unwrap();
+        connected.index().await.unwrap();
+        let block = hash(b"the same");
+
+        // the other file still provides the block, whichever file goes
+        fs::remove_file(root_dir.join("one")).unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(connected.read_block(&block).unwrap().as_deref(), Some(&b"the same"[..]));
+
+        fs::write(root_dir.join("one"), "the same").unwrap();
+        connected.index().await.unwrap();
+        fs::remove_file(root_dir.join("two")).unwrap();
+        connected.index().await.unwrap();
+        assert_eq!(connected.read_block(&block).unwrap().as_deref(), Some(&b"the same"[..]));
+
+        fs::write(root_dir.join("one"), "changed").unwrap();
+        connected.index().await.unwrap();
+        assert!(connected.read_block(&block).unwrap().is_none());
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     #[ignore]
     async fn large_index() {
@@ -389,7 +1353,7 @@ mod tests {
 
         let root_a = dfs.new_root("/home/jonathan/.config", "a").unwrap();
 
-        let mut connected_a = root_a.connect().unwrap();
+        let connected_a = root_a.connect().unwrap();
 
         connected_a.index().await.unwrap()
     }
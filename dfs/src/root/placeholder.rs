@@ -0,0 +1,155 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use libp2p::PeerId;
+use thiserror::Error;
+
+use crate::global_store::GlobalStore;
+use crate::network::Node;
+use crate::network::blocks::DownloadError;
+use crate::network::manifest::ManifestError;
+use crate::root::{ConnectedRoot, UnsafePath};
+use crate::root::blocks::hash_file;
+use crate::root::dir_entry::{Presence, StorableDirEntry};
+use crate::root::local_store::LocalStore;
+
+#[derive(Debug, Error)]
+pub enum PlaceholderError<GSE, LSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] LSE),
+
+    #[error("global db error: {0}")]
+    GlobalDb(GSE),
+
+    #[error("no file at {0:?}")]
+    NotFound(PathBuf),
+
+    #[error("{0:?} isn't a placeholder")]
+    NotPlaceholder(PathBuf),
+
+    #[error("the contents of {0:?} aren't on disk")]
+    NotLocal(PathBuf),
+
+    #[error("{0:?} changed since the root was last indexed")]
+    Changed(PathBuf),
+
+    #[error("none of the connected peers has the contents of {0:?}")]
+    NoCopies(PathBuf),
+
+    #[error("failed to download {0:?}: {1}")]
+    Download(PathBuf, DownloadError<GSE>),
+
+    #[error("failed to get the manifest of a peer: {0}")]
+    Manifest(ManifestError<GSE>),
+
+    #[error("{0}")]
+    UnsafePath(UnsafePath),
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+impl<'dfs, GS: GlobalStore, LS: LocalStore> ConnectedRoot<'dfs, GS, LS> {
+    /// Get the contents of a [placeholder](Presence::Placeholder) from the connected peers the root
+    /// is shared with, so the file is on disk again.
+    pub async fn hydrate(&self, node: &mut Node<'_, GS>, path: impl AsRef<Path>) -> Result<(), PlaceholderError<GS::Error, LS::Error>> {
+        let path = path.as_ref();
+        let entry = self.placeholder_file(path)?;
+        if !entry.is_placeholder() {
+            return Err(PlaceholderError::NotPlaceholder(path.to_path_buf()))
+        }
+
+        // the contents would overwrite whatever was written to the placeholder
+        let on_disk = self.path_on_disk(path).map_err(PlaceholderError::UnsafePath)?;
+        match fs::metadata(&on_disk) {
+            Ok(meta) if meta.is_file() && meta.len() == 0 => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Ok(_) => return Err(PlaceholderError::Changed(path.to_path_buf())),
+            Err(e) => return Err(PlaceholderError::Io(on_disk, e)),
+        }
+
+        let peers = self.connected_members(node)?;
+        if peers.is_empty() {
+            return Err(PlaceholderError::NoCopies(path.to_path_buf()))
+        }
+
+        node.download(self.id(), &entry, &peers).await
+            .map_err(|e| PlaceholderError::Download(path.to_path_buf(), e))?;
+        self.put_file(path, &entry)?;
+
+        Ok(())
+    }
+
+    /// Remove the contents of a file from disk, leaving an empty [placeholder](Presence::Placeholder)
+    /// which is still listed in the root and can be [hydrated](ConnectedRoot::hydrate) later.
+    ///
+    /// This only happens when one of the connected peers has the same contents on disk, so they
+    /// aren't lost.
+    pub async fn dehydrate(&self, node: &mut Node<'_, GS>, path: impl AsRef<Path>) -> Result<(), PlaceholderError<GS::Error, LS::Error>> {
+        let path = path.as_ref();
+        let entry = self.placeholder_file(path)?;
+        if !entry.is_local() {
+            return Err(PlaceholderError::NotLocal(path.to_path_buf()))
+        }
+        // the contents of empty files are known, so there is nothing to remove
+        if entry.size() == 0 {
+            return Ok(())
+        }
+
+        let on_disk = self.path_on_disk(path).map_err(PlaceholderError::UnsafePath)?;
+        let hashes = hash_file(&on_disk).map_err(|e| PlaceholderError::Io(on_disk.clone(), e))?;
+        if hashes.hash != *entry.hash() {
+            return Err(PlaceholderError::Changed(path.to_path_buf()))
+        }
+
+        let mut copy = false;
+        for peer in self.connected_members(node)? {
+            let manifest = node.fetch_manifest(peer, self.id(), None, None).await
+                .map_err(PlaceholderError::Manifest)?;
+            if manifest.iter().any(|e| e.path() == path && e.hash() == entry.hash() && e.is_local()) {
+                copy = true;
+                break
+            }
+        }
+        if !copy {
+            return Err(PlaceholderError::NoCopies(path.to_path_buf()))
+        }
+
+        fs::write(&on_disk, b"").map_err(|e| PlaceholderError::Io(on_disk, e))?;
+
+        let old = entry.clone();
+        let mut entry = entry;
+        entry.set_presence(Presence::Placeholder);
+        self.connection.put_direntry(entry.id(), &entry, true)?;
+        self.replace_blocks(Some(&old), None)?;
+
+        Ok(())
+    }
+
+    fn placeholder_file(&self, path: &Path) -> Result<StorableDirEntry, PlaceholderError<GS::Error, LS::Error>> {
+        match self.find(path, false)? {
+            Some(entry) if entry.is_file() => Ok(entry),
+            _ => Err(PlaceholderError::NotFound(path.to_path_buf())),
+        }
+    }
+
+    /// The peers the root is shared with, which this node may receive from and is connected to.
+    fn connected_members(&self, node: &Node<'_, GS>) -> Result<Vec<PeerId>, PlaceholderError<GS::Error, LS::Error>> {
+        let mut peers = Vec::new();
+        for (peer, mode) in self.dfs.connection.get_root_members(self.id()).map_err(PlaceholderError::GlobalDb)? {
+            if !mode.receives() {
+                continue
+            }
+
+            if let Some(peer) = self.dfs.connection.get_peer(peer).map_err(PlaceholderError::GlobalDb)? {
+                let peer = peer.peer_id();
+                if node.is_connected(&peer) {
+                    peers.push(peer);
+                }
+            }
+        }
+
+        Ok(peers)
+    }
+}
@@ -0,0 +1,151 @@
+use std::path::{Component, Path};
+
+use serde::{Serialize, Deserialize};
+
+/// Which parts of a root a peer keeps on disk. Entries outside the selection are still known
+/// (their metadata is kept in the index, see [`StorableDirEntry::is_excluded`](crate::root::dir_entry::StorableDirEntry::is_excluded)),
+/// but their contents aren't synced.
+///
+/// Patterns are root relative paths, in which `*` matches any part of a name, `?` a single
+/// character and `**` any number of directories. A pattern matches an entry and everything
+/// under it. An entry is selected when no exclude pattern matches it and, if there are include
+/// patterns, one of them matches it. The directories leading to an included entry are selected too.
+///
+/// ```
+/// # use std::path::Path;
+/// # use dfs::root::selection::Selection;
+/// let selection = Selection::default()
+///     .include("/docs")
+///     .include("/photos/**/*.jpg")
+///     .exclude("/docs/archive");
+///
+/// assert!(selection.contains(Path::new("/docs/report.txt"), false));
+/// assert!(!selection.contains(Path::new("/docs/archive/2019.txt"), false));
+/// assert!(selection.contains(Path::new("/photos/2021/summer/beach.jpg"), false));
+/// assert!(!selection.contains(Path::new("/photos/2021/summer/beach.raw"), false));
+/// assert!(!selection.contains(Path::new("/music"), true));
+///
+/// // the way to the included photos
+/// assert!(selection.contains(Path::new("/photos/2021"), true));
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Selection {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+/// How far a pattern matches a path.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum Match {
+    No,
+    /// the path is a directory leading to entries the pattern matches
+    Ancestor,
+    /// the pattern matches the path or one of its ancestors
+    Full,
+}
+
+impl Selection {
+    /// Select only what matches this pattern (or another include pattern).
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Leave out what matches this pattern, even when it is included.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    pub fn includes(&self) -> &[String] {
+        &self.include
+    }
+
+    pub fn excludes(&self) -> &[String] {
+        &self.exclude
+    }
+
+    /// Whether the entry at this (root relative) path is selected.
+    pub fn contains(&self, path: &Path, is_dir: bool) -> bool {
+        let path: Vec<_> = path.components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        let path: Vec<_> = path.iter().map(|c| c.as_ref()).collect();
+
+        if self.exclude.iter().any(|p| matches(&components(p), &path) == Match::Full) {
+            return false
+        }
+        if self.include.is_empty() {
+            return true
+        }
+
+        match self.include.iter().map(|p| matches(&components(p), &path)).max() {
+            Some(Match::Full) => true,
+            Some(Match::Ancestor) => is_dir,
+            _ => false,
+        }
+    }
+}
+
+fn components(pattern: &str) -> Vec<&str> {
+    pattern.split('/').filter(|c| !c.is_empty()).collect()
+}
+
+fn matches(pattern: &[&str], path: &[&str]) -> Match {
+    match (pattern.split_first(), path.split_first()) {
+        (None, _) => Match::Full,
+        (Some((&"**", rest)), _) => {
+            let here = matches(rest, path);
+            match path.split_first() {
+                Some((_, deeper)) if here != Match::Full => here.max(matches(pattern, deeper)),
+                _ => here,
+            }
+        }
+        (Some(_), None) => Match::Ancestor,
+        (Some((p, rest)), Some((name, deeper))) if wildcard(p.as_bytes(), name.as_bytes()) => matches(rest, deeper),
+        _ => Match::No,
+    }
+}
+
+/// Match a single name against a pattern with `*` and `?`.
+fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => wildcard(rest, name) || (!name.is_empty() && wildcard(pattern, &name[1..])),
+        (Some((b'?', rest)), Some((_, name))) => wildcard(rest, name),
+        (Some((p, rest)), Some((n, name))) if p == n => wildcard(rest, name),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::root::selection::Selection;
+
+    #[test]
+    fn patterns() {
+        let everything = Selection::default();
+        assert!(everything.contains(Path::new("/a/b/c"), false));
+
+        let selection = Selection::default().exclude("*.tmp").exclude("/build");
+        assert!(selection.contains(Path::new("/src/main.rs"), false));
+        assert!(!selection.contains(Path::new("/notes.tmp"), false));
+        // patterns are anchored at the top of the root
+        assert!(selection.contains(Path::new("/src/notes.tmp"), false));
+        assert!(!selection.contains(Path::new("/build/out/binary"), false));
+
+        let selection = Selection::default().include("/**/*.tmp").include("/a?c");
+        assert!(selection.contains(Path::new("/src/notes.tmp"), false));
+        assert!(selection.contains(Path::new("/notes.tmp"), false));
+        assert!(selection.contains(Path::new("/abc/d"), false));
+        assert!(!selection.contains(Path::new("/abbc"), false));
+        // any directory may contain a .tmp file
+        assert!(selection.contains(Path::new("/src"), true));
+        assert!(!selection.contains(Path::new("/src"), false));
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Serialize, Deserialize};
+
+/// How a root is shared with a peer. The mode is always seen from the side
+/// of the local node: it describes what we allow the peer to do with our copy of the root.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShareMode {
+    /// The peer receives our changes, but changes made by the peer are never applied locally.
+    ReadOnly,
+
+    /// Changes flow both ways.
+    ReadWrite,
+
+    /// We apply changes made by the peer, but never send ours to it.
+    ReceiveOnly,
+
+    /// Changes flow both ways, but the peer only gets the root sealed with its
+    /// [key](crate::encryption::RootKey): it stores and forwards the root without being able
+    /// to read it. The root must be [encrypted](crate::Dfs::encrypt_root).
+    Encrypted,
+}
+
+impl ShareMode {
+    /// Whether the contents of this root may be sent to the peer.
+    ///
+    /// ```
+    /// # use dfs::root::share::ShareMode;
+    /// assert!(ShareMode::ReadOnly.sends());
+    /// assert!(ShareMode::ReadWrite.sends());
+    /// assert!(!ShareMode::ReceiveOnly.sends());
+    /// assert!(ShareMode::Encrypted.sends());
+    /// ```
+    pub fn sends(&self) -> bool {
+        matches!(self, ShareMode::ReadOnly | ShareMode::ReadWrite | ShareMode::Encrypted)
+    }
+
+    /// Whether changes from the peer may be applied to this root.
+    ///
+    /// ```
+    /// # use dfs::root::share::ShareMode;
+    /// assert!(!ShareMode::ReadOnly.receives());
+    /// assert!(ShareMode::ReadWrite.receives());
+    /// assert!(ShareMode::ReceiveOnly.receives());
+    /// assert!(ShareMode::Encrypted.receives());
+    /// ```
+    pub fn receives(&self) -> bool {
+        matches!(self, ShareMode::ReadWrite | ShareMode::ReceiveOnly | ShareMode::Encrypted)
+    }
+
+    /// The mode as seen from the other side. When we share a root read-only with a peer,
+    /// that peer only receives from us. A peer which only gets the root encrypted exchanges
+    /// everything it has with us.
+    ///
+    /// ```
+    /// # use dfs::root::share::ShareMode;
+    /// assert_eq!(ShareMode::ReadOnly.inverse(), ShareMode::ReceiveOnly);
+    /// assert_eq!(ShareMode::ReadWrite.inverse(), ShareMode::ReadWrite);
+    /// assert_eq!(ShareMode::ReceiveOnly.inverse(), ShareMode::ReadOnly);
+    /// assert_eq!(ShareMode::Encrypted.inverse(), ShareMode::ReadWrite);
+    /// ```
+    pub fn inverse(&self) -> ShareMode {
+        match self {
+            ShareMode::ReadOnly => ShareMode::ReceiveOnly,
+            ShareMode::ReadWrite => ShareMode::ReadWrite,
+            ShareMode::ReceiveOnly => ShareMode::ReadOnly,
+            ShareMode::Encrypted => ShareMode::ReadWrite,
+        }
+    }
+}
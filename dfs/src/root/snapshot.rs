@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::diff::{self, Change};
+use crate::global_store::GlobalStore;
+use crate::root::{ConnectedRoot, GetDirEntryError, UnsafePath};
+use crate::root::blocks::hash_file;
+use crate::root::dir_entry::StorableDirEntry;
+use crate::root::history::HistoryError;
+use crate::root::local_store::LocalStore;
+use crate::root::merkle::Hash;
+
+/// The folder in the local db of a root in which contents are staged while restoring a snapshot.
+const RESTORE_DIR: &str = "restore";
+
+#[derive(Debug, Error)]
+pub enum SnapshotError<GSE, LSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] LSE),
+
+    #[error("no snapshot with id {0}")]
+    NotFound(Uuid),
+
+    #[error("failed to keep the previous version of a file: {0}")]
+    History(HistoryError<GSE, LSE>),
+
+    #[error("{0}")]
+    UnsafePath(UnsafePath),
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+impl<GSE, LSE> From<GetDirEntryError<LSE>> for SnapshotError<GSE, LSE> {
+    fn from(e: GetDirEntryError<LSE>) -> Self {
+        match e {
+            GetDirEntryError::DbInteractionError(e) => SnapshotError::DbInteractionError(e),
+        }
+    }
+}
+
+/// The index of a root at some moment, see [`ConnectedRoot::snapshot`]. Only the entries are
+/// kept, with the hashes of the contents of the files, not the contents themselves.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    id: Uuid,
+    label: String,
+    /// seconds since the unix epoch
+    created: u64,
+    /// all entries of the root except tombstones, in the order of [`ConnectedRoot::manifest`]
+    entries: Vec<StorableDirEntry>,
+}
+
+impl Snapshot {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn created(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.created)
+    }
+
+    /// The merkle hash of the whole root when the snapshot was taken.
+    pub fn root_hash(&self) -> Option<&Hash> {
+        self.entries.first().map(|e| e.hash())
+    }
+
+    pub fn entries(&self) -> &[StorableDirEntry] {
+        &self.entries
+    }
+}
+
+/// What [restoring a snapshot](ConnectedRoot::restore_snapshot) did. Paths are relative to the root.
+#[derive(Debug, Default, Clone)]
+pub struct RestoreReport {
+    /// files and directories which were brought back to the state in the snapshot
+    pub restored: Vec<PathBuf>,
+
+    /// entries which weren't in the snapshot, and were deleted
+    pub removed: Vec<PathBuf>,
+
+    /// files of which the contents in the snapshot are neither in the root anymore nor kept
+    /// as a [previous version](crate::root::history::FileVersion). They were left as they are.
+    pub missing: Vec<PathBuf>,
+}
+
+impl<'dfs, GS: GlobalStore, LS: LocalStore> ConnectedRoot<'dfs, GS, LS> {
+    /// Take a snapshot of the index of this root, to compare with or go back to later. The root
+    /// should be [indexed](ConnectedRoot::index) first, so the snapshot includes the latest changes.
+    ///
+    /// ```rust
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// # use dfs::config::Config;
+    /// # use dfs::Dfs;
+    /// # use dfs::test::populated_tempdir;
+    /// # let tempdir = populated_tempdir("snapshot");
+    /// # let dfs = Dfs::new(Config::test_config(&tempdir)).unwrap();
+    /// # let root = dfs.new_root(&tempdir, "test").unwrap().connect().unwrap();
+    /// root.index().await.unwrap();
+    /// let snapshot = root.snapshot("before the sync").unwrap();
+    ///
+    /// assert_eq!(root.list_snapshots().unwrap()[0].label(), "before the sync");
+    /// assert!(root.diff_snapshots(snapshot.id(), snapshot.id()).unwrap().is_empty());
+    /// # }
+    /// ```
+    pub fn snapshot(&self, label: impl Into<String>) -> Result<Snapshot, SnapshotError<GS::Error, LS::Error>> {
+        let entries = self.manifest(None, None)?
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| !e.is_deleted())
+            .collect();
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let snapshot = Snapshot {
+            id: Uuid::new_v4(),
+            label: label.into(),
+            created,
+            entries,
+        };
+        self.connection.put_snapshot(&snapshot)?;
+
+        Ok(snapshot)
+    }
+
+    /// All snapshots of this root, oldest first.
+    pub fn list_snapshots(&self) -> Result<Vec<Snapshot>, SnapshotError<GS::Error, LS::Error>> {
+        let mut snapshots = self.connection.get_snapshots()?;
+        snapshots.sort_by_key(|s| s.created);
+        Ok(snapshots)
+    }
+
+    /// Forget a snapshot. Returns whether there was a snapshot with this id.
+    pub fn remove_snapshot(&self, id: Uuid) -> Result<bool, SnapshotError<GS::Error, LS::Error>> {
+        Ok(self.connection.remove_snapshot(id)?.is_some())
+    }
+
+    fn get_snapshot(&self, id: Uuid) -> Result<Snapshot, SnapshotError<GS::Error, LS::Error>> {
+        self.connection.get_snapshot(id)?.ok_or(SnapshotError::NotFound(id))
+    }
+
+    /// The [changes](Change) from snapshot `a` to snapshot `b`.
+    pub fn diff_snapshots(&self, a: Uuid, b: Uuid) -> Result<Vec<Change>, SnapshotError<GS::Error, LS::Error>> {
+        let a = self.get_snapshot(a)?;
+        let b = self.get_snapshot(b)?;
+        Ok(diff::changes(a.entries, b.entries))
+    }
+
+    /// Bring the root back to the state of a snapshot. Entries which were added since are deleted,
+    /// and files which changed get their contents from the snapshot back. Those contents are taken
+    /// from any file in the root which has them, or from the
+    /// [previous versions](crate::root::history::FileVersion) of the file. Contents which are
+    /// replaced or deleted are kept as previous versions in turn.
+    ///
+    /// All of this is a change by this peer, so the peers take the restored state over when they sync.
+    pub fn restore_snapshot(&self, id: Uuid) -> Result<RestoreReport, SnapshotError<GS::Error, LS::Error>> {
+        let snapshot = self.get_snapshot(id)?;
+        let mut report = RestoreReport::default();
+
+        let wanted: HashMap<&Path, &StorableDirEntry> = snapshot.entries.iter()
+            .filter(|e| !e.is_root())
+            .map(|e| (e.path(), e))
+            .collect();
+        let current: Vec<_> = self.manifest(None, None)?
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| !e.is_deleted() && !e.is_root())
+            .collect();
+        let current_by_path: HashMap<&Path, &StorableDirEntry> = current.iter()
+            .map(|e| (e.path(), e))
+            .collect();
+        let on_disk_by_hash: HashMap<&Hash, &StorableDirEntry> = current.iter()
+            .filter(|e| e.is_file() && e.is_local())
+            .map(|e| (e.hash(), e))
+            .collect();
+
+        // first stage the contents of all files which change, since the files they are copied
+        // from may be changed or deleted below
+        let staging = self.local_db_path().join(RESTORE_DIR);
+        fs::create_dir_all(&staging).map_err(|e| SnapshotError::Io(staging.clone(), e))?;
+        let mut staged = Vec::new();
+        for entry in snapshot.entries.iter().filter(|e| e.is_file() && !e.is_root()) {
+            if matches!(current_by_path.get(entry.path()), Some(c) if c.is_file() && c.hash() == entry.hash()) {
+                continue
+            }
+
+            let to = staging.join(staged.len().to_string());
+            if self.stage_contents(entry, on_disk_by_hash.get(entry.hash()).copied(), &to)? {
+                staged.push((entry, to));
+            } else {
+                report.missing.push(entry.path().to_path_buf());
+            }
+        }
+
+        // entries which aren't in the snapshot, or are of another type there
+        let remove: Vec<_> = current.iter()
+            .filter(|c| !matches!(wanted.get(c.path()), Some(w) if w.is_dir() == c.is_dir()))
+            .collect();
+        for entry in &remove {
+            self.save_version(entry).map_err(SnapshotError::History)?;
+        }
+        for entry in remove {
+            // it's gone already when one of its parents was removed
+            if self.find(entry.path(), false)?.is_none() {
+                continue
+            }
+
+            let on_disk = self.path_on_disk(entry.path()).map_err(SnapshotError::UnsafePath)?;
+            let res = if entry.is_dir() { fs::remove_dir_all(&on_disk) } else { fs::remove_file(&on_disk) };
+            match res {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(SnapshotError::Io(on_disk, e)),
+                _ => {}
+            }
+            self.delete_path(entry.path(), None)?;
+            report.removed.push(entry.path().to_path_buf());
+        }
+
+        // the entries of the snapshot are ordered top down, so parents are created first
+        for entry in snapshot.entries.iter().filter(|e| e.is_dir() && !e.is_root()) {
+            if current_by_path.get(entry.path()).is_some_and(|c| c.is_dir()) {
+                continue
+            }
+
+            let on_disk = self.path_on_disk(entry.path()).map_err(SnapshotError::UnsafePath)?;
+            fs::create_dir_all(&on_disk).map_err(|e| SnapshotError::Io(on_disk, e))?;
+            self.ensure_dir(entry.path())?;
+            report.restored.push(entry.path().to_path_buf());
+        }
+
+        for (entry, from) in staged {
+            if let Some(current) = self.find(entry.path(), false)? {
+                self.save_version(&current).map_err(SnapshotError::History)?;
+            }
+
+            // the parent may be excluded, and so not on disk
+            let on_disk = self.path_on_disk(entry.path()).map_err(SnapshotError::UnsafePath)?;
+            if let Some(parent) = on_disk.parent() {
+                fs::create_dir_all(parent).map_err(|e| SnapshotError::Io(parent.to_path_buf(), e))?;
+            }
+            fs::rename(&from, &on_disk).map_err(|e| SnapshotError::Io(on_disk, e))?;
+            let contents = self.local_change(entry.path(), entry.clone())?;
+            self.put_file(entry.path(), &contents)?;
+            report.restored.push(entry.path().to_path_buf());
+        }
+
+        Ok(report)
+    }
+
+    /// Put the contents of the file of `entry` at `to`, copied from `source` or a kept version of
+    /// the file. Returns false when the contents are nowhere to be found.
+    fn stage_contents(&self, entry: &StorableDirEntry, source: Option<&StorableDirEntry>, to: &Path) -> Result<bool, SnapshotError<GS::Error, LS::Error>> {
+        if entry.size() == 0 {
+            fs::write(to, b"").map_err(|e| SnapshotError::Io(to.to_path_buf(), e))?;
+            return Ok(true)
+        }
+
+        if let Some(source) = source {
+            let from = self.path_on_disk(source.path()).map_err(SnapshotError::UnsafePath)?;
+            match fs::copy(&from, to) {
+                // the file may have changed since the root was last indexed
+                Ok(_) => match hash_file(to) {
+                    Ok(hashes) if &hashes.hash == entry.hash() => return Ok(true),
+                    Ok(_) => {}
+                    Err(e) => return Err(SnapshotError::Io(to.to_path_buf(), e)),
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(SnapshotError::Io(from, e)),
+            }
+        }
+
+        let versions = self.file_versions(entry.id())?.into_iter().filter(|v| v.hash() == entry.hash());
+        for version in versions {
+            if self.write_version(&version, to).map_err(SnapshotError::History)? {
+                return Ok(true)
+            }
+        }
+
+        match fs::remove_file(to) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(SnapshotError::Io(to.to_path_buf(), e)),
+            _ => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use temp_testdir::TempDir;
+
+    use crate::config::Config;
+    use crate::Dfs;
+
+    #[tokio::test]
+    async fn restore() {
+        let dir = TempDir::new("snapshot root", true);
+        let global = TempDir::new("snapshot global", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+        let root = dfs.new_root(&dir, "snapshot").unwrap().connect().unwrap();
+
+        fs::write(dir.join("a"), "one").unwrap();
+        fs::write(dir.join("b"), "two").unwrap();
+        root.index().await.unwrap();
+        let before = root.snapshot("before").unwrap();
+
+        fs::remove_file(dir.join("a")).unwrap();
+        fs::write(dir.join("b"), "one").unwrap();
+        fs::create_dir(dir.join("c")).unwrap();
+        fs::write(dir.join("c/d"), "three").unwrap();
+        root.index().await.unwrap();
+        let after = root.snapshot("after").unwrap();
+
+        let changes = root.diff_snapshots(before.id(), after.id()).unwrap();
+        let paths: Vec<_> = changes.iter().map(|c| c.path()).collect();
+        assert_eq!(paths, vec![Path::new("/a"), Path::new("/b"), Path::new("/c")]);
+
+        // the old contents of b are gone, but those of a are still around in b
+        let report = root.restore_snapshot(before.id()).unwrap();
+        assert_eq!(report.restored, vec![Path::new("/a")]);
+        assert_eq!(report.removed, vec![Path::new("/c")]);
+        assert_eq!(report.missing, vec![Path::new("/b")]);
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "one");
+        assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "one");
+        assert!(!dir.join("c").exists());
+
+        let hash = root.root_hash().unwrap();
+        root.index().await.unwrap();
+        assert_eq!(root.root_hash().unwrap(), hash);
+        assert_eq!(root.list_snapshots().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,79 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+
+/// A version vector counts, for every peer, how many changes that peer made to an entry.
+/// Comparing the vectors of two copies of an entry tells whether one copy is a newer version
+/// of the other, or whether both were changed independently:
+///
+/// ```
+/// # use libp2p::PeerId;
+/// # use dfs::root::version::VersionVector;
+/// let (a, b) = (PeerId::random(), PeerId::random());
+///
+/// let mut first = VersionVector::default();
+/// first.bump(&a);
+/// let mut second = first.clone();
+/// second.bump(&b);
+/// assert!(first < second);
+///
+/// // a changes its copy without having seen the change of b
+/// first.bump(&a);
+/// assert!(first.concurrent(&second));
+///
+/// first.merge(&second);
+/// assert!(first > second);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct VersionVector {
+    /// peer id bytes -> number of changes by that peer
+    counters: BTreeMap<Vec<u8>, u64>,
+}
+
+impl VersionVector {
+    /// How many changes the peer made.
+    pub fn get(&self, peer: &PeerId) -> u64 {
+        self.counters.get(&peer.to_bytes()).copied().unwrap_or(0)
+    }
+
+    /// Record a change made by the peer.
+    pub fn bump(&mut self, peer: &PeerId) {
+        *self.counters.entry(peer.to_bytes()).or_default() += 1;
+    }
+
+    /// Include all changes known by `other`. The result is newer than or equal to both vectors.
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (peer, &count) in &other.counters {
+            let own = self.counters.entry(peer.clone()).or_default();
+            *own = (*own).max(count);
+        }
+    }
+
+    /// Whether both vectors contain changes the other doesn't know about.
+    pub fn concurrent(&self, other: &VersionVector) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+}
+
+impl PartialOrd for VersionVector {
+    /// A vector is less than another when all its changes are known by the other. When
+    /// neither knows all changes of the other, the vectors are [concurrent](VersionVector::concurrent).
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let mut res = Ordering::Equal;
+        for peer in self.counters.keys().chain(other.counters.keys()) {
+            let ours = self.counters.get(peer).copied().unwrap_or(0);
+            let theirs = other.counters.get(peer).copied().unwrap_or(0);
+
+            match (res, ours.cmp(&theirs)) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, ordering) => res = ordering,
+                (res, ordering) if res != ordering => return None,
+                _ => {}
+            }
+        }
+
+        Some(res)
+    }
+}
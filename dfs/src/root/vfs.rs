@@ -0,0 +1,463 @@
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::global_store::GlobalStore;
+use crate::root::{ConnectedRoot, UnsafePath};
+use crate::root::blocks::{FileHashes, hash_file};
+use crate::root::dir_entry::StorableDirEntry;
+use crate::root::local_store::LocalStore;
+use crate::root::merkle::Hash;
+
+/// The folder in the local db of a root in which files removed through the [`Vfs`] are set
+/// aside until their removal is stored.
+const REMOVED_DIR: &str = "removed";
+
+#[derive(Debug, Error)]
+pub enum VfsError<LSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] LSE),
+
+    #[error("no entry with id {0}")]
+    NotFound(Uuid),
+
+    #[error("{0:?} doesn't exist")]
+    NoSuchName(PathBuf),
+
+    #[error("{0:?} already exists")]
+    Exists(PathBuf),
+
+    #[error("{0:?} isn't a directory")]
+    NotADirectory(PathBuf),
+
+    #[error("{0:?} is a directory")]
+    IsADirectory(PathBuf),
+
+    #[error("the contents of {0:?} aren't on disk")]
+    NotLocal(PathBuf),
+
+    #[error("{0:?} isn't a valid name")]
+    InvalidName(PathBuf),
+
+    #[error("the file wasn't opened for writing")]
+    ReadOnly,
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+/// A file opened through a [`Vfs`].
+#[derive(Debug)]
+pub struct FileHandle {
+    id: Uuid,
+    file: File,
+    writable: bool,
+    /// whether the file was written since its contents were last stored
+    dirty: bool,
+}
+
+impl FileHandle {
+    /// The id of the entry of the file.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Filesystem operations on a root, for front-ends which let tools work with a root through
+/// dfs instead of behind its back. Every change is made on disk and in the
+/// [`LocalStore`] together, so the index is up to date without [indexing](ConnectedRoot::index),
+/// and the change is a change by this peer like one found while indexing.
+///
+/// Entries are identified by their id, which doesn't change as long as the entry exists, so
+/// it can be used as an inode number. Entries of which the contents aren't on disk, like
+/// [placeholders](crate::root::dir_entry::Presence::Placeholder), are listed, but can't be opened.
+///
+/// Changes are made on disk first. When the store can't be updated afterwards, the change on
+/// disk is undone: created entries are removed, and removed or replaced files, which are set
+/// aside in the local db of the root until the change is stored, are put back. What can't be
+/// undone is picked up by the next index.
+pub trait Vfs {
+    type Error;
+
+    /// Find the entry with this name in the directory with id `parent`.
+    fn lookup(&self, parent: Uuid, name: &OsStr) -> Result<Option<StorableDirEntry>, Self::Error>;
+
+    /// The entries in the directory with id `dir`, ordered by their id.
+    fn readdir(&self, dir: Uuid) -> Result<Vec<StorableDirEntry>, Self::Error>;
+
+    /// The stored metadata of the entry with id `id`.
+    fn getattr(&self, id: Uuid) -> Result<StorableDirEntry, Self::Error>;
+
+    /// Open the file with id `id`, for reading and, when `write` is true, for writing.
+    fn open(&self, id: Uuid, write: bool) -> Result<FileHandle, Self::Error>;
+
+    /// Read from an open file at `offset` into `buf`. Returns how many bytes were read,
+    /// which is 0 at the end of the file.
+    fn read(&self, file: &mut FileHandle, offset: u64, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Write `data` to an open file at `offset`. The stored contents of the file are updated
+    /// when the file is [flushed](Vfs::flush) or [released](Vfs::release), so a file written in
+    /// many pieces is hashed and becomes a change once.
+    fn write(&self, file: &mut FileHandle, offset: u64, data: &[u8]) -> Result<usize, Self::Error>;
+
+    /// Store the contents of an open file, when it was written since they were last stored.
+    fn flush(&self, file: &mut FileHandle) -> Result<(), Self::Error>;
+
+    /// Close an open file, [flushing](Vfs::flush) it first. Writes to a file which is dropped
+    /// instead are only stored by the next index.
+    fn release(&self, file: FileHandle) -> Result<(), Self::Error>;
+
+    /// Create an empty file, or a directory, with this name in the directory with id `parent`.
+    fn create(&self, parent: Uuid, name: &OsStr, is_dir: bool) -> Result<StorableDirEntry, Self::Error>;
+
+    /// Remove the file or empty directory with this name from the directory with id `parent`.
+    /// A tombstone is left, so the deletion reaches the peers.
+    fn unlink(&self, parent: Uuid, name: &OsStr) -> Result<(), Self::Error>;
+
+    /// Move the entry with this name in `parent` to `new_name` in `new_parent`, replacing the
+    /// file which is there. Returns the moved entry.
+    fn rename(&self, parent: Uuid, name: &OsStr, new_parent: Uuid, new_name: &OsStr) -> Result<StorableDirEntry, Self::Error>;
+}
+
+impl<'dfs, GS: GlobalStore, LS: LocalStore> Vfs for ConnectedRoot<'dfs, GS, LS> {
+    type Error = VfsError<LS::Error>;
+
+    fn lookup(&self, parent: Uuid, name: &OsStr) -> Result<Option<StorableDirEntry>, Self::Error> {
+        let parent = self.vfs_dir(parent)?;
+        Ok(self.connection.get_children(parent.id())?
+            .into_iter()
+            .find(|c| c.name() == name && !c.is_deleted()))
+    }
+
+    fn readdir(&self, dir: Uuid) -> Result<Vec<StorableDirEntry>, Self::Error> {
+        let dir = self.vfs_dir(dir)?;
+        Ok(self.connection.get_children(dir.id())?
+            .into_iter()
+            .filter(|c| !c.is_deleted())
+            .collect())
+    }
+
+    fn getattr(&self, id: Uuid) -> Result<StorableDirEntry, Self::Error> {
+        match self.connection.get_direntry(id)? {
+            Some(entry) if !entry.is_deleted() => Ok(entry),
+            _ => Err(VfsError::NotFound(id)),
+        }
+    }
+
+    fn open(&self, id: Uuid, write: bool) -> Result<FileHandle, Self::Error> {
+        let entry = self.getattr(id)?;
+        if entry.is_dir() {
+            return Err(VfsError::IsADirectory(entry.path().to_path_buf()))
+        }
+        if !entry.is_local() {
+            return Err(VfsError::NotLocal(entry.path().to_path_buf()))
+        }
+
+        let on_disk = self.vfs_path_on_disk(entry.path())?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(write)
+            .open(&on_disk)
+            .map_err(|e| VfsError::Io(on_disk, e))?;
+
+        Ok(FileHandle {
+            id,
+            file,
+            writable: write,
+            dirty: false,
+        })
+    }
+
+    fn read(&self, file: &mut FileHandle, offset: u64, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let entry = self.getattr(file.id)?;
+        let on_disk = self.vfs_path_on_disk(entry.path())?;
+        let io_err = |e| VfsError::Io(on_disk.clone(), e);
+
+        file.file.seek(SeekFrom::Start(offset)).map_err(io_err)?;
+        file.file.read(buf).map_err(io_err)
+    }
+
+    fn write(&self, file: &mut FileHandle, offset: u64, data: &[u8]) -> Result<usize, Self::Error> {
+        if !file.writable {
+            return Err(VfsError::ReadOnly)
+        }
+
+        // the file may have been moved since it was opened
+        let entry = self.getattr(file.id)?;
+        let on_disk = self.vfs_path_on_disk(entry.path())?;
+        let io_err = |e| VfsError::Io(on_disk.clone(), e);
+
+        file.file.seek(SeekFrom::Start(offset)).map_err(io_err)?;
+        file.file.write_all(data).map_err(io_err)?;
+        file.dirty = true;
+
+        Ok(data.len())
+    }
+
+    fn flush(&self, file: &mut FileHandle) -> Result<(), Self::Error> {
+        if !file.dirty {
+            return Ok(())
+        }
+
+        let entry = self.getattr(file.id)?;
+        let on_disk = self.vfs_path_on_disk(entry.path())?;
+        let io_err = |e| VfsError::Io(on_disk.clone(), e);
+
+        file.file.flush().map_err(io_err)?;
+        let hashes = hash_file(&on_disk).map_err(io_err)?;
+        if &hashes.hash != entry.hash() {
+            self.put_local_file(entry.path(), hashes)?;
+        }
+        file.dirty = false;
+
+        Ok(())
+    }
+
+    fn release(&self, mut file: FileHandle) -> Result<(), Self::Error> {
+        self.flush(&mut file)
+    }
+
+    fn create(&self, parent: Uuid, name: &OsStr, is_dir: bool) -> Result<StorableDirEntry, Self::Error> {
+        let path = self.vfs_child_path(parent, name)?;
+        if self.find(&path, false)?.is_some() {
+            return Err(VfsError::Exists(path))
+        }
+
+        let on_disk = self.vfs_path_on_disk(&path)?;
+        if is_dir {
+            fs::create_dir(&on_disk).map_err(|e| VfsError::Io(on_disk.clone(), e))?;
+            let stored = self.ensure_dir(&path).map_err(VfsError::from);
+            vfs_rollback(stored, || fs::remove_dir(&on_disk))
+        } else {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&on_disk)
+                .map_err(|e| VfsError::Io(on_disk.clone(), e))?;
+            let stored = hash_file(&on_disk)
+                .map_err(|e| VfsError::Io(on_disk.clone(), e))
+                .and_then(|hashes| self.put_local_file(&path, hashes));
+            vfs_rollback(stored, || fs::remove_file(&on_disk))
+        }
+    }
+
+    fn unlink(&self, parent: Uuid, name: &OsStr) -> Result<(), Self::Error> {
+        let path = self.vfs_child_path(parent, name)?;
+        let entry = self.find(&path, false)?.ok_or_else(|| VfsError::NoSuchName(path.clone()))?;
+
+        let on_disk = self.vfs_path_on_disk(&path)?;
+        let res = if entry.is_dir() {
+            fs::remove_dir(&on_disk).map(|_| None)
+        } else {
+            self.vfs_set_aside(&on_disk).map(Some)
+        };
+        let (removed, aside) = match res {
+            Ok(aside) => (true, aside),
+            // excluded entries aren't on disk
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !entry.is_local() => (false, None),
+            Err(e) => return Err(VfsError::Io(on_disk, e)),
+        };
+
+        let stored = self.delete_path(&path, None).map_err(VfsError::from);
+        vfs_rollback(stored, || match &aside {
+            Some(aside) => fs::rename(aside, &on_disk),
+            None if removed => fs::create_dir(&on_disk),
+            None => Ok(()),
+        })?;
+        if let Some(aside) = aside {
+            vfs_remove_aside(&aside);
+        }
+        Ok(())
+    }
+
+    fn rename(&self, parent: Uuid, name: &OsStr, new_parent: Uuid, new_name: &OsStr) -> Result<StorableDirEntry, Self::Error> {
+        let from = self.vfs_child_path(parent, name)?;
+        let to = self.vfs_child_path(new_parent, new_name)?;
+        let entry = self.find(&from, false)?.ok_or_else(|| VfsError::NoSuchName(from.clone()))?;
+        if entry.is_excluded() {
+            return Err(VfsError::NotLocal(from))
+        }
+
+        let from_disk = self.vfs_path_on_disk(&from)?;
+        let to_disk = self.vfs_path_on_disk(&to)?;
+        if from == to {
+            fs::rename(&from_disk, &to_disk).map_err(|e| VfsError::Io(from_disk, e))?;
+            return Ok(entry)
+        }
+
+        // a file which is replaced is set aside until the move is stored, a replaced directory
+        // is empty
+        let (replaced_dir, aside) = match fs::symlink_metadata(&to_disk) {
+            Ok(meta) if meta.is_dir() => (true, None),
+            Ok(_) if !entry.is_dir() => {
+                let aside = self.vfs_set_aside(&to_disk).map_err(|e| VfsError::Io(to_disk.clone(), e))?;
+                (false, Some(aside))
+            }
+            _ => (false, None),
+        };
+        if let Err(e) = fs::rename(&from_disk, &to_disk) {
+            if let Some(aside) = &aside {
+                let _ = fs::rename(aside, &to_disk);
+            }
+            return Err(VfsError::Io(from_disk, e))
+        }
+
+        // whatever was at the target was replaced, which is a deletion
+        let stored = self.delete_path(&to, None)
+            .map_err(VfsError::from)
+            .and_then(|_| self.move_path(&from, &to, None)?.ok_or_else(|| VfsError::NoSuchName(from.clone())));
+        let moved = vfs_rollback(stored, || {
+            fs::rename(&to_disk, &from_disk)?;
+            match &aside {
+                Some(aside) => fs::rename(aside, &to_disk),
+                None if replaced_dir => fs::create_dir(&to_disk),
+                None => Ok(()),
+            }
+        })?;
+        if let Some(aside) = aside {
+            vfs_remove_aside(&aside);
+        }
+        Ok(moved)
+    }
+}
+
+impl<'dfs, GS: GlobalStore, LS: LocalStore> ConnectedRoot<'dfs, GS, LS> {
+    /// The directory with id `id`, for the [`Vfs`] operations.
+    fn vfs_dir(&self, id: Uuid) -> Result<StorableDirEntry, VfsError<LS::Error>> {
+        match self.connection.get_direntry(id)? {
+            Some(dir) if dir.is_dir() && !dir.is_deleted() => Ok(dir),
+            Some(entry) if !entry.is_deleted() => Err(VfsError::NotADirectory(entry.path().to_path_buf())),
+            _ => Err(VfsError::NotFound(id)),
+        }
+    }
+
+    /// The path of the entry named `name` in the directory with id `parent`.
+    fn vfs_child_path(&self, parent: Uuid, name: &OsStr) -> Result<PathBuf, VfsError<LS::Error>> {
+        let parent = self.vfs_dir(parent)?;
+        let path = parent.path().join(name);
+
+        // the local store of the root isn't part of it
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) if self.path_on_disk(&path).is_ok() => Ok(path),
+            _ => Err(VfsError::InvalidName(path)),
+        }
+    }
+
+    /// Where the entry at `path` is on disk. Paths of entries which can't be on disk aren't valid names.
+    fn vfs_path_on_disk(&self, path: &Path) -> Result<PathBuf, VfsError<LS::Error>> {
+        self.path_on_disk(path).map_err(|UnsafePath(path)| VfsError::InvalidName(path))
+    }
+
+    /// Move the file at `on_disk` into the local db, from where it can be put back.
+    fn vfs_set_aside(&self, on_disk: &Path) -> io::Result<PathBuf> {
+        let dir = self.local_db_path().join(REMOVED_DIR);
+        fs::create_dir_all(&dir)?;
+        let aside = dir.join(Uuid::new_v4().to_string());
+        fs::rename(on_disk, &aside)?;
+        Ok(aside)
+    }
+
+    /// Store new contents of the file at `path`, which were written here.
+    fn put_local_file(&self, path: &Path, hashes: FileHashes) -> Result<StorableDirEntry, VfsError<LS::Error>> {
+        let mut contents = StorableDirEntry::new(Uuid::new_v4(), path.to_path_buf(), None, false, Hash::default());
+        contents.set_contents(hashes);
+        let contents = self.local_change(path, contents)?;
+        self.put_file(path, &contents)?;
+
+        self.find(path, false)?.ok_or_else(|| VfsError::NoSuchName(path.to_path_buf()))
+    }
+}
+
+/// Undo a change on disk with `undo` when storing it failed, so the disk and the store still
+/// agree.
+fn vfs_rollback<T, E>(stored: Result<T, E>, undo: impl FnOnce() -> io::Result<()>) -> Result<T, E> {
+    if stored.is_err() {
+        if let Err(e) = undo() {
+            log::warn!("failed to undo a change on disk after storing it failed: {}", e);
+        }
+    }
+    stored
+}
+
+/// Remove a file which was set aside, now that its removal is stored.
+fn vfs_remove_aside(aside: &Path) {
+    if let Err(e) = fs::remove_file(aside) {
+        log::warn!("failed to remove {:?}: {}", aside, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::fs;
+
+    use temp_testdir::TempDir;
+
+    use crate::config::Config;
+    use crate::Dfs;
+    use crate::root::vfs::{REMOVED_DIR, Vfs};
+
+    #[tokio::test]
+    async fn changes_are_indexed() {
+        let dir = TempDir::new("vfs root", true);
+        let global = TempDir::new("vfs global", true);
+        let dfs = Dfs::new(Config::test_config(&global)).unwrap();
+        let root = dfs.new_root(&dir, "vfs").unwrap().connect().unwrap();
+        let top = root.root_dir().unwrap().id();
+
+        let docs = root.create(top, OsStr::new("docs"), true).unwrap();
+        let report = root.create(docs.id(), OsStr::new("report"), false).unwrap();
+        assert!(root.create(docs.id(), OsStr::new("report"), false).is_err());
+
+        let mut file = root.open(report.id(), true).unwrap();
+        root.write(&mut file, 0, b"draft").unwrap();
+        root.write(&mut file, 5, b" two").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("docs/report")).unwrap(), "draft two");
+        let mut buf = [0; 3];
+        assert_eq!(root.read(&mut file, 6, &mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"two");
+
+        // the writes are stored, and become one change, when the file is released
+        assert_eq!(root.getattr(report.id()).unwrap().size(), 0);
+        root.release(file).unwrap();
+        let written = root.getattr(report.id()).unwrap();
+        assert_eq!(written.size(), 9);
+        let file = root.open(report.id(), true).unwrap();
+        root.release(file).unwrap();
+        assert_eq!(root.getattr(report.id()).unwrap().version(), written.version());
+
+        let moved = root.rename(top, OsStr::new("docs"), top, OsStr::new("papers")).unwrap();
+        assert_eq!(moved.id(), docs.id());
+        assert!(root.lookup(top, OsStr::new("docs")).unwrap().is_none());
+        let report = root.lookup(moved.id(), OsStr::new("report")).unwrap().unwrap();
+        assert_eq!(report.size(), 9);
+        assert_eq!(fs::read_to_string(dir.join("papers/report")).unwrap(), "draft two");
+
+        let notes = root.create(moved.id(), OsStr::new("notes"), false).unwrap();
+        root.unlink(moved.id(), OsStr::new("notes")).unwrap();
+        assert!(!dir.join("papers/notes").exists());
+        assert!(root.getattr(notes.id()).is_err());
+        assert_eq!(root.readdir(moved.id()).unwrap().len(), 1);
+
+        // a replaced file is a deletion, and isn't kept once that's stored
+        let draft = root.create(moved.id(), OsStr::new("draft"), false).unwrap();
+        let replaced = root.rename(moved.id(), OsStr::new("draft"), moved.id(), OsStr::new("report")).unwrap();
+        assert_eq!(replaced.id(), draft.id());
+        assert!(root.getattr(report.id()).is_err());
+        assert_eq!(fs::read_to_string(dir.join("papers/report")).unwrap(), "");
+        assert!(fs::read_dir(root.local_db_path().join(REMOVED_DIR)).unwrap().next().is_none());
+        let report = replaced;
+
+        // the store already has everything indexing finds
+        let hash = root.root_hash().unwrap();
+        let version = report.version().clone();
+        root.index().await.unwrap();
+        assert_eq!(root.root_hash().unwrap(), hash);
+        assert_eq!(root.getattr(report.id()).unwrap().version(), &version);
+    }
+}
@@ -0,0 +1,590 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use libp2p::PeerId;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::GetRootError;
+use crate::global_store::GlobalStore;
+use crate::network::Node;
+use crate::network::announce::AnnounceError;
+use crate::network::blocks::DownloadError;
+use crate::network::manifest::ManifestError;
+use crate::root::{ConnectedRoot, GcError, GetDirEntryError, UnsafePath};
+use crate::root::conflict::{copy_path, numbered_copy_path, Conflict};
+use crate::root::dir_entry::{Presence, StorableDirEntry};
+use crate::root::history::HistoryError;
+use crate::root::index::IndexError;
+use crate::root::local_store::LocalStore;
+use crate::root::merkle::Difference;
+use crate::root::selection::Selection;
+
+#[derive(Debug, Error)]
+pub enum SyncError<GSE, LSE> {
+    #[error("db error: {0}")]
+    DbInteractionError(#[from] LSE),
+
+    #[error("global db error: {0}")]
+    GlobalDb(GSE),
+
+    #[error("failed to index the root: {0}")]
+    Index(IndexError<LSE>),
+
+    #[error("failed to compare with the peer: {0}")]
+    Manifest(ManifestError<GSE>),
+
+    #[error("failed to download {0:?}: {1}")]
+    Download(PathBuf, DownloadError<GSE>),
+
+    #[error("io error at {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+
+    #[error("failed to announce the changes: {0}")]
+    Announce(AnnounceError),
+
+    #[error("failed to keep the previous version of a file: {0}")]
+    History(HistoryError<GSE, LSE>),
+
+    #[error("{0}")]
+    UnsafePath(UnsafePath),
+}
+
+/// A step needed to bring a root in sync with a peer. Paths are relative to the root.
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// The peer has an entry we don't have, or a newer version of a file we have. For directories,
+    /// everything under it is downloaded.
+    Download(StorableDirEntry),
+
+    /// We have an entry the peer doesn't have, or a newer version of it. The peer is told to sync
+    /// with us, and downloads it then.
+    Upload(PathBuf),
+
+    /// The peer deleted an entry after all changes we made to it. This is the tombstone of the peer.
+    /// Directories are only deleted when nothing newer than the deletion is left in them.
+    Delete(StorableDirEntry),
+
+    /// We deleted an entry after all changes the peer made to it. The peer is told to sync with us,
+    /// and deletes it then.
+    DeleteRemote(PathBuf),
+
+    /// The peer renamed a file which we didn't change since, or a directory of which we have the
+    /// same contents. `from` is the tombstone of the peer at the old path.
+    Rename {
+        from: StorableDirEntry,
+        to: StorableDirEntry,
+    },
+
+    /// Both sides changed the file since the last sync. One version is kept as a conflict copy,
+    /// see [`Conflict`].
+    ConflictCopy {
+        local: StorableDirEntry,
+        remote: StorableDirEntry,
+    },
+
+    /// The entry is a file on one side and a directory on the other.
+    Conflict(PathBuf),
+}
+
+/// What happened during a [sync](sync).
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub downloaded: Vec<PathBuf>,
+
+    /// changes the peer will download from us, once it syncs after being told to
+    pub uploads: Vec<PathBuf>,
+
+    pub deleted: Vec<PathBuf>,
+
+    /// entries the peer will delete, once it syncs after being told to
+    pub remote_deletions: Vec<PathBuf>,
+
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+
+    /// entries which aren't [selected](Selection): only their metadata is kept, and they were
+    /// removed from disk if they were there
+    pub excluded: Vec<PathBuf>,
+
+    /// files which are [placeholders](Presence::Placeholder) here, or of which the peer doesn't have
+    /// the contents either: only their metadata was updated
+    pub placeholders: Vec<PathBuf>,
+
+    /// entries which both sides changed. Files changed on both sides get a conflict copy
+    /// (see [`ConnectedRoot::conflicts`]), other conflicts are left alone.
+    pub conflicts: Vec<PathBuf>,
+}
+
+impl SyncReport {
+    /// Whether nothing was changed locally.
+    pub fn is_empty(&self) -> bool {
+        self.downloaded.is_empty() && self.deleted.is_empty() && self.renamed.is_empty() && self.excluded.is_empty()
+            && self.placeholders.is_empty()
+    }
+}
+
+/// Decide what to do about the differences between a root and the same root at a peer. When
+/// an entry exists on both sides, their [versions](crate::root::version::VersionVector) tell
+/// whether one side is newer or both changed it. Deletions are compared the same way, through
+/// the tombstones they leave: a deletion only wins from the changes it knows about. An entry
+/// which exists on one side only is new.
+///
+/// A file or directory which the peer deleted and which appears at the peer with the same contents
+/// under another path is a rename.
+pub fn plan(differences: Vec<Difference>) -> Vec<Action> {
+    let mut actions = Vec::new();
+    // (tombstone of the peer, hash of the local file) of local files the peer deleted
+    let mut deleted = Vec::new();
+    // (tombstone of the peer, hash of the local directory) of local directories the peer deleted,
+    // handled after everything in them
+    let mut deleted_dirs = Vec::new();
+
+    for difference in differences {
+        let action = match (difference.local, difference.remote) {
+            (Some(local), Some(remote)) if local.is_dir() && remote.is_dir() => {
+                // the differences are in the children, which are compared separately
+                if remote.is_deleted() && !local.is_deleted() {
+                    deleted_dirs.push((remote, *local.hash()));
+                }
+                continue
+            }
+            (Some(local), Some(remote)) if local.is_deleted() || remote.is_deleted() => {
+                let files = !local.is_dir() && !remote.is_dir();
+                match (local.is_deleted(), remote.is_deleted()) {
+                    (true, true) => continue,
+                    (true, false) if files && local.version() > remote.version() => Action::DeleteRemote(difference.path),
+                    (true, false) => Action::Download(remote),
+                    (false, true) if files && remote.version() > local.version() => {
+                        deleted.push((remote, *local.hash()));
+                        continue
+                    }
+                    (false, true) => Action::Upload(difference.path),
+                    (false, false) => unreachable!(),
+                }
+            }
+            (Some(local), Some(remote)) => {
+                if local.is_dir() != remote.is_dir() {
+                    Action::Conflict(difference.path)
+                } else if local.version() < remote.version() {
+                    Action::Download(remote)
+                } else if local.version() > remote.version() {
+                    Action::Upload(difference.path)
+                } else {
+                    // concurrent changes, or (equal versions) changes which weren't indexed yet
+                    Action::ConflictCopy { local, remote }
+                }
+            }
+            // the tombstone of an entry we never had (or already forgot about)
+            (None, Some(remote)) if remote.is_deleted() => continue,
+            (None, Some(remote)) => Action::Download(remote),
+            (Some(local), None) if local.is_deleted() => continue,
+            (Some(_), None) => Action::Upload(difference.path),
+            (None, None) => continue,
+        };
+
+        actions.push(action);
+    }
+
+    let mut renamed_dirs = Vec::new();
+    for action in actions.iter_mut() {
+        if let Action::Download(to) = action {
+            let candidates = if to.is_dir() { &mut deleted_dirs } else { &mut deleted };
+            if let Some(index) = candidates.iter().position(|(_, hash)| hash == to.hash()) {
+                let (from, _) = candidates.remove(index);
+                if from.is_dir() {
+                    renamed_dirs.push(from.path().to_path_buf());
+                }
+                *action = Action::Rename { from, to: to.clone() };
+            }
+        }
+    }
+    // the peer has only tombstones left under the old path of a renamed directory, but what we
+    // have there moves along
+    let renamed = |path: &Path| renamed_dirs.iter().any(|d| path.starts_with(d));
+    actions.retain(|a| !matches!(a, Action::Upload(path) if renamed(path)));
+    actions.extend(deleted.into_iter()
+        .filter(|(tombstone, _)| !renamed(tombstone.path()))
+        .map(|(tombstone, _)| Action::Delete(tombstone)));
+
+    // the deepest directories first, so their parents are empty by the time they are deleted
+    deleted_dirs.sort_by_key(|(d, _)| std::cmp::Reverse(d.path().components().count()));
+    actions.extend(deleted_dirs.into_iter().map(|(tombstone, _)| Action::Delete(tombstone)));
+
+    actions
+}
+
+/// Sync a root with a peer. The root is indexed first, then compared with the copy of the
+/// peer and all changes the peer made are applied here, on disk and in the [`LocalStore`].
+/// Changes made here are pushed by [announcing](Node::reannounce) the root, so the node of the
+/// peer emits a [`NodeEvent::RootChanged`](crate::network::NodeEvent::RootChanged), on which
+/// the peer syncs with us and applies them. After one sync on each side, both are in sync.
+///
+/// The root has to be [served](Node::serve) by the node, and shared with the peer in a mode
+/// which allows receiving from it. When both sides changed a file, one version is kept as a
+/// [conflict copy](Conflict).
+///
+/// Only the [selected](crate::Dfs::set_selection) parts of the root are downloaded, of the
+/// rest only the metadata is kept.
+///
+/// Afterwards, the new state of the root is [announced](Node::announce) to the other peers as well.
+pub async fn sync<GS: GlobalStore, LS: LocalStore>(node: &mut Node<'_, GS>, root: &ConnectedRoot<'_, GS, LS>, peer: PeerId) -> Result<SyncReport, SyncError<GS::Error, LS::Error>> {
+    root.index().await.map_err(SyncError::Index)?;
+    let selection = root.dfs().selection(root.id())
+        .map_err(|GetRootError::CompileStatement(e)| SyncError::GlobalDb(e))?;
+
+    let differences = node.diff(peer, root.id()).await.map_err(SyncError::Manifest)?;
+    let actions = plan(differences);
+
+    let mut report = SyncReport::default();
+    for action in actions {
+        match action {
+            Action::Download(entry) if entry.is_dir() => fetch_dir(node, root, peer, &selection, &entry, &mut report).await?,
+            Action::Download(entry) => fetch(node, root, peer, &selection, &entry, &mut report).await?,
+            Action::Upload(path) => report.uploads.push(path),
+            Action::Delete(tombstone) => {
+                if delete(root, &tombstone)? {
+                    report.deleted.push(tombstone.path().to_path_buf());
+                }
+            }
+            Action::DeleteRemote(path) => report.remote_deletions.push(path),
+            Action::Rename { from, to } if !selection.contains(to.path(), to.is_dir()) => {
+                remove_from_disk(&root.path_on_disk(from.path()).map_err(SyncError::UnsafePath)?)?;
+                if let Some(moved) = root.move_path(from.path(), to.path(), Some((&from, &to)))? {
+                    root.exclude(moved)?;
+                }
+                report.renamed.push((from.path().to_path_buf(), to.path().to_path_buf()));
+            }
+            Action::Rename { from, to } => {
+                let from_disk = root.path_on_disk(from.path()).map_err(SyncError::UnsafePath)?;
+                let to_disk = root.path_on_disk(to.path()).map_err(SyncError::UnsafePath)?;
+                if let Some(parent) = to_disk.parent() {
+                    fs::create_dir_all(parent).map_err(|e| SyncError::Io(parent.to_path_buf(), e))?;
+                }
+
+                let placeholder = local_placeholder(root, from.path())?;
+                match fs::rename(&from_disk, &to_disk) {
+                    Ok(()) => {
+                        root.move_path(from.path(), to.path(), Some((&from, &to)))?;
+                        // the contents of a renamed directory are the same on both sides
+                        if to.is_file() && placeholder {
+                            root.put_placeholder(to.path(), &to)?;
+                        } else if to.is_file() {
+                            root.put_file(to.path(), &to)?;
+                        }
+                    }
+                    // it disappeared since it was indexed, so get it from the peer after all
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        root.delete_path(from.path(), Some(&from))?;
+                        if to.is_dir() {
+                            fetch_dir(node, root, peer, &selection, &to, &mut report).await?;
+                        } else {
+                            fetch(node, root, peer, &selection, &to, &mut report).await?;
+                        }
+                    }
+                    Err(e) => return Err(SyncError::Io(from_disk, e)),
+                }
+                report.renamed.push((from.path().to_path_buf(), to.path().to_path_buf()));
+            }
+            Action::ConflictCopy { local, remote } => {
+                conflict_copy(node, root, peer, &local, &remote).await?;
+                report.conflicts.push(local.path().to_path_buf());
+            }
+            Action::Conflict(path) => report.conflicts.push(path),
+        }
+    }
+
+    update_base(node, root, peer).await?;
+    apply_selection(node, root, peer, &selection, &mut report).await?;
+    root.gc_tombstones().map_err(|e| match e {
+        GcError::DbInteractionError(e) => SyncError::DbInteractionError(e),
+        GcError::GlobalDb(e) => SyncError::GlobalDb(e),
+    })?;
+    if report.uploads.is_empty() && report.remote_deletions.is_empty() {
+        node.announce(root.id()).map_err(SyncError::Announce)?;
+    } else {
+        node.reannounce(root.id()).map_err(SyncError::Announce)?;
+    }
+
+    Ok(report)
+}
+
+/// [Sync](sync) every root with every connected peer it is shared with in a mode which allows
+/// receiving from that peer. Returns the result for every root and peer.
+pub async fn sync_all<GS: GlobalStore, LS: LocalStore>(node: &mut Node<'_, GS>, roots: &[&ConnectedRoot<'_, GS, LS>]) -> Vec<(Uuid, PeerId, Result<SyncReport, SyncError<GS::Error, LS::Error>>)> {
+    let mut res = Vec::new();
+    for root in roots {
+        let peers = match root.dfs().connection.get_root_members(root.id()) {
+            Ok(peers) => peers,
+            Err(e) => {
+                res.push((root.id(), node.peer_id(), Err(SyncError::GlobalDb(e))));
+                continue
+            }
+        };
+
+        for (peer, mode) in peers {
+            if !mode.receives() {
+                continue
+            }
+
+            let peer = match root.dfs().connection.get_peer(peer) {
+                Ok(Some(peer)) => peer.peer_id(),
+                Ok(None) => continue,
+                Err(e) => {
+                    res.push((root.id(), node.peer_id(), Err(SyncError::GlobalDb(e))));
+                    continue
+                }
+            };
+
+            if node.is_connected(&peer) {
+                let result = sync(node, root, peer).await;
+                res.push((root.id(), peer, result));
+            }
+        }
+    }
+
+    res
+}
+
+/// Take over an entry of the peer. Of entries which aren't selected only the metadata is stored.
+/// Files which are placeholders here stay placeholders, and files of which the peer has no
+/// contents become placeholders.
+async fn fetch<GS: GlobalStore, LS: LocalStore>(node: &mut Node<'_, GS>, root: &ConnectedRoot<'_, GS, LS>, peer: PeerId, selection: &Selection, entry: &StorableDirEntry, report: &mut SyncReport) -> Result<(), SyncError<GS::Error, LS::Error>> {
+    let path = entry.path().to_path_buf();
+    if !selection.contains(entry.path(), entry.is_dir()) {
+        root.put_excluded(entry)?;
+        report.excluded.push(path);
+    } else if entry.is_dir() {
+        let on_disk = root.path_on_disk(entry.path()).map_err(SyncError::UnsafePath)?;
+        fs::create_dir_all(&on_disk).map_err(|e| SyncError::Io(on_disk, e))?;
+        let mut dir = root.ensure_dir(entry.path())?;
+        if !dir.is_local() {
+            dir.set_presence(Presence::Local);
+            root.connection.put_direntry(dir.id(), &dir, true)?;
+        }
+        report.downloaded.push(path);
+    } else if !entry.is_local() || local_placeholder(root, entry.path())? {
+        placeholder(root, entry)?;
+        report.placeholders.push(path);
+    } else {
+        download(node, root, peer, entry).await?;
+        report.downloaded.push(path);
+    }
+
+    Ok(())
+}
+
+/// Download a directory and everything in it.
+async fn fetch_dir<GS: GlobalStore, LS: LocalStore>(node: &mut Node<'_, GS>, root: &ConnectedRoot<'_, GS, LS>, peer: PeerId, selection: &Selection, dir: &StorableDirEntry, report: &mut SyncReport) -> Result<(), SyncError<GS::Error, LS::Error>> {
+    let entries = node.fetch_manifest(peer, root.id(), Some(dir.id()), None).await
+        .map_err(SyncError::Manifest)?;
+
+    for entry in entries.iter().filter(|e| !e.is_deleted()) {
+        fetch(node, root, peer, selection, entry, report).await?;
+    }
+
+    Ok(())
+}
+
+fn local_placeholder<GS: GlobalStore, LS: LocalStore>(root: &ConnectedRoot<'_, GS, LS>, path: &Path) -> Result<bool, LS::Error> {
+    let local = root.get_by_path(path).map_err(|GetDirEntryError::DbInteractionError(e)| e)?;
+    Ok(local.is_some_and(|l| l.is_placeholder()))
+}
+
+/// Put an empty file in place of a file, and store it as a placeholder with the metadata of `entry`.
+fn placeholder<GS: GlobalStore, LS: LocalStore>(root: &ConnectedRoot<'_, GS, LS>, entry: &StorableDirEntry) -> Result<(), SyncError<GS::Error, LS::Error>> {
+    let on_disk = root.path_on_disk(entry.path()).map_err(SyncError::UnsafePath)?;
+    if on_disk.is_dir() {
+        remove_from_disk(&on_disk)?;
+    }
+    fs::write(&on_disk, b"").map_err(|e| SyncError::Io(on_disk, e))?;
+
+    root.put_placeholder(entry.path(), entry)?;
+    Ok(())
+}
+
+/// Bring what is on disk in line with the selection, after it changed. Entries which are no longer
+/// selected are removed from disk, but only when the peer has the same version, so no changes are
+/// lost. Selected entries of which only the metadata is known are downloaded, when the peer has them.
+async fn apply_selection<GS: GlobalStore, LS: LocalStore>(node: &mut Node<'_, GS>, root: &ConnectedRoot<'_, GS, LS>, peer: PeerId, selection: &Selection, report: &mut SyncReport) -> Result<(), SyncError<GS::Error, LS::Error>> {
+    let mut entries = root.connection.get_all_direntries()?;
+    entries.retain(|e| !e.is_deleted() && !e.is_root());
+    // parents before their children
+    entries.sort_by_key(|e| e.path().components().count());
+
+    let mut hydrate = Vec::new();
+    let mut evicted: Vec<PathBuf> = Vec::new();
+    for entry in entries {
+        let selected = selection.contains(entry.path(), entry.is_dir());
+        if entry.is_excluded() && selected {
+            hydrate.push(entry);
+        } else if !entry.is_excluded() && !selected && !evicted.iter().any(|p| entry.path().starts_with(p)) {
+            if root.connection.get_base(&peer, entry.path())? != Some(*entry.hash()) {
+                continue
+            }
+
+            remove_from_disk(&root.path_on_disk(entry.path()).map_err(SyncError::UnsafePath)?)?;
+            evicted.push(entry.path().to_path_buf());
+            report.excluded.push(entry.path().to_path_buf());
+            root.exclude(entry)?;
+        }
+    }
+
+    if hydrate.is_empty() {
+        return Ok(())
+    }
+
+    let remote: HashMap<_, _> = node.fetch_manifest(peer, root.id(), None, None).await
+        .map_err(SyncError::Manifest)?
+        .into_iter()
+        .map(|e| (e.path().to_path_buf(), e))
+        .collect();
+
+    for mut entry in hydrate {
+        if entry.is_dir() {
+            let path = root.path_on_disk(entry.path()).map_err(SyncError::UnsafePath)?;
+            fs::create_dir_all(&path).map_err(|e| SyncError::Io(path, e))?;
+            entry.set_presence(Presence::Local);
+            root.connection.put_direntry(entry.id(), &entry, true)?;
+            continue
+        }
+
+        match remote.get(entry.path()) {
+            Some(remote) if remote.hash() == entry.hash() && remote.is_local() => {
+                download(node, root, peer, remote).await?;
+                report.downloaded.push(entry.path().to_path_buf());
+            }
+            // another peer may have the contents, they can be hydrated from there later
+            _ => {
+                placeholder(root, &entry)?;
+                report.placeholders.push(entry.path().to_path_buf());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn download<GS: GlobalStore, LS: LocalStore>(node: &mut Node<'_, GS>, root: &ConnectedRoot<'_, GS, LS>, peer: PeerId, entry: &StorableDirEntry) -> Result<(), SyncError<GS::Error, LS::Error>> {
+    let on_disk = root.path_on_disk(entry.path()).map_err(SyncError::UnsafePath)?;
+    if on_disk.is_dir() {
+        remove_from_disk(&on_disk)?;
+    }
+
+    // the contents are replaced, keep them around
+    let local = root.get_by_path(entry.path()).map_err(|GetDirEntryError::DbInteractionError(e)| e)?;
+    if let Some(local) = local.filter(|l| l.hash() != entry.hash()) {
+        root.save_version(&local).map_err(SyncError::History)?;
+    }
+
+    node.download(root.id(), entry, &[peer]).await
+        .map_err(|e| SyncError::Download(entry.path().to_path_buf(), e))?;
+    root.put_file(entry.path(), entry)?;
+
+    Ok(())
+}
+
+/// Keep both versions of a file which was changed on both sides. The version with the greatest
+/// hash wins, so every peer picks the same one. The winner ends up at the path of the file, with
+/// a version newer than both, so the peer takes it over. The loser is kept in a conflict copy,
+/// which the peer downloads as a new file.
+async fn conflict_copy<GS: GlobalStore, LS: LocalStore>(node: &mut Node<'_, GS>, root: &ConnectedRoot<'_, GS, LS>, peer: PeerId, local: &StorableDirEntry, remote: &StorableDirEntry) -> Result<(), SyncError<GS::Error, LS::Error>> {
+    let remote_wins = remote.hash() > local.hash();
+    let (loser, losing) = if remote_wins { (node.peer_id(), local) } else { (peer, remote) };
+    let copy = free_copy_path(root, copy_path(local.path(), losing.hash(), &loser))?;
+    let conflict = Conflict::new(local.path().to_path_buf(), copy, &loser, SystemTime::now());
+
+    if remote_wins {
+        let from = root.path_on_disk(local.path()).map_err(SyncError::UnsafePath)?;
+        let to = root.path_on_disk(conflict.copy()).map_err(SyncError::UnsafePath)?;
+        fs::rename(&from, &to).map_err(|e| SyncError::Io(from, e))?;
+        root.put_file(conflict.copy(), local)?;
+
+        download(node, root, peer, remote).await?;
+    } else {
+        let mut copy = remote.clone();
+        copy.set_path(conflict.copy().to_path_buf());
+        download(node, root, peer, &copy).await?;
+
+        let mut winner = local.clone();
+        winner.version_mut().merge(remote.version());
+        root.put_file(local.path(), &winner)?;
+    }
+
+    root.connection.put_conflict(&conflict)?;
+    Ok(())
+}
+
+/// `copy`, or when a file is already there, the first numbered alternative which is free.
+fn free_copy_path<GS: GlobalStore, LS: LocalStore>(root: &ConnectedRoot<'_, GS, LS>, copy: PathBuf) -> Result<PathBuf, SyncError<GS::Error, LS::Error>> {
+    let mut candidate = copy.clone();
+    for n in 2.. {
+        let indexed = root.get_by_path(&candidate).map_err(|GetDirEntryError::DbInteractionError(e)| e)?.is_some();
+        if !indexed && !root.path_on_disk(&candidate).map_err(SyncError::UnsafePath)?.exists() {
+            break
+        }
+        candidate = numbered_copy_path(&copy, n);
+    }
+
+    Ok(candidate)
+}
+
+/// Apply the deletion of an entry by the peer. Returns whether anything was deleted: directories
+/// are kept when something in them is newer than the deletion.
+fn delete<GS: GlobalStore, LS: LocalStore>(root: &ConnectedRoot<'_, GS, LS>, tombstone: &StorableDirEntry) -> Result<bool, SyncError<GS::Error, LS::Error>> {
+    let local = match root.get_by_path(tombstone.path()).map_err(|GetDirEntryError::DbInteractionError(e)| e)? {
+        Some(local) => local,
+        None => return Ok(false),
+    };
+
+    if local.is_dir() && root.connection.get_children(local.id())?.iter().any(|c| !c.is_deleted()) {
+        return Ok(false)
+    }
+
+    remove_from_disk(&root.path_on_disk(tombstone.path()).map_err(SyncError::UnsafePath)?)?;
+    Ok(root.delete_path(tombstone.path(), Some(tombstone))?)
+}
+
+fn remove_from_disk<GSE, LSE>(path: &Path) -> Result<(), SyncError<GSE, LSE>> {
+    let res = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    match res {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(SyncError::Io(path.to_path_buf(), e)),
+        _ => Ok(()),
+    }
+}
+
+/// Record the state both sides agree on as the base with the peer. Tombstones in the base are
+/// known to the peer, so they can be [removed](ConnectedRoot::gc_tombstones) once every peer
+/// knows them. Entries which still differ, and everything above or below them, are left out,
+/// except for tombstones of entries the peer doesn't have at all: it doesn't need those.
+async fn update_base<GS: GlobalStore, LS: LocalStore>(node: &mut Node<'_, GS>, root: &ConnectedRoot<'_, GS, LS>, peer: PeerId) -> Result<(), SyncError<GS::Error, LS::Error>> {
+    let differences = node.diff(peer, root.id()).await.map_err(SyncError::Manifest)?;
+    let entries = root.manifest(None, None)
+        .map_err(|e| match e {
+            GetDirEntryError::DbInteractionError(e) => SyncError::DbInteractionError(e),
+        })?
+        .unwrap_or_default();
+
+    let settled = |d: &Difference, e: &StorableDirEntry| e.is_deleted()
+        && e.path().starts_with(&d.path)
+        && d.remote.is_none()
+        && d.local.as_ref().is_some_and(StorableDirEntry::is_deleted);
+    // the children of directories which exist on both sides are compared on their own
+    let both_dirs = |d: &Difference| d.local.as_ref().is_some_and(StorableDirEntry::is_dir) && d.remote.as_ref().is_some_and(StorableDirEntry::is_dir);
+    let overlaps = |d: &Difference, e: &StorableDirEntry| d.path.starts_with(e.path()) || (e.path().starts_with(&d.path) && !both_dirs(d));
+
+    let base: Vec<_> = entries.into_iter()
+        .filter(|e| differences.iter().all(|d| settled(d, e) || !overlaps(d, e)))
+        .map(|e| (e.path().to_path_buf(), *e.hash()))
+        .collect();
+
+    root.connection.set_base(&peer, &base)?;
+    Ok(())
+}
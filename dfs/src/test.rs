@@ -1,7 +1,12 @@
 use temp_testdir::TempDir;
+use std::future::Future;
 use std::path::Path;
 
 use std::{io, fs};
+use libp2p::Multiaddr;
+
+use crate::global_store::GlobalStore;
+use crate::network::{Node, NodeEvent};
 
 fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
     fs::create_dir_all(&dst)?;
@@ -24,3 +29,39 @@ pub fn populated_tempdir(name: impl AsRef<Path>) -> TempDir {
 
     t
 }
+
+/// Wait until `node` listens, and return the address.
+pub async fn wait_listening<GS: GlobalStore>(node: &mut Node<'_, GS>) -> Multiaddr {
+    loop {
+        if let NodeEvent::Listening(address) = node.next_event().await {
+            return address
+        }
+    }
+}
+
+/// Let `b` dial `a` and wait until both sides have established the connection. Until `a` has,
+/// requests it sends to `b` fail.
+pub async fn connect<GS: GlobalStore>(a: &mut Node<'_, GS>, b: &mut Node<'_, GS>) {
+    let address = wait_listening(a).await;
+    b.dial_addr(address).unwrap();
+
+    let (peer_a, peer_b) = (a.peer_id(), b.peer_id());
+    let (mut a_connected, mut b_connected) = (false, false);
+    while !(a_connected && b_connected) {
+        tokio::select! {
+            e = a.next_event() => if let NodeEvent::Connected(p) = e { a_connected |= p == peer_b },
+            e = b.next_event() => if let NodeEvent::Connected(p) = e { b_connected |= p == peer_a },
+        }
+    }
+}
+
+/// Drive the future (usually using some other node) while `node` keeps processing events.
+pub async fn drive<F: Future, GS: GlobalStore>(future: F, node: &mut Node<'_, GS>) -> F::Output {
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            r = &mut future => break r,
+            _ = node.next_event() => {},
+        }
+    }
+}
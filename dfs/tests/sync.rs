@@ -0,0 +1,606 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::Path;
+use std::time::Duration;
+
+use dfs::Dfs;
+use dfs::config::Config;
+use dfs::diff::Change;
+use dfs::network::{Node, NodeEvent};
+use dfs::network::sealed::{SealedRoot, SEALED_BLOCKS_DIR};
+use dfs::root::ConnectedRoot;
+use dfs::root::conflict::Resolution;
+use dfs::root::selection::Selection;
+use dfs::root::share::ShareMode;
+use dfs::sync::sync;
+use dfs::test::{connect, drive};
+use temp_testdir::TempDir;
+
+fn read(root: &Path, path: &str) -> Option<String> {
+    fs::read_to_string(root.join(path)).ok()
+}
+
+#[tokio::test]
+async fn two_roots_sync() {
+    let dir_a = TempDir::new("sync root a", true);
+    let dir_b = TempDir::new("sync root b", true);
+    let global_a = TempDir::new("sync global a", true);
+    let global_b = TempDir::new("sync global b", true);
+    let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+    let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+    fs::write(dir_a.join("one"), "one").unwrap();
+    fs::create_dir(dir_a.join("dir")).unwrap();
+    fs::write(dir_a.join("dir/two"), "two").unwrap();
+
+    let root = dfs_a.new_root(&dir_a, "a").unwrap();
+    let invite = dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap();
+    let invite = dfs_b.accept_invite(&invite).unwrap();
+    dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+
+    let root_a = root.connect().unwrap();
+    root_a.index().await.unwrap();
+    let root_b = dfs_b.join_root(&invite, &dir_b, "b").unwrap().connect().unwrap();
+
+    let mut node_a = Node::new(&dfs_a).await.unwrap();
+    let mut node_b = Node::new(&dfs_b).await.unwrap();
+    node_a.serve(&root_a);
+    node_b.serve(&root_b);
+    connect(&mut node_b, &mut node_a).await;
+
+    // b starts out empty and gets everything from a
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.downloaded.len(), 3);
+    assert_eq!(read(&dir_b, "one").as_deref(), Some("one"));
+    assert_eq!(read(&dir_b, "dir/two").as_deref(), Some("two"));
+
+    let report = drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert!(report.is_empty());
+    assert!(report.uploads.is_empty());
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+
+    // b changes, adds and deletes a file. Those are uploads for b, and applied when a syncs
+    fs::write(dir_b.join("one"), "one, changed").unwrap();
+    fs::write(dir_b.join("three"), "three").unwrap();
+    fs::remove_file(dir_b.join("dir/two")).unwrap();
+
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert!(report.is_empty());
+    assert_eq!(report.uploads.len(), 2);
+    assert_eq!(report.remote_deletions, vec![Path::new("/dir/two")]);
+
+    let report = drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert_eq!(report.downloaded.len(), 2);
+    assert_eq!(report.deleted, vec![Path::new("/dir/two")]);
+    assert_eq!(read(&dir_a, "one").as_deref(), Some("one, changed"));
+    assert_eq!(read(&dir_a, "three").as_deref(), Some("three"));
+    assert!(!dir_a.join("dir/two").exists());
+
+    // a knows b has the tombstone, so it is removed. b follows once it knows a has it too
+    let has_tombstone = |root: &ConnectedRoot<_, _>| root.manifest(None, None).unwrap().unwrap().iter().any(|e| e.is_deleted());
+    assert!(!has_tombstone(&root_a));
+    assert!(has_tombstone(&root_b));
+    drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert!(!has_tombstone(&root_b));
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+
+    // a renames a file, which b applies without downloading it again. Both keep the id of the file
+    let id = |root: &ConnectedRoot<_, _>, path: &str| root.get_by_path(path).unwrap().unwrap().id();
+    let (three_a, three_b) = (id(&root_a, "/three"), id(&root_b, "/three"));
+    fs::rename(dir_a.join("three"), dir_a.join("dir/three")).unwrap();
+    drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert_eq!(id(&root_a, "/dir/three"), three_a);
+
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.renamed, vec![(Path::new("/three").to_path_buf(), Path::new("/dir/three").to_path_buf())]);
+    assert!(report.downloaded.is_empty());
+    assert_eq!(read(&dir_b, "dir/three").as_deref(), Some("three"));
+    assert!(!dir_b.join("three").exists());
+    assert_eq!(id(&root_b, "/dir/three"), three_b);
+    drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+
+    // the same goes for directories
+    fs::rename(dir_a.join("dir"), dir_a.join("moved")).unwrap();
+    drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert_eq!(id(&root_a, "/moved/three"), three_a);
+
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.renamed, vec![(Path::new("/dir").to_path_buf(), Path::new("/moved").to_path_buf())]);
+    assert!(report.downloaded.is_empty());
+    assert!(report.uploads.is_empty());
+    assert_eq!(read(&dir_b, "moved/three").as_deref(), Some("three"));
+    assert!(!dir_b.join("dir").exists());
+    assert_eq!(id(&root_b, "/moved/three"), three_b);
+    drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+
+    // the versions tell a newer file from concurrent changes
+    let version = |root: &ConnectedRoot<_, _>| root.get_by_path("/one").unwrap().unwrap().version().clone();
+    assert_eq!(version(&root_a), version(&root_b));
+    fs::write(dir_a.join("one"), "changed by a").unwrap();
+    fs::write(dir_b.join("one"), "changed by b").unwrap();
+    root_a.index().await.unwrap();
+    root_b.index().await.unwrap();
+    assert!(version(&root_a).concurrent(&version(&root_b)));
+
+    // both versions are kept, and both peers end up with the same winner and copy
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.conflicts, vec![Path::new("/one")]);
+    let conflicts = root_b.conflicts().unwrap();
+    assert_eq!(conflicts.len(), 1);
+    let copy = conflicts[0].copy().strip_prefix("/").unwrap().to_path_buf();
+    let mut versions = vec![read(&dir_b, "one").unwrap(), read(&dir_b, copy.to_str().unwrap()).unwrap()];
+    versions.sort();
+    assert_eq!(versions, vec!["changed by a", "changed by b"]);
+
+    drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert_eq!(read(&dir_a, "one"), read(&dir_b, "one"));
+    assert_eq!(read(&dir_a, copy.to_str().unwrap()), read(&dir_b, copy.to_str().unwrap()));
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+
+    // resolving the conflict by keeping the copy replaces the file everywhere
+    let loser = read(&dir_b, copy.to_str().unwrap()).unwrap();
+    drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    root_b.resolve_conflict(conflicts[0].copy(), Resolution::KeepCopy).unwrap();
+    assert!(root_b.conflicts().unwrap().is_empty());
+    assert!(!dir_b.join(&copy).exists());
+
+    drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert_eq!(read(&dir_a, "one"), Some(loser));
+    assert!(!dir_a.join(&copy).exists());
+    drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+}
+
+#[tokio::test]
+async fn deletions_reach_peers_which_never_synced_with_the_deleter() {
+    let dirs: Vec<_> = (0..3).map(|i| TempDir::new(format!("sync deletions root {}", i), true)).collect();
+    let globals: Vec<_> = (0..3).map(|i| TempDir::new(format!("sync deletions global {}", i), true)).collect();
+    let dfs_a = Dfs::new(Config::test_config(&globals[0])).unwrap();
+    let dfs_b = Dfs::new(Config::test_config(&globals[1])).unwrap();
+    let dfs_c = Dfs::new(Config::test_config(&globals[2])).unwrap();
+    fs::write(dirs[0].join("file"), "contents").unwrap();
+
+    // a shares the root with b and c, but b and c don't know each other
+    let root = dfs_a.new_root(&dirs[0], "a").unwrap();
+    let expiry = Duration::from_secs(60);
+    let invite_b = dfs_b.accept_invite(&dfs_a.create_invite(&root, ShareMode::ReadWrite, expiry).unwrap()).unwrap();
+    dfs_a.redeem_invite(invite_b.id(), dfs_b.identity()).unwrap();
+    let invite_c = dfs_c.accept_invite(&dfs_a.create_invite(&root, ShareMode::ReadWrite, expiry).unwrap()).unwrap();
+    dfs_a.redeem_invite(invite_c.id(), dfs_c.identity()).unwrap();
+
+    let root_a = root.connect().unwrap();
+    root_a.index().await.unwrap();
+    let root_b = dfs_b.join_root(&invite_b, &dirs[1], "b").unwrap().connect().unwrap();
+    let root_c = dfs_c.join_root(&invite_c, &dirs[2], "c").unwrap().connect().unwrap();
+
+    let mut node_a = Node::new(&dfs_a).await.unwrap();
+    let mut node_b = Node::new(&dfs_b).await.unwrap();
+    let mut node_c = Node::new(&dfs_c).await.unwrap();
+    node_a.serve(&root_a);
+    node_b.serve(&root_b);
+    node_c.serve(&root_c);
+    connect(&mut node_b, &mut node_a).await;
+    connect(&mut node_c, &mut node_a).await;
+
+    drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    drive(sync(&mut node_c, &root_c, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(read(&dirs[2], "file").as_deref(), Some("contents"));
+
+    // b deletes the file, a learns about it from b and c from a
+    fs::remove_file(dirs[1].join("file")).unwrap();
+    root_b.index().await.unwrap();
+    let report = drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert_eq!(report.deleted, vec![Path::new("/file")]);
+
+    let report = drive(sync(&mut node_c, &root_c, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.deleted, vec![Path::new("/file")]);
+    assert!(!dirs[2].join("file").exists());
+
+    // and the file doesn't come back from c
+    let report = drive(sync(&mut node_a, &root_a, dfs_c.peer_id()), &mut node_c).await.unwrap();
+    assert!(report.is_empty() && report.uploads.is_empty());
+    assert!(root_a.get_by_path("/file").unwrap().is_none());
+    assert!(!dirs[0].join("file").exists());
+}
+
+#[tokio::test]
+async fn announced_changes_trigger_a_sync() {
+    let dir_a = TempDir::new("sync announce root a", true);
+    let dir_b = TempDir::new("sync announce root b", true);
+    let global_a = TempDir::new("sync announce global a", true);
+    let global_b = TempDir::new("sync announce global b", true);
+    let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+    let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+    fs::write(dir_a.join("file"), "contents").unwrap();
+
+    let root = dfs_a.new_root(&dir_a, "a").unwrap();
+    let invite = dfs_b.accept_invite(&dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap()).unwrap();
+    dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+    let root_id = root.id();
+
+    let root_a = root.connect().unwrap();
+    root_a.index().await.unwrap();
+    let root_b = dfs_b.join_root(&invite, &dir_b, "b").unwrap().connect().unwrap();
+
+    let mut node_a = Node::new(&dfs_a).await.unwrap();
+    let mut node_b = Node::new(&dfs_b).await.unwrap();
+    node_a.serve(&root_a);
+    node_b.serve(&root_b);
+    connect(&mut node_b, &mut node_a).await;
+
+    let changed = |e: NodeEvent| matches!(e, NodeEvent::RootChanged(p, r) if p == dfs_a.peer_id() && r == root_id);
+
+    // a tells b where it is as soon as b subscribes, and b is behind
+    while !changed(drive(node_b.next_event(), &mut node_a).await) {}
+    drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(read(&dir_b, "file").as_deref(), Some("contents"));
+
+    // a change at a is announced, and b picks it up
+    fs::write(dir_a.join("file"), "changed").unwrap();
+    root_a.index().await.unwrap();
+    assert!(node_a.announce(root_id).unwrap());
+    assert!(!node_a.announce(root_id).unwrap(), "nothing changed since the last announcement");
+
+    while !changed(drive(node_b.next_event(), &mut node_a).await) {}
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.downloaded, vec![Path::new("/file")]);
+    assert_eq!(read(&dir_b, "file").as_deref(), Some("changed"));
+}
+
+#[tokio::test]
+async fn one_sync_each_converges() {
+    // under the temp dir of the system, so nothing is left in the crate when the test is killed
+    let base = TempDir::default();
+    let dir_a = TempDir::new(base.join("root a"), true);
+    let dir_b = TempDir::new(base.join("root b"), true);
+    let global_a = TempDir::new(base.join("global a"), true);
+    let global_b = TempDir::new(base.join("global b"), true);
+    let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+    let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+    fs::write(dir_a.join("shared"), "shared").unwrap();
+
+    let root = dfs_a.new_root(&dir_a, "a").unwrap();
+    let invite = dfs_b.accept_invite(&dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap()).unwrap();
+    dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+    let root_id = root.id();
+
+    let root_a = root.connect().unwrap();
+    root_a.index().await.unwrap();
+    let root_b = dfs_b.join_root(&invite, &dir_b, "b").unwrap().connect().unwrap();
+
+    let mut node_a = Node::new(&dfs_a).await.unwrap();
+    let mut node_b = Node::new(&dfs_b).await.unwrap();
+    node_a.serve(&root_a);
+    node_b.serve(&root_b);
+    connect(&mut node_b, &mut node_a).await;
+
+    let changed = |e: NodeEvent| matches!(e, NodeEvent::RootChanged(p, r) if p == dfs_a.peer_id() && r == root_id);
+    while !changed(drive(node_b.next_event(), &mut node_a).await) {}
+    drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+
+    // both sides change something
+    fs::write(dir_a.join("from a"), "from a").unwrap();
+    fs::write(dir_b.join("from b"), "from b").unwrap();
+    fs::write(dir_b.join("shared"), "changed by b").unwrap();
+    root_b.index().await.unwrap();
+
+    // a takes over the changes of b, and tells b about its own
+    let mut told = false;
+    let report = {
+        let sync_a = sync(&mut node_a, &root_a, dfs_b.peer_id());
+        tokio::pin!(sync_a);
+        loop {
+            tokio::select! {
+                r = &mut sync_a => break r.unwrap(),
+                e = node_b.next_event() => told |= changed(e),
+            }
+        }
+    };
+    let mut downloaded = report.downloaded.clone();
+    downloaded.sort();
+    assert_eq!(downloaded, vec![Path::new("/from b"), Path::new("/shared")]);
+    assert_eq!(report.uploads, vec![Path::new("/from a")]);
+    while !told {
+        told = changed(drive(node_b.next_event(), &mut node_a).await);
+    }
+
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.downloaded, vec![Path::new("/from a")]);
+    assert!(report.uploads.is_empty());
+    for dir in [&dir_a, &dir_b] {
+        assert_eq!(read(dir, "from a").as_deref(), Some("from a"));
+        assert_eq!(read(dir, "from b").as_deref(), Some("from b"));
+        assert_eq!(read(dir, "shared").as_deref(), Some("changed by b"));
+    }
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+
+    // b is told again when a has changes for it, even if a announced them before
+    fs::remove_file(dir_a.join("from b")).unwrap();
+    root_a.index().await.unwrap();
+    assert!(node_a.announce(root_id).unwrap());
+    while !changed(drive(node_b.next_event(), &mut node_a).await) {}
+
+    let mut told = false;
+    let report = {
+        let sync_a = sync(&mut node_a, &root_a, dfs_b.peer_id());
+        tokio::pin!(sync_a);
+        loop {
+            tokio::select! {
+                r = &mut sync_a => break r.unwrap(),
+                e = node_b.next_event() => told |= changed(e),
+            }
+        }
+    };
+    assert!(report.is_empty());
+    assert_eq!(report.remote_deletions, vec![Path::new("/from b")]);
+    while !told {
+        told = changed(drive(node_b.next_event(), &mut node_a).await);
+    }
+
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.deleted, vec![Path::new("/from b")]);
+    assert!(report.uploads.is_empty() && report.remote_deletions.is_empty());
+    assert!(!dir_b.join("from b").exists());
+}
+
+#[tokio::test]
+async fn selective_sync() {
+    let dir_a = TempDir::new("sync selective root a", true);
+    let dir_b = TempDir::new("sync selective root b", true);
+    let global_a = TempDir::new("sync selective global a", true);
+    let global_b = TempDir::new("sync selective global b", true);
+    let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+    let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+    fs::create_dir_all(dir_a.join("docs")).unwrap();
+    fs::create_dir_all(dir_a.join("photos/2021")).unwrap();
+    fs::write(dir_a.join("docs/report.txt"), "report").unwrap();
+    fs::write(dir_a.join("photos/beach.jpg"), "beach").unwrap();
+    fs::write(dir_a.join("photos/2021/summer.jpg"), "summer").unwrap();
+
+    let root = dfs_a.new_root(&dir_a, "a").unwrap();
+    let invite = dfs_b.accept_invite(&dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap()).unwrap();
+    dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+
+    let root_a = root.connect().unwrap();
+    root_a.index().await.unwrap();
+    let root_b = dfs_b.join_root(&invite, &dir_b, "b").unwrap();
+    dfs_b.set_selection(&root_b, &Selection::default().include("/docs")).unwrap();
+    let root_b = root_b.connect().unwrap();
+
+    let mut node_a = Node::new(&dfs_a).await.unwrap();
+    let mut node_b = Node::new(&dfs_b).await.unwrap();
+    node_a.serve(&root_a);
+    node_b.serve(&root_b);
+    connect(&mut node_b, &mut node_a).await;
+
+    // b only gets the docs, but knows about the photos
+    let mut report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    report.excluded.sort();
+    assert_eq!(report.excluded, vec![Path::new("/photos"), Path::new("/photos/2021"), Path::new("/photos/2021/summer.jpg"), Path::new("/photos/beach.jpg")]);
+    assert_eq!(read(&dir_b, "docs/report.txt").as_deref(), Some("report"));
+    assert!(!dir_b.join("photos").exists());
+    let beach = |root: &ConnectedRoot<_, _>| root.get_by_path("/photos/beach.jpg").unwrap().unwrap().deref().clone();
+    assert!(beach(&root_b).is_excluded());
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+
+    // changes to excluded files only update the metadata, and indexing doesn't take them for deletions
+    fs::write(dir_a.join("photos/beach.jpg"), "sunset").unwrap();
+    root_a.index().await.unwrap();
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.excluded, vec![Path::new("/photos/beach.jpg")]);
+    assert!(!dir_b.join("photos").exists());
+    assert_eq!(beach(&root_b).hash(), beach(&root_a).hash());
+    root_b.index().await.unwrap();
+    assert!(beach(&root_b).is_excluded());
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+
+    // selecting the photos downloads them
+    dfs_b.set_selection(&root_b, &Selection::default().include("/docs").include("/photos/*.jpg")).unwrap();
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.downloaded, vec![Path::new("/photos/beach.jpg")]);
+    assert_eq!(read(&dir_b, "photos/beach.jpg").as_deref(), Some("sunset"));
+    assert!(!dir_b.join("photos/2021").exists());
+
+    // and leaving out the docs removes them from disk, but not from a
+    dfs_b.set_selection(&root_b, &Selection::default().exclude("/docs")).unwrap();
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.excluded, vec![Path::new("/docs")]);
+    assert!(!dir_b.join("docs").exists());
+    assert_eq!(read(&dir_b, "photos/2021/summer.jpg").as_deref(), Some("summer"));
+
+    let report = drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert!(report.is_empty() && report.remote_deletions.is_empty());
+    assert_eq!(read(&dir_a, "docs/report.txt").as_deref(), Some("report"));
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+}
+
+#[tokio::test]
+async fn placeholders() {
+    let dir_a = TempDir::new("sync placeholder root a", true);
+    let dir_b = TempDir::new("sync placeholder root b", true);
+    let global_a = TempDir::new("sync placeholder global a", true);
+    let global_b = TempDir::new("sync placeholder global b", true);
+    let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+    let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+
+    fs::write(dir_a.join("video"), "a long video").unwrap();
+
+    let root = dfs_a.new_root(&dir_a, "a").unwrap();
+    let invite = dfs_b.accept_invite(&dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap()).unwrap();
+    dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+
+    let root_a = root.connect().unwrap();
+    root_a.index().await.unwrap();
+    let root_b = dfs_b.join_root(&invite, &dir_b, "b").unwrap().connect().unwrap();
+
+    let mut node_a = Node::new(&dfs_a).await.unwrap();
+    let mut node_b = Node::new(&dfs_b).await.unwrap();
+    node_a.serve(&root_a);
+    node_b.serve(&root_b);
+    connect(&mut node_b, &mut node_a).await;
+    drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+
+    // the file stays listed without its contents, and indexing doesn't take the empty file for a change
+    drive(root_b.dehydrate(&mut node_b, "/video"), &mut node_a).await.unwrap();
+    assert_eq!(read(&dir_b, "video").as_deref(), Some(""));
+    let video = |root: &ConnectedRoot<_, _>| root.get_by_path("/video").unwrap().unwrap().deref().clone();
+    assert!(video(&root_b).is_placeholder());
+    root_b.index().await.unwrap();
+    assert!(video(&root_b).is_placeholder());
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+
+    // a can't remove its contents too, b doesn't have them anymore
+    assert!(drive(root_a.dehydrate(&mut node_a, "/video"), &mut node_b).await.is_err());
+
+    // changes only update the metadata of the placeholder
+    fs::write(dir_a.join("video"), "a longer video").unwrap();
+    root_a.index().await.unwrap();
+    let report = drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    assert_eq!(report.placeholders, vec![Path::new("/video")]);
+    assert_eq!(read(&dir_b, "video").as_deref(), Some(""));
+    assert_eq!(video(&root_b).hash(), video(&root_a).hash());
+
+    drive(root_b.hydrate(&mut node_b, "/video"), &mut node_a).await.unwrap();
+    assert_eq!(read(&dir_b, "video").as_deref(), Some("a longer video"));
+    assert!(video(&root_b).is_local());
+    root_b.index().await.unwrap();
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+}
+
+#[tokio::test]
+async fn file_versions() {
+    let dir_a = TempDir::new("sync versions root a", true);
+    let dir_b = TempDir::new("sync versions root b", true);
+    let global_a = TempDir::new("sync versions global a", true);
+    let global_b = TempDir::new("sync versions global b", true);
+    let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+    // only the number of versions limits them
+    let dfs_b = Dfs::new(Config {
+        versions_kept: 2,
+        version_retention: Duration::MAX,
+        ..Config::test_config(&global_b)
+    }).unwrap();
+
+    fs::write(dir_a.join("notes"), "one").unwrap();
+
+    let root = dfs_a.new_root(&dir_a, "a").unwrap();
+    let invite = dfs_b.accept_invite(&dfs_a.create_invite(&root, ShareMode::ReadWrite, Duration::from_secs(60)).unwrap()).unwrap();
+    dfs_a.redeem_invite(invite.id(), dfs_b.identity()).unwrap();
+
+    let root_a = root.connect().unwrap();
+    root_a.index().await.unwrap();
+    let root_b = dfs_b.join_root(&invite, &dir_b, "b").unwrap().connect().unwrap();
+
+    let mut node_a = Node::new(&dfs_a).await.unwrap();
+    let mut node_b = Node::new(&dfs_b).await.unwrap();
+    node_a.serve(&root_a);
+    node_b.serve(&root_b);
+    connect(&mut node_b, &mut node_a).await;
+    drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+
+    fs::rename(dir_a.join("notes"), dir_a.join("renamed")).unwrap();
+    root_a.index().await.unwrap();
+    let changes = drive(node_b.changes(dfs_a.peer_id(), root_b.id()), &mut node_a).await.unwrap();
+    assert!(matches!(&changes[..], [Change::Moved { old, new }] if old.path() == Path::new("/notes") && new.path() == Path::new("/renamed")));
+    fs::rename(dir_a.join("renamed"), dir_a.join("notes")).unwrap();
+
+    // every sync which replaces the file keeps the previous contents, up to two of them
+    for contents in ["two", "three", "four"] {
+        fs::write(dir_a.join("notes"), contents).unwrap();
+        root_a.index().await.unwrap();
+        drive(sync(&mut node_b, &root_b, dfs_a.peer_id()), &mut node_a).await.unwrap();
+    }
+    assert_eq!(read(&dir_b, "notes").as_deref(), Some("four"));
+
+    let notes = root_b.get_by_path("/notes").unwrap().unwrap();
+    let versions = notes.versions().unwrap();
+    assert_eq!(versions.iter().map(|v| v.size()).collect::<Vec<_>>(), vec![3, 5]);
+
+    // restoring is a change, which a takes over
+    notes.restore(&versions[0]).unwrap();
+    assert_eq!(read(&dir_b, "notes").as_deref(), Some("two"));
+    let notes = root_b.get_by_path("/notes").unwrap().unwrap();
+    assert_eq!(notes.versions().unwrap().last().unwrap().size(), 4);
+
+    drive(sync(&mut node_a, &root_a, dfs_b.peer_id()), &mut node_b).await.unwrap();
+    assert_eq!(read(&dir_a, "notes").as_deref(), Some("two"));
+    root_b.index().await.unwrap();
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+}
+
+#[tokio::test]
+async fn encrypted_roots_are_relayed() {
+    let dir_a = TempDir::new("sync relay a", true);
+    let dir_b = TempDir::new("sync relay b", true);
+    let dir_r = TempDir::new("sync relay r", true);
+    let global_a = TempDir::new("sync relay global a", true);
+    let global_b = TempDir::new("sync relay global b", true);
+    let global_r = TempDir::new("sync relay global r", true);
+    let dfs_a = Dfs::new(Config::test_config(&global_a)).unwrap();
+    let dfs_b = Dfs::new(Config::test_config(&global_b)).unwrap();
+    let dfs_r = Dfs::new(Config::test_config(&global_r)).unwrap();
+
+    fs::create_dir(dir_a.join("taxes")).unwrap();
+    fs::write(dir_a.join("taxes/2021"), "owed nothing").unwrap();
+
+    // a and b may read the root, r only stores and forwards it
+    let root = dfs_a.new_root(&dir_a, "a").unwrap();
+    dfs_a.encrypt_root(&root).unwrap();
+    let share = |from: &Dfs<_>, to: &Dfs<_>, root, mode| {
+        let invite = from.create_invite(root, mode, Duration::from_secs(60)).unwrap();
+        let invite = to.accept_invite(&invite).unwrap();
+        let peer = from.redeem_invite(invite.id(), to.identity()).unwrap();
+        (invite, peer)
+    };
+    let (_, relay) = share(&dfs_a, &dfs_r, &root, ShareMode::Encrypted);
+    let (invite, peer_b) = share(&dfs_a, &dfs_b, &root, ShareMode::ReadWrite);
+    assert!(dfs_a.grant_root_key(&root, &relay).is_err());
+    dfs_b.accept_root_key(&dfs_a.grant_root_key(&root, &peer_b).unwrap()).unwrap();
+
+    let joined = dfs_b.join_root(&invite, &dir_b, "b").unwrap();
+    share(&dfs_b, &dfs_r, &joined, ShareMode::Encrypted);
+
+    let sealed = SealedRoot::open(&dir_r, root.id()).unwrap();
+    let root_a = root.connect().unwrap();
+    root_a.index().await.unwrap();
+    let root_b = joined.connect().unwrap();
+
+    let mut node_r = Node::new(&dfs_r).await.unwrap();
+    node_r.serve(&sealed);
+    {
+        let mut node_a = Node::new(&dfs_a).await.unwrap();
+        node_a.serve(&root_a);
+        connect(&mut node_r, &mut node_a).await;
+
+        let report = drive(node_r.relay(dfs_a.peer_id(), &sealed), &mut node_a).await.unwrap();
+        assert_eq!(report.entries, 3);
+        assert_eq!(report.fetched, 1);
+    }
+
+    // r can't read any of it, and can't tell the blocks by the hashes of their contents
+    let entries = sealed.load_entries().unwrap();
+    for entry in &entries {
+        assert!(!entry.sealed().windows(5).any(|w| w == b"taxes"));
+    }
+    let file = root_a.get_by_path("/taxes/2021").unwrap().unwrap();
+    let addresses: Vec<_> = entries.iter().flat_map(|e| e.blocks()).collect();
+    assert_eq!(addresses.len(), 1);
+    assert!(!addresses.contains(&&file.blocks()[0]));
+    for block in fs::read_dir(dir_r.join(SEALED_BLOCKS_DIR)).unwrap() {
+        let block = fs::read(block.unwrap().path()).unwrap();
+        assert!(!block.windows(4).any(|w| w == b"owed"));
+    }
+
+    // a is gone, but b gets the root from r
+    let mut node_b = Node::new(&dfs_b).await.unwrap();
+    node_b.serve(&root_b);
+    connect(&mut node_b, &mut node_r).await;
+
+    let report = drive(sync(&mut node_b, &root_b, dfs_r.peer_id()), &mut node_r).await.unwrap();
+    assert_eq!(report.downloaded.len(), 2);
+    assert_eq!(read(&dir_b, "taxes/2021").as_deref(), Some("owed nothing"));
+    assert_eq!(root_a.root_hash().unwrap(), root_b.root_hash().unwrap());
+}